@@ -0,0 +1,181 @@
+//! Command line arguments of the browser.
+//!
+//! ```text
+//! toy-browser                      open the browser window
+//! toy-browser render <url> -o out.png [--width 800] [--height 600] [--user-stylesheet user.css]
+//!                                     [--media print] [--color-scheme dark]
+//!                                     [--parallel-style] [--style-stats]
+//! ```
+
+use super::errors::Error;
+use super::parser::media::{ColorScheme, MediaType};
+use super::Result;
+
+pub const USAGE: &str = "Usage:
+    toy-browser
+    toy-browser render <url> -o <out.png> [--width <px>] [--height <px>]
+                       [--user-stylesheet <file.css>] [--media <screen|print>]
+                       [--color-scheme <light|dark>] [--parallel-style]
+                       [--style-stats]";
+
+pub enum Command {
+    /// Open the browser window.
+    Browse,
+    /// Render a page without a window and save it as an image.
+    Render(RenderOptions),
+    /// Print the usage text.
+    Help,
+}
+
+pub struct RenderOptions {
+    pub url: String,
+    pub output: String,
+    pub width: usize,
+    pub height: usize,
+    /// Stylesheet of the reader, applied in the user origin
+    pub user_stylesheet: Option<String>,
+    /// Media type and color scheme `@media` rules are evaluated against
+    pub media_type: MediaType,
+    pub color_scheme: ColorScheme,
+    /// Compute styles on several threads
+    pub parallel_style: bool,
+    /// Print how many elements shared a style
+    pub style_stats: bool,
+}
+
+/// Parse the arguments following the program name.
+pub fn parse_args(args: &[String]) -> Result<Command> {
+    match args.first().map(String::as_str) {
+        None => Ok(Command::Browse),
+        Some("-h") | Some("--help") => Ok(Command::Help),
+        Some("render") => parse_render_args(&args[1..]).map(Command::Render),
+        Some(other) => Err(usage_error(format!("unknown command `{}`", other))),
+    }
+}
+
+fn parse_render_args(args: &[String]) -> Result<RenderOptions> {
+    let mut url = None;
+    let mut output = None;
+    let mut width = 800;
+    let mut height = 600;
+    let mut user_stylesheet = None;
+    let mut media_type = MediaType::Screen;
+    let mut color_scheme = ColorScheme::Light;
+    let mut parallel_style = false;
+    let mut style_stats = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" => output = Some(flag_value(arg, args.next())?.clone()),
+            "--width" => width = parse_size(arg, flag_value(arg, args.next())?)?,
+            "--height" => height = parse_size(arg, flag_value(arg, args.next())?)?,
+            "--user-stylesheet" => user_stylesheet = Some(to_url(flag_value(arg, args.next())?)),
+            "--media" => {
+                media_type = match flag_value(arg, args.next())?.as_str() {
+                    "screen" => MediaType::Screen,
+                    "print" => MediaType::Print,
+                    value => return Err(choice_error(arg, "screen or print", value)),
+                }
+            }
+            "--color-scheme" => {
+                color_scheme = match flag_value(arg, args.next())?.as_str() {
+                    "light" => ColorScheme::Light,
+                    "dark" => ColorScheme::Dark,
+                    value => return Err(choice_error(arg, "light or dark", value)),
+                }
+            }
+            "--parallel-style" => parallel_style = true,
+            "--style-stats" => style_stats = true,
+            flag if flag.starts_with('-') => {
+                return Err(usage_error(format!("unknown option `{}`", flag)))
+            }
+            _ if url.is_none() => url = Some(to_url(arg)),
+            _ => return Err(usage_error(format!("unexpected argument `{}`", arg))),
+        }
+    }
+
+    Ok(RenderOptions {
+        url: url.ok_or_else(|| usage_error("missing <url>".to_string()))?,
+        output: output.ok_or_else(|| usage_error("missing -o <out.png>".to_string()))?,
+        width,
+        height,
+        user_stylesheet,
+        media_type,
+        color_scheme,
+        parallel_style,
+        style_stats,
+    })
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a String> {
+    value.ok_or_else(|| usage_error(format!("`{}` needs a value", flag)))
+}
+
+fn parse_size(flag: &str, value: &str) -> Result<usize> {
+    match value.parse::<usize>() {
+        Ok(size) if size > 0 => Ok(size),
+        _ => Err(usage_error(format!(
+            "`{}` expects a positive number of pixels, got `{}`",
+            flag, value
+        ))),
+    }
+}
+
+fn choice_error(flag: &str, choices: &str, value: &str) -> Error {
+    usage_error(format!("`{}` expects {}, got `{}`", flag, choices, value))
+}
+
+/// A bare path is treated as a local file.
+fn to_url(arg: &str) -> String {
+    if arg.contains("://") {
+        return arg.to_string();
+    }
+    match std::fs::canonicalize(arg) {
+        Ok(path) => format!("file://{}", path.display()),
+        Err(_) => format!("file://{}", arg),
+    }
+}
+
+fn usage_error(msg: String) -> Error {
+    Error::Usage(msg)
+}
+
+#[cfg(test)]
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(String::from).collect()
+}
+
+#[test]
+fn test_parse_render_args() {
+    match parse_args(&args("render http://example.com -o out.png --width 320")) {
+        Ok(Command::Render(options)) => {
+            assert_eq!(options.url, "http://example.com");
+            assert_eq!(options.output, "out.png");
+            assert_eq!((options.width, options.height), (320, 600));
+            assert_eq!(options.user_stylesheet, None);
+            assert_eq!(options.media_type, MediaType::Screen);
+            assert!(!options.parallel_style);
+        }
+        _ => panic!("expected a render command"),
+    }
+    match parse_args(&args(
+        "render a -o out.png --media print --color-scheme dark",
+    )) {
+        Ok(Command::Render(options)) => {
+            assert_eq!(options.media_type, MediaType::Print);
+            assert_eq!(options.color_scheme, ColorScheme::Dark);
+        }
+        _ => panic!("expected a render command"),
+    }
+    match parse_args(&args("render /nonexistent/page.html -o out.png")) {
+        Ok(Command::Render(options)) => assert_eq!(options.url, "file:///nonexistent/page.html"),
+        _ => panic!("expected a render command"),
+    }
+
+    assert!(parse_args(&args("render http://example.com")).is_err());
+    assert!(parse_args(&args("render -o out.png")).is_err());
+    assert!(parse_args(&args("render a -o out.png --height 0")).is_err());
+    assert!(parse_args(&args("render a -o out.png --media tv")).is_err());
+    assert!(parse_args(&args("paint a")).is_err());
+}
@@ -0,0 +1,621 @@
+/// This module turns the specified values of a node into computed values
+///
+/// Inherited properties take the value of the parent when nothing is
+/// specified, the `inherit`, `initial` and `unset` keywords are resolved
+/// and lengths are converted to px, whatever their unit. Percentages of the containing block
+/// are kept as they are, only layout knows the size they refer to. So are
+/// `calc()` expressions using them, simplified around them.
+use super::font;
+use super::parser::calc::Calc;
+use super::parser::css::{Color, Unit, Value};
+use super::style::Display;
+use super::variables::CustomProperties;
+use std::collections::hash_map::HashMap;
+use std::sync::Arc;
+
+/// A length that may still depend on the containing block
+#[derive(Debug, Clone, PartialEq)]
+pub enum Length {
+    Auto,
+    Px(f32),
+    Percent(f32),
+    /// A `calc()` mixing percentages with lengths, which are in px already
+    Calc(Box<Calc>),
+}
+
+impl Length {
+    /// Size in px, percentages are of `reference`. `None` stands for `auto`.
+    pub fn resolve(&self, reference: f32) -> Option<f32> {
+        match self {
+            Length::Auto => None,
+            Length::Px(px) => Some(*px),
+            Length::Percent(percent) => Some(reference * percent / 100.0),
+            Length::Calc(calc) => {
+                let to_px = |value, unit| match unit {
+                    Unit::Percent => Some(reference * value / 100.0),
+                    _ => Some(value),
+                };
+                match calc.simplify(&to_px)? {
+                    Calc::Length(px, _) => Some(px),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Like `resolve`, but `auto` is zero
+    pub fn to_px(&self, reference: f32) -> f32 {
+        self.resolve(reference).unwrap_or(0.0)
+    }
+
+    /// Like `resolve`, for sizes that can't be negative: a `calc()` that
+    /// turns out negative is clamped at zero.
+    pub fn resolve_non_negative(&self, reference: f32) -> Option<f32> {
+        self.resolve(reference).map(|px| px.max(0.0))
+    }
+
+    // A `calc()` may only turn out negative in layout
+    fn is_negative(&self) -> bool {
+        match self {
+            Length::Auto | Length::Calc(_) => false,
+            Length::Px(value) | Length::Percent(value) => *value < 0.0,
+        }
+    }
+}
+
+/// What lengths in units of the page rather than the element refer to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Environment {
+    /// Computed font size of the root element, in px, for `rem`
+    pub root_font_size: f32,
+    /// Size of the viewport in px, for `vw`, `vh`, `vmin` and `vmax`
+    pub viewport_width: f32,
+    pub viewport_height: f32,
+}
+
+impl Environment {
+    /// The environment of the root element, whose `rem` is the initial
+    /// font size
+    pub fn new(viewport_width: f32, viewport_height: f32) -> Environment {
+        Environment {
+            root_font_size: font::DEFAULT_FONT_SIZE,
+            viewport_width,
+            viewport_height,
+        }
+    }
+}
+
+/// A value for each side of a box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sides<T> {
+    pub top: T,
+    pub right: T,
+    pub bottom: T,
+    pub left: T,
+}
+
+impl<T: Clone> Sides<T> {
+    fn all(value: T) -> Sides<T> {
+        Sides {
+            top: value.clone(),
+            right: value.clone(),
+            bottom: value.clone(),
+            left: value,
+        }
+    }
+
+    fn get(&self, side: Side) -> T {
+        match side {
+            Side::Top => self.top.clone(),
+            Side::Right => self.right.clone(),
+            Side::Bottom => self.bottom.clone(),
+            Side::Left => self.left.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Side {
+    Top,
+    Right,
+    Bottom,
+    Left,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LineHeight {
+    Normal,
+    /// A multiple of the font size, inherited as the number
+    Number(f32),
+    Px(f32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FontStyle {
+    Normal,
+    Italic,
+    Oblique,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Visibility {
+    Visible,
+    Hidden,
+    Collapse,
+}
+
+/// The computed values of the properties the browser supports
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComputedStyle {
+    pub display: Display,
+    pub visibility: Visibility,
+    pub color: Color,
+    pub background_color: Color,
+    /// in px
+    pub font_size: f32,
+    /// 100 to 900, 400 is normal and 700 bold
+    pub font_weight: u16,
+    pub font_style: FontStyle,
+    pub line_height: LineHeight,
+    pub width: Length,
+    pub height: Length,
+    pub margin: Sides<Length>,
+    pub padding: Sides<Length>,
+    /// in px
+    pub border_width: Sides<f32>,
+    pub border_color: Sides<Color>,
+    /// Inherited as they are, their `var()` substituted already
+    pub custom_properties: Arc<CustomProperties>,
+}
+
+impl ComputedStyle {
+    /// The initial value of every property, the root element inherits from it.
+    pub fn initial() -> ComputedStyle {
+        let black = Color::from(0, 0, 0, 255);
+        ComputedStyle {
+            display: Display::Inline,
+            visibility: Visibility::Visible,
+            color: black.clone(),
+            background_color: Color::from(0, 0, 0, 0),
+            font_size: font::DEFAULT_FONT_SIZE,
+            font_weight: 400,
+            font_style: FontStyle::Normal,
+            line_height: LineHeight::Normal,
+            width: Length::Auto,
+            height: Length::Auto,
+            margin: Sides::all(Length::Px(0.0)),
+            padding: Sides::all(Length::Px(0.0)),
+            border_width: Sides::all(0.0),
+            border_color: Sides::all(black),
+            custom_properties: Arc::default(),
+        }
+    }
+
+    /// Height of a line of text in px
+    pub fn line_height_px(&self) -> f32 {
+        match self.line_height {
+            LineHeight::Normal => font::line_height(self.font_size),
+            LineHeight::Number(number) => number * self.font_size,
+            LineHeight::Px(px) => px,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visibility == Visibility::Visible
+    }
+}
+
+/// Compute the style of a node from its specified values and the computed style of its parent.
+pub fn compute(
+    specified: &HashMap<String, Value>,
+    parent: &ComputedStyle,
+    env: &Environment,
+) -> ComputedStyle {
+    let mut cx = Context {
+        specified,
+        font_size: parent.font_size,
+        env,
+    };
+    let initial = ComputedStyle::initial();
+
+    // font-size comes first, em lengths of the other properties refer to it
+    let font_size = cx.compute(
+        "font-size",
+        true,
+        parent.font_size,
+        initial.font_size,
+        |value| font_size(value, parent.font_size, env),
+    );
+    cx.font_size = font_size;
+
+    let color = cx.compute(
+        "color",
+        true,
+        parent.color.clone(),
+        initial.color.clone(),
+        |value| match value {
+            Value::Color(color) => Some(color.clone()),
+            // the color of the parent, as `color` itself refers to it
+            Value::Keyword(k) if k == "currentcolor" => Some(parent.color.clone()),
+            _ => None,
+        },
+    );
+    let to_color = |value: &Value| match value {
+        Value::Color(color) => Some(color.clone()),
+        Value::Keyword(k) if k == "currentcolor" => Some(color.clone()),
+        _ => None,
+    };
+
+    let length = |value: &Value| cx.length(value);
+    // a negative `calc()` is clamped rather than invalid
+    let size = |value: &Value| match value {
+        Value::Calc(_) => cx.length(value).map(|l| match l {
+            Length::Px(px) => Length::Px(px.max(0.0)),
+            Length::Percent(percent) => Length::Percent(percent.max(0.0)),
+            l => l,
+        }),
+        _ => cx.length(value).filter(|l| !l.is_negative()),
+    };
+    let padding = |value: &Value| size(value).filter(|l| *l != Length::Auto);
+    let border_width = |value: &Value| cx.border_width(value);
+    let none = Sides::all("none");
+
+    let side = |side| {
+        let margin = cx.compute_side("margin", side, "", &parent.margin, &initial.margin, length);
+        let padding = cx.compute_side(
+            "padding",
+            side,
+            "",
+            &parent.padding,
+            &initial.padding,
+            padding,
+        );
+        // no border is drawn without a style
+        let border_style = cx.compute_side("border", side, "-style", &none, &none, keyword);
+        let border_width = match border_style {
+            "none" | "hidden" => 0.0,
+            _ => cx.compute_side(
+                "border",
+                side,
+                "-width",
+                &parent.border_width,
+                &initial.border_width,
+                border_width,
+            ),
+        };
+        // the initial border color is the text color
+        let border_color = cx.compute_side(
+            "border",
+            side,
+            "-color",
+            &parent.border_color,
+            &Sides::all(color.clone()),
+            to_color,
+        );
+        (margin, padding, border_width, border_color)
+    };
+    let (margin_top, padding_top, border_top, border_color_top) = side(Side::Top);
+    let (margin_right, padding_right, border_right, border_color_right) = side(Side::Right);
+    let (margin_bottom, padding_bottom, border_bottom, border_color_bottom) = side(Side::Bottom);
+    let (margin_left, padding_left, border_left, border_color_left) = side(Side::Left);
+
+    ComputedStyle {
+        display: cx.compute("display", false, parent.display, initial.display, |value| {
+            keyword(value).map(display)
+        }),
+        visibility: cx.compute(
+            "visibility",
+            true,
+            parent.visibility,
+            initial.visibility,
+            |value| match keyword(value)? {
+                "visible" => Some(Visibility::Visible),
+                "hidden" => Some(Visibility::Hidden),
+                "collapse" => Some(Visibility::Collapse),
+                _ => None,
+            },
+        ),
+        background_color: cx.compute(
+            "background-color",
+            false,
+            parent.background_color.clone(),
+            initial.background_color.clone(),
+            to_color,
+        ),
+        font_weight: cx.compute(
+            "font-weight",
+            true,
+            parent.font_weight,
+            initial.font_weight,
+            |value| font_weight(value, parent.font_weight),
+        ),
+        font_style: cx.compute(
+            "font-style",
+            true,
+            parent.font_style,
+            initial.font_style,
+            |value| match keyword(value)? {
+                "normal" => Some(FontStyle::Normal),
+                "italic" => Some(FontStyle::Italic),
+                k if k.starts_with("oblique") => Some(FontStyle::Oblique),
+                _ => None,
+            },
+        ),
+        line_height: cx.compute(
+            "line-height",
+            true,
+            parent.line_height,
+            initial.line_height,
+            |value| line_height(value, font_size, env),
+        ),
+        width: cx.compute("width", false, parent.width.clone(), initial.width, size),
+        height: cx.compute("height", false, parent.height.clone(), initial.height, size),
+        margin: Sides {
+            top: margin_top,
+            right: margin_right,
+            bottom: margin_bottom,
+            left: margin_left,
+        },
+        padding: Sides {
+            top: padding_top,
+            right: padding_right,
+            bottom: padding_bottom,
+            left: padding_left,
+        },
+        border_width: Sides {
+            top: border_top,
+            right: border_right,
+            bottom: border_bottom,
+            left: border_left,
+        },
+        border_color: Sides {
+            top: border_color_top,
+            right: border_color_right,
+            bottom: border_color_bottom,
+            left: border_color_left,
+        },
+        custom_properties: custom_properties(specified, &parent.custom_properties),
+        color,
+        font_size,
+    }
+}
+
+// The custom properties of the parent with those set on the node
+fn custom_properties(
+    specified: &HashMap<String, Value>,
+    parent: &Arc<CustomProperties>,
+) -> Arc<CustomProperties> {
+    let mut properties: Option<CustomProperties> = None;
+    for (name, value) in specified {
+        if !name.starts_with("--") {
+            continue;
+        }
+        let properties = properties.get_or_insert_with(|| (**parent).clone());
+        match value {
+            Value::Custom(tokens) => {
+                properties.insert(name.clone(), tokens.clone());
+            }
+            // `inherit` and `unset` keep the value of the parent
+            Value::Keyword(k) if k == "initial" => {
+                properties.remove(name);
+            }
+            _ => {}
+        }
+    }
+    properties.map_or_else(|| Arc::clone(parent), Arc::new)
+}
+
+struct Context<'a> {
+    specified: &'a HashMap<String, Value>,
+    // computed font size of the node, em lengths are relative to it
+    font_size: f32,
+    env: &'a Environment,
+}
+
+impl<'a> Context<'a> {
+    /// Computed value of a property. The `inherit`, `initial` and `unset`
+    /// keywords pick the value of the parent or the initial value,
+    /// and an invalid value is ignored.
+    fn compute<'v, T>(
+        &'v self,
+        name: &str,
+        inherited: bool,
+        parent: T,
+        initial: T,
+        convert: impl Fn(&'v Value) -> Option<T>,
+    ) -> T {
+        let value = match self.specified.get(name) {
+            Some(value) => value,
+            None if inherited => return parent,
+            None => return initial,
+        };
+        match keyword(value) {
+            Some("inherit") => parent,
+            Some("initial") => initial,
+            _ => match convert(value) {
+                Some(computed) => computed,
+                // `unset` as well as invalid values
+                None if inherited => parent,
+                None => initial,
+            },
+        }
+    }
+
+    /// Computed value of one side of a box property like `border-top-width`.
+    fn compute_side<'v, T: Clone>(
+        &'v self,
+        prefix: &str,
+        side: Side,
+        suffix: &str,
+        parent: &Sides<T>,
+        initial: &Sides<T>,
+        convert: impl Fn(&'v Value) -> Option<T>,
+    ) -> T {
+        let side_name = match side {
+            Side::Top => "top",
+            Side::Right => "right",
+            Side::Bottom => "bottom",
+            Side::Left => "left",
+        };
+        let name = format!("{}-{}{}", prefix, side_name, suffix);
+        self.compute(&name, false, parent.get(side), initial.get(side), convert)
+    }
+
+    fn length(&self, value: &Value) -> Option<Length> {
+        match value {
+            Value::Length(v, Unit::Percent) => Some(Length::Percent(*v)),
+            Value::Length(v, unit) => {
+                absolute_length(*v, *unit, self.font_size, self.env).map(Length::Px)
+            }
+            Value::Keyword(k) if k == "auto" => Some(Length::Auto),
+            // a zero length may leave out the unit
+            Value::Number(v) if *v == 0.0 => Some(Length::Px(0.0)),
+            // percentages of the containing block are left for layout
+            Value::Calc(calc) => {
+                let to_px = |value, unit| absolute_length(value, unit, self.font_size, self.env);
+                match calc.simplify(&to_px)? {
+                    Calc::Length(px, Unit::Px) => Some(Length::Px(px)),
+                    Calc::Length(percent, Unit::Percent) => Some(Length::Percent(percent)),
+                    Calc::Number(_) => None,
+                    calc => Some(Length::Calc(Box::new(calc))),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    fn border_width(&self, value: &Value) -> Option<f32> {
+        match keyword(value) {
+            Some("thin") => Some(1.0),
+            Some("medium") => Some(3.0),
+            Some("thick") => Some(5.0),
+            _ => match self.length(value)? {
+                Length::Px(px) if px >= 0.0 => Some(px),
+                Length::Px(_) if matches!(value, Value::Calc(_)) => Some(0.0),
+                _ => None,
+            },
+        }
+    }
+}
+
+fn keyword(value: &Value) -> Option<&str> {
+    match value {
+        Value::Keyword(k) => Some(k.as_str()),
+        _ => None,
+    }
+}
+
+fn display(keyword: &str) -> Display {
+    match keyword {
+        "block" => Display::Block,
+        "none" => Display::None,
+        _ => Display::Inline,
+    }
+}
+
+/// Size in px of a length in any unit but a percentage. em, ex and ch are
+/// relative to `font_size`.
+pub fn absolute_length(v: f32, unit: Unit, font_size: f32, env: &Environment) -> Option<f32> {
+    let vmin = env.viewport_width.min(env.viewport_height);
+    let vmax = env.viewport_width.max(env.viewport_height);
+    Some(match unit {
+        Unit::Px => v,
+        Unit::Em => v * font_size,
+        Unit::Rem => v * env.root_font_size,
+        Unit::Ex => v * font_size / 2.0,
+        Unit::Ch => v * font::char_width(font_size),
+        Unit::Vw => v * env.viewport_width / 100.0,
+        Unit::Vh => v * env.viewport_height / 100.0,
+        Unit::Vmin => v * vmin / 100.0,
+        Unit::Vmax => v * vmax / 100.0,
+        // 96 px to the inch
+        Unit::Pt => v * 96.0 / 72.0,
+        Unit::Pc => v * 16.0,
+        Unit::In => v * 96.0,
+        Unit::Cm => v * 96.0 / 2.54,
+        Unit::Mm => v * 96.0 / 25.4,
+        Unit::Percent => return None,
+    })
+}
+
+// A `calc()` with its lengths in px, percentages are of `reference`
+fn calc_px(calc: &Calc, reference: f32, font_size: f32, env: &Environment) -> Option<Calc> {
+    calc.simplify(&|value, unit| match unit {
+        Unit::Percent => Some(value * reference / 100.0),
+        unit => absolute_length(value, unit, font_size, env),
+    })
+}
+
+// em and percentages are relative to the font size of the parent
+fn font_size(value: &Value, parent_font_size: f32, env: &Environment) -> Option<f32> {
+    let size = match value {
+        Value::Length(v, Unit::Percent) => v * parent_font_size / 100.0,
+        Value::Length(v, unit) => absolute_length(*v, *unit, parent_font_size, env)?,
+        Value::Calc(calc) => match calc_px(calc, parent_font_size, parent_font_size, env)? {
+            Calc::Length(px, _) => px,
+            _ => return None,
+        },
+        Value::Keyword(k) => match k.as_str() {
+            "xx-small" => 9.0,
+            "x-small" => 10.0,
+            "small" => 13.0,
+            "medium" => font::DEFAULT_FONT_SIZE,
+            "large" => 18.0,
+            "x-large" => 24.0,
+            "xx-large" => 32.0,
+            "smaller" => parent_font_size / 1.2,
+            "larger" => parent_font_size * 1.2,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(size).filter(|size| *size >= 0.0)
+}
+
+fn font_weight(value: &Value, parent_weight: u16) -> Option<u16> {
+    let number = match value {
+        Value::Number(number) => Some(*number),
+        Value::Calc(calc) => match calc.simplify(&|_, _| None)? {
+            Calc::Number(number) => Some(number),
+            _ => return None,
+        },
+        _ => None,
+    };
+    if let Some(number) = number {
+        return Some(number.round() as u16).filter(|w| (1..=1000).contains(w));
+    }
+    match keyword(value)? {
+        "normal" => Some(400),
+        "bold" => Some(700),
+        "bolder" => Some(match parent_weight {
+            0..=349 => 400,
+            350..=549 => 700,
+            _ => 900,
+        }),
+        "lighter" => Some(match parent_weight {
+            0..=549 => 100,
+            550..=749 => 400,
+            _ => 700,
+        }),
+        _ => None,
+    }
+}
+
+fn line_height(value: &Value, font_size: f32, env: &Environment) -> Option<LineHeight> {
+    let line_height = match value {
+        Value::Length(v, Unit::Percent) => LineHeight::Px(v * font_size / 100.0),
+        Value::Length(v, unit) => LineHeight::Px(absolute_length(*v, *unit, font_size, env)?),
+        Value::Number(number) => LineHeight::Number(*number),
+        Value::Calc(calc) => match calc_px(calc, font_size, font_size, env)? {
+            Calc::Number(number) => LineHeight::Number(number),
+            Calc::Length(px, _) => LineHeight::Px(px),
+            _ => return None,
+        },
+        Value::Keyword(k) if k == "normal" => LineHeight::Normal,
+        _ => return None,
+    };
+    match line_height {
+        LineHeight::Px(v) | LineHeight::Number(v) if v < 0.0 => None,
+        _ => Some(line_height),
+    }
+}
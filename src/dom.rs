@@ -2,8 +2,10 @@ use std::collections::hash_map::HashMap;
 use std::collections::hash_set::HashSet;
 use std::fmt;
 
-type AttrMap = HashMap<String, String>;
+/// Attributes of an element, names are lower case
+pub type AttrMap = HashMap<String, String>;
 
+#[derive(Clone)]
 pub struct Node {
     // data common to all nodes:
     pub children: Vec<Node>,
@@ -22,6 +24,7 @@ impl fmt::Display for Node {
     }
 }
 
+#[derive(Clone)]
 pub enum NodeType {
     Text(String),
     Element(ElementData),
@@ -29,32 +32,146 @@ pub enum NodeType {
 
 impl fmt::Display for NodeType {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match &*self {
+        match self {
             NodeType::Text(text) => write!(f, "{}", text),
             NodeType::Element(element_data) => write!(f, "{:?}", element_data),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ElementData {
     pub tag_name: String,
     attributes: AttrMap,
+    /// Interactive state, matched by `:hover`, `:focus` and `:checked`
+    pub state: ElementState,
+}
+
+/// A set of flags for the state of an element that changes while the page
+/// is shown. The initial state comes from the attributes; nothing points at
+/// or focuses elements yet, so `:hover` and `:focus` only match once the
+/// flags are turned on with `set` before styling.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ElementState(u8);
+
+impl ElementState {
+    pub const HOVER: ElementState = ElementState(1);
+    pub const FOCUS: ElementState = ElementState(1 << 1);
+    pub const CHECKED: ElementState = ElementState(1 << 2);
+
+    /// If all the flags of `other` are set
+    pub fn contains(self, other: ElementState) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Set or clear the flags of `other`
+    pub fn set(&mut self, other: ElementState, on: bool) {
+        if on {
+            self.0 |= other.0;
+        } else {
+            self.0 &= !other.0;
+        }
+    }
 }
 
 impl ElementData {
+    /// Value of the attribute `name`, an attribute without a value is the empty string
+    pub fn attr(&self, name: &str) -> Option<&String> {
+        self.attributes.get(name)
+    }
+
     pub fn id(&self) -> Option<&String> {
-        self.attributes.get("id")
+        self.attr("id")
     }
 
     pub fn classes(&self) -> HashSet<&str> {
-        match self.attributes.get("class") {
+        match self.attr("class") {
             Some(class_list) => class_list.split(' ').collect(),
             None => HashSet::new(),
         }
     }
 }
 
+/// An element together with the path that leads to it from the root.
+///
+/// Nodes only own their children, the path gives selector matching access
+/// to the parent and siblings of an element without links inside the tree.
+#[derive(Clone, Copy)]
+pub struct ElementRef<'a, 'p> {
+    pub node: &'a Node,
+    parent: Option<&'p ElementRef<'a, 'p>>,
+    // position among the children of the parent
+    index: usize,
+}
+
+impl<'a, 'p> ElementRef<'a, 'p> {
+    pub fn root(node: &'a Node) -> ElementRef<'a, 'p> {
+        ElementRef {
+            node,
+            parent: None,
+            index: 0,
+        }
+    }
+
+    /// The child of `parent` at `index`
+    pub fn child(parent: &'p ElementRef<'a, 'p>, index: usize) -> ElementRef<'a, 'p> {
+        ElementRef {
+            node: &parent.node.children[index],
+            parent: Some(parent),
+            index,
+        }
+    }
+
+    pub fn data(&self) -> &'a ElementData {
+        match self.node.node_type {
+            NodeType::Element(ref data) => data,
+            NodeType::Text(_) => panic!("ElementRef to a text node"),
+        }
+    }
+
+    pub fn parent(&self) -> Option<&'p ElementRef<'a, 'p>> {
+        self.parent
+    }
+
+    /// The elements after this one with the same parent, closest first
+    pub fn next_siblings(&self) -> impl Iterator<Item = ElementRef<'a, 'p>> {
+        let parent = self.parent;
+        let start = self.index + 1;
+        let siblings = match parent {
+            Some(parent) => &parent.node.children[start..],
+            None => &[],
+        };
+        siblings
+            .iter()
+            .enumerate()
+            .filter(|(_, node)| matches!(node.node_type, NodeType::Element(_)))
+            .map(move |(offset, node)| ElementRef {
+                node,
+                parent,
+                index: start + offset,
+            })
+    }
+
+    /// The elements before this one with the same parent, closest first
+    pub fn previous_siblings(&self) -> impl Iterator<Item = ElementRef<'a, 'p>> {
+        let parent = self.parent;
+        let siblings = match parent {
+            Some(parent) => &parent.node.children[..self.index],
+            None => &[],
+        };
+        siblings
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, node)| matches!(node.node_type, NodeType::Element(_)))
+            .map(move |(index, node)| ElementRef {
+                node,
+                parent,
+                index,
+            })
+    }
+}
+
 pub fn create_text(data: String) -> Node {
     Node {
         children: Vec::new(),
@@ -63,35 +180,91 @@ pub fn create_text(data: String) -> Node {
 }
 
 pub fn create_element(name: String, attrs: AttrMap, children: Vec<Node>) -> Node {
+    let mut state = ElementState::default();
+    let checked = match name.as_str() {
+        "input" => attrs.contains_key("checked"),
+        "option" => attrs.contains_key("selected"),
+        _ => false,
+    };
+    state.set(ElementState::CHECKED, checked);
     Node {
-        children: children,
+        children,
         node_type: NodeType::Element(ElementData {
             tag_name: name,
             attributes: attrs,
+            state,
         }),
     }
 }
 
-pub fn get_css_text(dom_tree: &Node) -> String {
-    let nodes = &dom_tree.children;
-    // Find Element with style tag and get its first child
-    for node in nodes.iter() {
-        match &node.node_type {
-            NodeType::Text(_) => {}
-            NodeType::Element(el) => {
-                if el.tag_name == "style" {
-                    return get_node_text(&node.children[0]);
+/// A stylesheet referenced by the document
+#[derive(Debug, PartialEq)]
+pub enum StylesheetRef {
+    /// The text of a `<style>` element
+    Inline(String),
+    /// The `href` of a `<link rel="stylesheet">` element
+    Link(String),
+}
+
+/// Find every stylesheet of the document, in document order.
+pub fn find_stylesheets(root: &Node) -> Vec<StylesheetRef> {
+    let mut found = Vec::new();
+    collect_stylesheets(root, &mut found);
+    found
+}
+
+fn collect_stylesheets(node: &Node, found: &mut Vec<StylesheetRef>) {
+    if let NodeType::Element(ref el) = node.node_type {
+        match el.tag_name.as_str() {
+            "style" => found.push(StylesheetRef::Inline(get_node_text(node))),
+            "link" if is_stylesheet_link(el) => {
+                if let Some(href) = el.attr("href") {
+                    found.push(StylesheetRef::Link(href.trim().to_string()));
                 }
             }
+            _ => {}
         }
     }
+    for child in &node.children {
+        collect_stylesheets(child, found);
+    }
+}
 
-    String::from("")
+// `rel` is a list of keywords, `<link rel="alternate stylesheet">` is
+// only applied when the user picks it.
+fn is_stylesheet_link(el: &ElementData) -> bool {
+    let rel = match el.attr("rel") {
+        Some(rel) => rel.to_ascii_lowercase(),
+        None => return false,
+    };
+    let mut keywords = rel.split_whitespace();
+    keywords.clone().any(|keyword| keyword == "stylesheet")
+        && !keywords.any(|keyword| keyword == "alternate")
 }
 
+// Concatenate the text children of a node
 fn get_node_text(node: &Node) -> String {
-    match &node.node_type {
-        NodeType::Text(s) => return s.to_string(),
-        NodeType::Element(_) => return String::from(""),
-    }
+    node.children
+        .iter()
+        .filter_map(|child| match &child.node_type {
+            NodeType::Text(s) => Some(s.as_str()),
+            NodeType::Element(_) => None,
+        })
+        .collect()
+}
+
+#[test]
+fn test_find_stylesheets() {
+    let dom = super::parser::html::parse(
+        "<html><head><style>a {}</style><link rel=\"Stylesheet\" href=\"a.css\"><link rel=icon href=i.png></head><body><div><style>b {}</style></div></body></html>"
+            .to_string(),
+    );
+    assert_eq!(
+        find_stylesheets(&dom),
+        vec![
+            StylesheetRef::Inline("a {}".to_string()),
+            StylesheetRef::Link("a.css".to_string()),
+            StylesheetRef::Inline("b {}".to_string()),
+        ]
+    );
 }
@@ -9,13 +9,25 @@ use super::Result;
 #[derive(Debug)]
 pub enum Error {
     /// The stream contained malformed data and could not be parsed.
-    ParseError(&'static str),
+    Parse(&'static str),
+    /// The command line arguments were not understood.
+    Usage(String),
+    /// Reading or writing a file failed.
+    Io(std::io::Error),
+    /// The rendered page could not be encoded as an image.
+    Encode(png::EncodingError),
+    /// A resource could not be fetched over the network.
+    Http(reqwest::Error),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            &Error::ParseError(ref msg) => write!(f, "{}", msg),
+            Error::Parse(msg) => write!(f, "{}", msg),
+            Error::Usage(msg) => write!(f, "{}", msg),
+            Error::Io(err) => write!(f, "{}", err),
+            Error::Encode(err) => write!(f, "{}", err),
+            Error::Http(err) => write!(f, "{}", err),
         }
     }
 }
@@ -23,24 +35,45 @@ impl fmt::Display for Error {
 impl std::error::Error for Error {
     fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
-            Error::ParseError(_) => None,
+            Error::Parse(_) | Error::Usage(_) => None,
+            Error::Io(ref err) => Some(err),
+            Error::Encode(ref err) => Some(err),
+            Error::Http(ref err) => Some(err),
         }
     }
 }
 
 impl From<std::num::ParseIntError> for Error {
     fn from(_: std::num::ParseIntError) -> Self {
-        Error::ParseError("unable to parse color")
+        Error::Parse("unable to parse color")
     }
 }
 
 impl From<std::num::ParseFloatError> for Error {
     fn from(_: std::num::ParseFloatError) -> Self {
-        Error::ParseError("unable to parse color")
+        Error::Parse("unable to parse color")
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    fn from(err: png::EncodingError) -> Self {
+        Error::Encode(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
     }
 }
 
 /// function to create a decode error.
 pub fn parse_error<T>(desc: &'static str) -> Result<T> {
-    Err(Error::ParseError(desc))
+    Err(Error::Parse(desc))
 }
@@ -1,37 +1,57 @@
-use reqwest;
 use std::fs;
 
+use reqwest::Url;
+
+use super::Result;
+
 pub struct HttpClient {
     client: reqwest::blocking::Client,
-    error_string: String,
     file_prefix: &'static str,
 }
 
 impl HttpClient {
-    pub fn get(self, url: &str) -> String {
+    /// Read the resource at `url`, a `file://` url reads a local file.
+    pub fn fetch(&self, url: &str) -> Result<String> {
         // add local file read
-        if url.contains(self.file_prefix) {
-            let file_path = url.replace(self.file_prefix, "");
-            return match fs::read_to_string(file_path) {
-                Err(_) => self.error_string,
-                Ok(data) => data,
-            };
+        if url.starts_with(self.file_prefix) {
+            let file_path = url.replacen(self.file_prefix, "", 1);
+            return Ok(fs::read_to_string(file_path)?);
         }
 
-        match self.client.get(url).send() {
-            Err(_) => self.error_string,
-            Ok(resp) => match resp.text() {
-                Err(_) => self.error_string,
-                Ok(text) => text,
-            },
-        }
+        let resp = self.client.get(url).send()?.error_for_status()?;
+        Ok(resp.text()?)
     }
 
     pub fn new() -> HttpClient {
         HttpClient {
             client: reqwest::blocking::Client::new(),
-            error_string: String::from("<html><body>Error</body></html>"),
             file_prefix: "file://",
         }
     }
 }
+
+/// Resolve a possibly relative `href` against the url of the page it appears in.
+pub fn resolve_url(base: &str, href: &str) -> Option<String> {
+    let url = match Url::parse(base) {
+        Ok(base) => base.join(href),
+        Err(_) => Url::parse(href),
+    };
+    url.ok().map(String::from)
+}
+
+#[test]
+fn test_resolve_url() {
+    let page = "http://example.com/docs/index.html";
+    assert_eq!(
+        resolve_url(page, "style.css"),
+        Some("http://example.com/docs/style.css".to_string())
+    );
+    assert_eq!(
+        resolve_url(page, "/main.css"),
+        Some("http://example.com/main.css".to_string())
+    );
+    assert_eq!(
+        resolve_url("file:///home/page.html", "a.css"),
+        Some("file:///home/a.css".to_string())
+    );
+}
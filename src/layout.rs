@@ -0,0 +1,696 @@
+/// This module takes the style tree and builds a tree of boxes,
+/// calculating the position and size of every box.
+///
+/// Block boxes are stacked vertically inside their containing block,
+/// inline boxes and text are placed in line boxes from left to right
+/// and wrapped when a line is full.
+use super::computed::{Length, Sides};
+use super::dom;
+use super::font;
+use super::style::{Display, StyledNode};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rect {
+    pub fn expanded_by(self, edge: EdgeSizes) -> Rect {
+        Rect {
+            x: self.x - edge.left,
+            y: self.y - edge.top,
+            width: self.width + edge.left + edge.right,
+            height: self.height + edge.top + edge.bottom,
+        }
+    }
+
+    /// Smallest rectangle containing both `self` and `other`
+    pub fn union(self, other: Rect) -> Rect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct EdgeSizes {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Dimensions {
+    // Position of the content area relative to the document origin:
+    pub content: Rect,
+
+    // Surrounding edges:
+    pub padding: EdgeSizes,
+    pub border: EdgeSizes,
+    pub margin: EdgeSizes,
+}
+
+impl Dimensions {
+    // The area covered by the content area plus its padding.
+    pub fn padding_box(self) -> Rect {
+        self.content.expanded_by(self.padding)
+    }
+
+    // The area covered by the content area plus padding and borders.
+    pub fn border_box(self) -> Rect {
+        self.padding_box().expanded_by(self.border)
+    }
+
+    // The area covered by the content area plus padding, borders, and margin.
+    pub fn margin_box(self) -> Rect {
+        self.border_box().expanded_by(self.margin)
+    }
+}
+
+/// A piece of text placed on a single line
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    pub text: String,
+    pub rect: Rect,
+}
+
+pub struct LayoutBox<'a> {
+    pub dimensions: Dimensions,
+    pub box_type: BoxType<'a>,
+    pub children: Vec<LayoutBox<'a>>,
+    // font size in px, used to measure text
+    pub font_size: f32,
+    // minimum height of the lines the box is on
+    pub line_height: f32,
+    // only filled for text boxes, one run per line the text occupies
+    pub text_runs: Vec<TextRun>,
+}
+
+pub enum BoxType<'a> {
+    BlockNode(&'a StyledNode<'a>),
+    InlineNode(&'a StyledNode<'a>),
+    TextNode(&'a StyledNode<'a>),
+    AnonymousBlock,
+}
+
+/// Tracks where the next piece of inline content goes
+/// while filling line boxes of a block
+struct LineCursor {
+    x: f32,
+    y: f32,
+    left: f32,
+    right: f32,
+    // height of the tallest content on the current line
+    line_height: f32,
+    // whitespace was seen since the last word
+    pending_space: bool,
+}
+
+impl LineCursor {
+    fn at_line_start(&self) -> bool {
+        self.x <= self.left
+    }
+
+    fn new_line(&mut self) {
+        self.y += self.line_height;
+        self.x = self.left;
+        self.line_height = 0.0;
+        self.pending_space = false;
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    fn new(box_type: BoxType<'a>, font_size: f32, line_height: f32) -> LayoutBox<'a> {
+        LayoutBox {
+            box_type,
+            dimensions: Default::default(),
+            children: Vec::new(),
+            font_size,
+            line_height,
+            text_runs: Vec::new(),
+        }
+    }
+
+    pub fn get_style_node(&self) -> Option<&'a StyledNode<'a>> {
+        match self.box_type {
+            BoxType::BlockNode(node) | BoxType::InlineNode(node) | BoxType::TextNode(node) => {
+                Some(node)
+            }
+            BoxType::AnonymousBlock => None,
+        }
+    }
+}
+
+/// Transform a style tree into a layout tree, laid out inside `containing_block`.
+///
+/// Only the width of the containing block is used, the height grows with the content.
+pub fn layout_tree<'a>(
+    node: &'a StyledNode<'a>,
+    mut containing_block: Dimensions,
+) -> LayoutBox<'a> {
+    // The layout algorithm expects the container height to start at 0.
+    containing_block.content.height = 0.0;
+
+    let mut root_box = build_root_box(node);
+    root_box.layout(containing_block);
+    root_box
+}
+
+// The root element always generates a block box.
+fn build_root_box<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    let style = style_node.computed_style();
+    let mut root = LayoutBox::new(
+        BoxType::BlockNode(style_node),
+        style.font_size,
+        style.line_height_px(),
+    );
+    if style_node.display() != Display::None {
+        add_children(&mut root, style_node);
+    }
+    root
+}
+
+// Build the tree of LayoutBoxes, but don't perform any layout calculations yet.
+fn build_layout_tree<'a>(style_node: &'a StyledNode<'a>) -> LayoutBox<'a> {
+    let style = style_node.computed_style();
+    let box_type = match style_node.node.node_type {
+        dom::NodeType::Text(_) => BoxType::TextNode(style_node),
+        dom::NodeType::Element(_) => match style_node.display() {
+            Display::Block => BoxType::BlockNode(style_node),
+            Display::Inline => BoxType::InlineNode(style_node),
+            Display::None => unreachable!("display: none nodes do not generate boxes"),
+        },
+    };
+    let mut root = LayoutBox::new(box_type, style.font_size, style.line_height_px());
+    add_children(&mut root, style_node);
+    root
+}
+
+fn add_children<'a>(root: &mut LayoutBox<'a>, style_node: &'a StyledNode<'a>) {
+    for child in &style_node.children {
+        let display = match child.node.node_type {
+            dom::NodeType::Text(_) => Display::Inline,
+            dom::NodeType::Element(_) => child.display(),
+        };
+        match display {
+            Display::None => {} // Skip nodes with `display: none;`
+            // A block inside an inline box is laid out as inline content.
+            Display::Block => root.children.push(build_layout_tree(child)),
+            Display::Inline => {
+                let child_box = build_layout_tree(child);
+                root.get_inline_container().children.push(child_box)
+            }
+        }
+    }
+}
+
+impl<'a> LayoutBox<'a> {
+    // Where a new inline child should go.
+    fn get_inline_container(&mut self) -> &mut LayoutBox<'a> {
+        match self.box_type {
+            BoxType::InlineNode(_) | BoxType::TextNode(_) | BoxType::AnonymousBlock => self,
+            BoxType::BlockNode(_) => {
+                // If we've just generated an anonymous block box, keep using it.
+                // Otherwise, create a new one.
+                let (font_size, line_height) = (self.font_size, self.line_height);
+                match self.children.last() {
+                    Some(LayoutBox {
+                        box_type: BoxType::AnonymousBlock,
+                        ..
+                    }) => {}
+                    _ => self.children.push(LayoutBox::new(
+                        BoxType::AnonymousBlock,
+                        font_size,
+                        line_height,
+                    )),
+                }
+                self.children.last_mut().unwrap()
+            }
+        }
+    }
+
+    // Lay out a box and its descendants.
+    fn layout(&mut self, containing_block: Dimensions) {
+        match self.box_type {
+            BoxType::BlockNode(_) => self.layout_block(containing_block),
+            BoxType::AnonymousBlock => self.layout_anonymous_block(containing_block),
+            // inline content is placed by its block container
+            BoxType::InlineNode(_) | BoxType::TextNode(_) => {}
+        }
+    }
+
+    // Lay out a block-level element and its descendants.
+    fn layout_block(&mut self, containing_block: Dimensions) {
+        // Child width can depend on parent width, so we need to calculate
+        // this box's width before laying out its children.
+        self.calculate_block_width(containing_block);
+
+        // Determine where the box is located within its container.
+        self.calculate_block_position(containing_block);
+
+        // Recursively lay out the children of this box.
+        self.layout_block_children();
+
+        // Parent height can depend on child height, so `calculate_height`
+        // must be called *after* the children are laid out.
+        self.calculate_block_height();
+    }
+
+    // An anonymous block holds a run of inline content between block siblings.
+    fn layout_anonymous_block(&mut self, containing_block: Dimensions) {
+        let d = &mut self.dimensions;
+        d.content.width = containing_block.content.width;
+        d.content.x = containing_block.content.x;
+        d.content.y = containing_block.content.y + containing_block.content.height;
+
+        self.layout_inline_children();
+    }
+
+    /// Calculate the width of a block-level non-replaced element in normal flow.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#blockwidth
+    ///
+    /// Sets the horizontal margin/padding/border dimensions, and the `width`.
+    fn calculate_block_width(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node().unwrap().computed_style();
+        // percentages refer to the width of the containing block
+        let cb_width = containing_block.content.width;
+
+        // `width` has initial value `auto`, `None` stands for `auto` below.
+        let width = style.width.resolve_non_negative(cb_width);
+
+        // margin, border, and padding have initial value 0.
+        let mut margin_left = style.margin.left.resolve(cb_width);
+        let mut margin_right = style.margin.right.resolve(cb_width);
+
+        let border_left = style.border_width.left;
+        let border_right = style.border_width.right;
+
+        let padding_left = style.padding.left.to_px(cb_width).max(0.0);
+        let padding_right = style.padding.right.to_px(cb_width).max(0.0);
+
+        let total = margin_left.unwrap_or(0.0)
+            + margin_right.unwrap_or(0.0)
+            + border_left
+            + border_right
+            + padding_left
+            + padding_right
+            + width.unwrap_or(0.0);
+
+        // If width is not auto and the total is wider than the container, treat auto margins as 0.
+        if width.is_some() && total > containing_block.content.width {
+            margin_left = margin_left.or(Some(0.0));
+            margin_right = margin_right.or(Some(0.0));
+        }
+
+        // Adjust used values so that the above sum equals `containing_block.width`.
+        // Each arm of the `match` should increase the total width by exactly `underflow`,
+        // and afterward all values should be absolute lengths in px.
+        let underflow = containing_block.content.width - total;
+
+        let (width, margin_left, margin_right) = match (width, margin_left, margin_right) {
+            // If the values are overconstrained, calculate margin_right.
+            (Some(w), Some(ml), Some(mr)) => (w, ml, mr + underflow),
+
+            // If exactly one size is auto, its used value follows from the equality.
+            (Some(w), Some(ml), None) => (w, ml, underflow),
+            (Some(w), None, Some(mr)) => (w, underflow, mr),
+
+            // If margin-left and margin-right are both auto, their used values are equal.
+            (Some(w), None, None) => (w, underflow / 2.0, underflow / 2.0),
+
+            // If width is set to auto, any other auto values become 0.
+            (None, ml, mr) => {
+                let (ml, mr) = (ml.unwrap_or(0.0), mr.unwrap_or(0.0));
+                if underflow >= 0.0 {
+                    // Expand width to fill the underflow.
+                    (underflow, ml, mr)
+                } else {
+                    // Width can't be negative. Adjust the right margin instead.
+                    (0.0, ml, mr + underflow)
+                }
+            }
+        };
+
+        let d = &mut self.dimensions;
+        d.content.width = width;
+
+        d.padding.left = padding_left;
+        d.padding.right = padding_right;
+
+        d.border.left = border_left;
+        d.border.right = border_right;
+
+        d.margin.left = margin_left;
+        d.margin.right = margin_right;
+    }
+
+    /// Finish calculating the block's edge sizes, and position it within its containing block.
+    ///
+    /// http://www.w3.org/TR/CSS2/visudet.html#normal-block
+    ///
+    /// Sets the vertical margin/padding/border dimensions, and the `x`, `y` values.
+    fn calculate_block_position(&mut self, containing_block: Dimensions) {
+        let style = self.get_style_node().unwrap().computed_style();
+        let cb_width = containing_block.content.width;
+        let d = &mut self.dimensions;
+
+        // If margin-top or margin-bottom is `auto`, the used value is zero.
+        d.margin.top = style.margin.top.to_px(cb_width);
+        d.margin.bottom = style.margin.bottom.to_px(cb_width);
+
+        d.border.top = style.border_width.top;
+        d.border.bottom = style.border_width.bottom;
+
+        d.padding.top = style.padding.top.to_px(cb_width).max(0.0);
+        d.padding.bottom = style.padding.bottom.to_px(cb_width).max(0.0);
+
+        d.content.x = containing_block.content.x + d.margin.left + d.border.left + d.padding.left;
+
+        // Position the box below all the previous boxes in the container.
+        d.content.y = containing_block.content.height
+            + containing_block.content.y
+            + d.margin.top
+            + d.border.top
+            + d.padding.top;
+    }
+
+    /// Lay out the block's children within its content area.
+    ///
+    /// Sets `self.dimensions.height` to the total content height.
+    fn layout_block_children(&mut self) {
+        let d = &mut self.dimensions;
+        for child in &mut self.children {
+            child.layout(*d);
+            // Increment the height so each child is laid out below the previous one.
+            d.content.height += child.dimensions.margin_box().height;
+        }
+    }
+
+    /// Height of a block-level non-replaced element in normal flow with overflow visible.
+    fn calculate_block_height(&mut self) {
+        // If the height is set to an explicit length, use that exact length.
+        // Otherwise, just keep the value set by `layout_block_children`.
+        // The containing block grows with its content, so percentages act as `auto`.
+        if let Length::Px(h) = self.get_style_node().unwrap().computed_style().height {
+            self.dimensions.content.height = h;
+        }
+    }
+
+    /// Fill line boxes with the inline children, wrapping them at the content edge.
+    ///
+    /// Sets `self.dimensions.height` to the total height of all lines.
+    fn layout_inline_children(&mut self) {
+        let content = self.dimensions.content;
+        let mut cursor = LineCursor {
+            x: content.x,
+            y: content.y,
+            left: content.x,
+            right: content.x + content.width,
+            line_height: 0.0,
+            pending_space: false,
+        };
+
+        for child in &mut self.children {
+            child.layout_inline(&mut cursor);
+        }
+
+        self.dimensions.content.height = cursor.y + cursor.line_height - content.y;
+    }
+
+    // Place an inline box or a piece of text at the cursor.
+    fn layout_inline(&mut self, cursor: &mut LineCursor) {
+        match self.box_type {
+            BoxType::TextNode(style) => match style.node.node_type {
+                dom::NodeType::Text(ref text) => self.layout_text(text, cursor),
+                dom::NodeType::Element(_) => {}
+            },
+            _ => self.layout_inline_box(cursor),
+        }
+    }
+
+    /// Horizontal margin, border and padding push the content along the line,
+    /// vertical ones are drawn around the content but don't affect the line height.
+    fn layout_inline_box(&mut self, cursor: &mut LineCursor) {
+        let font_size = self.font_size;
+        let line_height = self.line_height;
+        let style = self.get_style_node();
+        let d = &mut self.dimensions;
+        // a block nested in an inline box wraps its text in an anonymous
+        // block, which has no edges of its own
+        if let Some(style) = style {
+            let style = style.computed_style();
+            // percentages refer to the width of the block holding the line
+            let cb_width = cursor.right - cursor.left;
+            let px = |sides: &Sides<Length>| EdgeSizes {
+                left: sides.left.to_px(cb_width),
+                right: sides.right.to_px(cb_width),
+                top: sides.top.to_px(cb_width),
+                bottom: sides.bottom.to_px(cb_width),
+            };
+            let non_negative = |edges: EdgeSizes| EdgeSizes {
+                left: edges.left.max(0.0),
+                right: edges.right.max(0.0),
+                top: edges.top.max(0.0),
+                bottom: edges.bottom.max(0.0),
+            };
+            d.margin = px(&style.margin);
+            d.padding = non_negative(px(&style.padding));
+            d.border = EdgeSizes {
+                left: style.border_width.left,
+                right: style.border_width.right,
+                top: style.border_width.top,
+                bottom: style.border_width.bottom,
+            };
+        }
+
+        let start = d.margin.left + d.border.left + d.padding.left;
+        let end = d.margin.right + d.border.right + d.padding.right;
+        // A space before the box belongs outside its edges.
+        if start > 0.0 && cursor.pending_space && !cursor.at_line_start() {
+            cursor.x += font::char_width(font_size);
+            cursor.pending_space = false;
+        }
+        cursor.x += start;
+
+        // An empty box still occupies a line of its own height.
+        let empty = Rect {
+            x: cursor.x,
+            y: cursor.y,
+            width: 0.0,
+            height: line_height,
+        };
+        let mut content: Option<Rect> = None;
+        for child in &mut self.children {
+            child.layout_inline(cursor);
+            let child_box = child.dimensions.margin_box();
+            if child_box.width > 0.0 || child_box.height > 0.0 {
+                content = Some(content.map_or(child_box, |rect| rect.union(child_box)));
+            }
+        }
+        cursor.line_height = cursor.line_height.max(line_height);
+
+        self.dimensions.content = content.unwrap_or(empty);
+        cursor.x += end;
+    }
+
+    // Break the text into words and put as many as fit on each line.
+    fn layout_text(&mut self, text: &str, cursor: &mut LineCursor) {
+        let font_size = self.font_size;
+        let line_height = self.line_height;
+        let space = font::char_width(font_size);
+
+        if text.starts_with(char::is_whitespace) {
+            cursor.pending_space = true;
+        }
+
+        let mut runs: Vec<TextRun> = Vec::new();
+        for (i, word) in text.split_whitespace().enumerate() {
+            if i > 0 {
+                cursor.pending_space = true;
+            }
+            let width = font::text_width(word, font_size);
+            let mut gap = if cursor.pending_space && !cursor.at_line_start() {
+                space
+            } else {
+                0.0
+            };
+            if cursor.x + gap + width > cursor.right && !cursor.at_line_start() {
+                cursor.new_line();
+                gap = 0.0;
+            }
+
+            // Words on the same line extend the previous run.
+            match runs.last_mut() {
+                Some(run) if run.rect.y == cursor.y => {
+                    if gap > 0.0 {
+                        run.text.push(' ');
+                    }
+                    run.text.push_str(word);
+                    run.rect.width += gap + width;
+                }
+                _ => runs.push(TextRun {
+                    text: word.to_string(),
+                    rect: Rect {
+                        x: cursor.x + gap,
+                        y: cursor.y,
+                        width,
+                        height: line_height,
+                    },
+                }),
+            }
+            cursor.x += gap + width;
+            cursor.line_height = cursor.line_height.max(line_height);
+            cursor.pending_space = false;
+        }
+
+        if text.ends_with(char::is_whitespace) {
+            cursor.pending_space = true;
+        }
+
+        self.dimensions.content = match runs.first() {
+            Some(first) => runs
+                .iter()
+                .fold(first.rect, |rect, run| rect.union(run.rect)),
+            None => Rect {
+                x: cursor.x,
+                y: cursor.y,
+                width: 0.0,
+                height: 0.0,
+            },
+        };
+        self.text_runs = runs;
+    }
+}
+
+#[cfg(test)]
+fn viewport(width: f32) -> Dimensions {
+    let mut viewport: Dimensions = Default::default();
+    viewport.content.width = width;
+    viewport
+}
+
+#[test]
+fn test_layout_block() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\"><p class=\"b\"></p><p class=\"b\"></p></div>".to_string(),
+    );
+    let stylesheets = [super::parser::css::parse(
+        ".a { display: block; margin: 10px; padding: 5px; } .b { display: block; height: 20px; border: 1px solid; }"
+            .to_string(),
+        super::parser::css::Origin::Author,
+    )];
+    let style = super::style::style_tree(&dom, &stylesheets, super::style::StyleOptions::default());
+    let root = layout_tree(&style, viewport(200.0));
+
+    let d = root.dimensions;
+    assert_eq!(
+        d.content,
+        Rect {
+            x: 15.0,
+            y: 15.0,
+            width: 170.0,
+            height: 44.0
+        }
+    );
+    assert_eq!(d.margin_box().height, 74.0);
+
+    let second = root.children[1].dimensions;
+    assert_eq!(
+        second.border_box(),
+        Rect {
+            x: 15.0,
+            y: 37.0,
+            width: 170.0,
+            height: 22.0
+        }
+    );
+}
+
+#[test]
+fn test_layout_inline_wraps_lines() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\">aaaa bbbb <span>cccc</span></div>".to_string(),
+    );
+    let stylesheets = [super::parser::css::parse(
+        ".a { display: block; }".to_string(),
+        super::parser::css::Origin::Author,
+    )];
+    let style = super::style::style_tree(&dom, &stylesheets, super::style::StyleOptions::default());
+    // room for 10 characters per line
+    let root = layout_tree(&style, viewport(font::char_width(16.0) * 10.0));
+
+    let line = root.children[0].children[0].clone_runs();
+    assert_eq!(line, vec!["aaaa bbbb"]);
+    let span = &root.children[0].children[1];
+    assert_eq!(span.children[0].clone_runs(), vec!["cccc"]);
+    assert_eq!(span.dimensions.content.y, font::line_height(16.0));
+    assert_eq!(
+        root.dimensions.content.height,
+        font::line_height(16.0) * 2.0
+    );
+}
+
+#[test]
+fn test_layout_block_in_inline() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\"><span><p class=\"a\">x</p></span></div>".to_string(),
+    );
+    let stylesheets = [super::parser::css::parse(
+        ".a { display: block; }".to_string(),
+        super::parser::css::Origin::Author,
+    )];
+    let style = super::style::style_tree(&dom, &stylesheets, super::style::StyleOptions::default());
+    let root = layout_tree(&style, viewport(200.0));
+
+    // div > anonymous block > span > p > anonymous block > text
+    let p = &root.children[0].children[0].children[0];
+    assert_eq!(p.children[0].children[0].clone_runs(), vec!["x"]);
+    assert_eq!(root.dimensions.content.height, font::line_height(16.0));
+}
+
+#[test]
+fn test_layout_calc() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\"><p class=\"b\"></p><p class=\"c\"></p><p class=\"d\"></p></div>"
+            .to_string(),
+    );
+    let stylesheets = [super::parser::css::parse(
+        ".a { display: block; width: calc(50% + 2em); } \
+         .b { display: block; width: calc(100% - 2 * 10px); height: max(1em, 10px); padding: 0 calc(5% + 1px); } \
+         .c { display: block; width: clamp(50px, 10%, 100px); margin-left: min(25%, 1in); } \
+         .d { display: block; width: calc(0% - 100px); height: calc(1px - 2em); \
+              padding-left: calc(0% - 10px); border: solid calc(1px - 1em); }"
+            .to_string(),
+        super::parser::css::Origin::Author,
+    )];
+    let style = super::style::style_tree(&dom, &stylesheets, super::style::StyleOptions::default());
+    let root = layout_tree(&style, viewport(400.0));
+
+    assert_eq!(root.dimensions.content.width, 232.0);
+    let b = root.children[0].dimensions;
+    assert_eq!((b.content.width, b.content.height), (212.0, 16.0));
+    assert_eq!(b.padding.left, 12.6);
+    let c = root.children[1].dimensions;
+    assert_eq!((c.content.width, c.margin.left), (50.0, 58.0));
+    // negative results are clamped at zero
+    let d = root.children[2].dimensions;
+    assert_eq!((d.content.width, d.content.height), (0.0, 0.0));
+    assert_eq!((d.padding.left, d.border.left), (0.0, 0.0));
+}
+
+#[cfg(test)]
+impl<'a> LayoutBox<'a> {
+    fn clone_runs(&self) -> Vec<String> {
+        self.text_runs.iter().map(|run| run.text.clone()).collect()
+    }
+}
@@ -1,29 +1,158 @@
-#[macro_use]
-extern crate lazy_static;
-
+mod cli;
+mod computed;
 mod dom;
 mod errors;
+mod font;
 mod http;
+mod layout;
+mod paint;
 mod parser;
 mod style;
+mod variables;
 mod window;
 
+use std::env;
+use std::process;
+
 pub const BROWSER_NAME: &str = "ToyBrowser";
 
 /// A type for result generated by Cauldron
 pub type Result<T> = std::result::Result<T, errors::Error>;
 
 fn main() {
-    // window::open_browser(BROWSER_NAME);
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match cli::parse_args(&args) {
+        Ok(cli::Command::Browse) => {
+            window::open_browser(BROWSER_NAME);
+            Ok(())
+        }
+        Ok(cli::Command::Help) => {
+            println!("{}", cli::USAGE);
+            Ok(())
+        }
+        Ok(cli::Command::Render(options)) => render(&options),
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
 
+/// Run the whole pipeline on a page and save the viewport as a PNG image.
+fn render(options: &cli::RenderOptions) -> Result<()> {
     let client = http::HttpClient::new();
 
-    let a = client.get("file:///home/deepankar/test.html");
-    // println!("{}", a);
+    let html = client.fetch(&options.url)?;
+
+    let dom_tree = parser::html::parse(html);
+
+    let mut stylesheets = vec![style::user_agent_stylesheet()];
+    if let Some(ref url) = options.user_stylesheet {
+        let text = client.fetch(url)?;
+        let mut stylesheet = parse_stylesheet(text, parser::css::Origin::User, url);
+        stylesheet.source = parser::css::StyleSource::Url(url.clone());
+        stylesheets.push(stylesheet);
+    }
+    stylesheets.extend(load_stylesheets(&dom_tree, &options.url, &client));
+
+    let style_options = style::StyleOptions {
+        parallel: options.parallel_style,
+        media: parser::media::MediaEnvironment {
+            media_type: options.media_type,
+            width: options.width as f32,
+            height: options.height as f32,
+            color_scheme: options.color_scheme,
+            ..Default::default()
+        },
+    };
+    let style_dom = if options.style_stats {
+        let (style_dom, stats) =
+            style::style_tree_with_stats(&dom_tree, &stylesheets, style_options);
+        eprintln!("{}", stats);
+        style_dom
+    } else {
+        style::style_tree(&dom_tree, &stylesheets, style_options)
+    };
 
-    let dom_tree = parser::html::parse(a);
+    let mut viewport: layout::Dimensions = Default::default();
+    viewport.content.width = options.width as f32;
+    viewport.content.height = options.height as f32;
 
-    let stylesheet = parser::css::parse(dom::get_css_text(&dom_tree));
+    let layout_root = layout::layout_tree(&style_dom, viewport);
+
+    let canvas = paint::paint(&layout_root, viewport.content);
+
+    canvas.write_png(&options.output)
+}
+
+/// Parse every stylesheet of the page, fetching the linked ones.
+///
+/// A sheet that can't be loaded is skipped with a warning, a sheet linked
+/// more than once is only fetched the first time.
+fn load_stylesheets(
+    dom_tree: &dom::Node,
+    page_url: &str,
+    client: &http::HttpClient,
+) -> Vec<parser::css::Stylesheet> {
+    let mut stylesheets: Vec<parser::css::Stylesheet> = Vec::new();
+
+    for found in dom::find_stylesheets(dom_tree) {
+        let href = match found {
+            dom::StylesheetRef::Inline(text) => {
+                stylesheets.push(parse_stylesheet(
+                    text,
+                    parser::css::Origin::Author,
+                    "<style>",
+                ));
+                continue;
+            }
+            dom::StylesheetRef::Link(href) => href,
+        };
+        let url = match http::resolve_url(page_url, &href) {
+            Some(url) => url,
+            None => {
+                eprintln!("warning: invalid stylesheet url `{}`", href);
+                continue;
+            }
+        };
+        let source = parser::css::StyleSource::Url(url.clone());
+
+        let loaded = stylesheets.iter().find(|sheet| sheet.source == source);
+        let mut stylesheet = match loaded {
+            Some(sheet) => parser::css::Stylesheet {
+                rules: sheet.rules.clone(),
+                source: sheet.source.clone(),
+            },
+            None => match client.fetch(&url) {
+                Ok(text) => parse_stylesheet(text, parser::css::Origin::Author, &url),
+                Err(err) => {
+                    eprintln!("warning: could not load stylesheet {}: {}", url, err);
+                    continue;
+                }
+            },
+        };
+        stylesheet.source = source;
+        stylesheets.push(stylesheet);
+    }
+
+    stylesheets
+}
 
-    let style_dom = style::style_tree(&dom_tree, &stylesheet);
+// Parse a stylesheet, warning about the rules and declarations it drops
+fn parse_stylesheet(
+    text: String,
+    origin: parser::css::Origin,
+    name: &str,
+) -> parser::css::Stylesheet {
+    let (stylesheet, diagnostics) = parser::css::parse_with_diagnostics(text, origin);
+    for diagnostic in diagnostics {
+        eprintln!("warning: {}: {}", name, diagnostic);
+    }
+    stylesheet
 }
@@ -0,0 +1,316 @@
+/// This module takes the layout tree and paints it
+///
+/// Painting happens in two steps, first a display list of simple
+/// drawing commands is built from the boxes, then each command is
+/// rasterized into a canvas of pixels in memory. Everything runs on
+/// the CPU so pages can be rendered without a GPU or a display.
+use super::computed::{ComputedStyle, FontStyle};
+use super::font;
+use super::layout::{LayoutBox, Rect, TextRun};
+use super::parser::css::Color;
+use super::Result;
+
+use std::fs::File;
+use std::io::BufWriter;
+
+pub type DisplayList = Vec<DisplayCommand>;
+
+#[derive(Debug, PartialEq)]
+pub enum DisplayCommand {
+    SolidColor(Color, Rect),
+    Text {
+        color: Color,
+        run: TextRun,
+        font_size: f32,
+        bold: bool,
+        italic: bool,
+    },
+}
+
+/// A buffer of pixels, stored row by row
+pub struct Canvas {
+    pub pixels: Vec<Color>,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Paint a tree of LayoutBoxes to an array of pixels.
+pub fn paint(layout_root: &LayoutBox, bounds: Rect) -> Canvas {
+    let display_list = build_display_list(layout_root);
+    let mut canvas = Canvas::new(bounds.width as usize, bounds.height as usize);
+    for item in display_list {
+        canvas.paint_item(&item);
+    }
+    canvas
+}
+
+pub fn build_display_list(layout_root: &LayoutBox) -> DisplayList {
+    let mut list = Vec::new();
+    render_layout_box(&mut list, layout_root);
+    list
+}
+
+fn render_layout_box(list: &mut DisplayList, layout_box: &LayoutBox) {
+    // anonymous blocks have no style and draw nothing themselves,
+    // the children of a hidden box may still be visible
+    let style = layout_box
+        .get_style_node()
+        .map(|node| node.computed_style());
+    if let Some(style) = style.filter(|style| style.is_visible()) {
+        render_background(list, layout_box, style);
+        render_borders(list, layout_box, style);
+        render_text(list, layout_box, style);
+    }
+
+    for child in &layout_box.children {
+        render_layout_box(list, child);
+    }
+}
+
+fn render_background(list: &mut DisplayList, layout_box: &LayoutBox, style: &ComputedStyle) {
+    list.push(DisplayCommand::SolidColor(
+        style.background_color.clone(),
+        layout_box.dimensions.padding_box(),
+    ));
+}
+
+fn render_borders(list: &mut DisplayList, layout_box: &LayoutBox, style: &ComputedStyle) {
+    let d = &layout_box.dimensions;
+    let border_box = d.border_box();
+    let colors = &style.border_color;
+
+    // Left border
+    list.push(DisplayCommand::SolidColor(
+        colors.left.clone(),
+        Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: d.border.left,
+            height: border_box.height,
+        },
+    ));
+
+    // Right border
+    list.push(DisplayCommand::SolidColor(
+        colors.right.clone(),
+        Rect {
+            x: border_box.x + border_box.width - d.border.right,
+            y: border_box.y,
+            width: d.border.right,
+            height: border_box.height,
+        },
+    ));
+
+    // Top border
+    list.push(DisplayCommand::SolidColor(
+        colors.top.clone(),
+        Rect {
+            x: border_box.x,
+            y: border_box.y,
+            width: border_box.width,
+            height: d.border.top,
+        },
+    ));
+
+    // Bottom border
+    list.push(DisplayCommand::SolidColor(
+        colors.bottom.clone(),
+        Rect {
+            x: border_box.x,
+            y: border_box.y + border_box.height - d.border.bottom,
+            width: border_box.width,
+            height: d.border.bottom,
+        },
+    ));
+}
+
+fn render_text(list: &mut DisplayList, layout_box: &LayoutBox, style: &ComputedStyle) {
+    for run in &layout_box.text_runs {
+        list.push(DisplayCommand::Text {
+            color: style.color.clone(),
+            run: run.clone(),
+            font_size: layout_box.font_size,
+            bold: style.font_weight >= 600,
+            italic: style.font_style != FontStyle::Normal,
+        });
+    }
+}
+
+#[cfg(test)]
+fn black() -> Color {
+    Color::from(0, 0, 0, 255)
+}
+
+impl Canvas {
+    /// Create a blank canvas
+    pub fn new(width: usize, height: usize) -> Canvas {
+        let white = Color::from(255, 255, 255, 255);
+        Canvas {
+            pixels: vec![white; width * height],
+            width,
+            height,
+        }
+    }
+
+    fn paint_item(&mut self, item: &DisplayCommand) {
+        match item {
+            DisplayCommand::SolidColor(color, rect) => self.fill_rect(color, *rect),
+            DisplayCommand::Text {
+                color,
+                run,
+                font_size,
+                bold,
+                italic,
+            } => self.draw_text(color, run, *font_size, *bold, *italic),
+        }
+    }
+
+    // Fill the pixels covered by `rect`, clipped to the canvas.
+    fn fill_rect(&mut self, color: &Color, rect: Rect) {
+        if color.a == 0 {
+            return;
+        }
+        let x0 = rect.x.round().max(0.0).min(self.width as f32) as usize;
+        let y0 = rect.y.round().max(0.0).min(self.height as f32) as usize;
+        let x1 = (rect.x + rect.width)
+            .round()
+            .max(0.0)
+            .min(self.width as f32) as usize;
+        let y1 = (rect.y + rect.height)
+            .round()
+            .max(0.0)
+            .min(self.height as f32) as usize;
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let pixel = &mut self.pixels[y * self.width + x];
+                *pixel = blend(color, pixel);
+            }
+        }
+    }
+
+    /// Draw every character of the run with the built-in bitmap font,
+    /// each glyph dot is scaled up to a square of `font_size / CELL_HEIGHT` px.
+    ///
+    /// Bold text is drawn twice one dot apart, italic text leans
+    /// by shifting the rows above the baseline to the right.
+    fn draw_text(
+        &mut self,
+        color: &Color,
+        run: &TextRun,
+        font_size: f32,
+        bold: bool,
+        italic: bool,
+    ) {
+        let scale = font_size / font::CELL_HEIGHT as f32;
+        let advance = font::char_width(font_size);
+        // center the glyph cell vertically in the line
+        let top = run.rect.y + (run.rect.height - font_size) / 2.0 + scale;
+
+        for (i, c) in run.text.chars().enumerate() {
+            let left = run.rect.x + i as f32 * advance;
+            for (column, bits) in font::glyph(c).iter().enumerate() {
+                for row in 0..8 {
+                    if bits & (1 << row) == 0 {
+                        continue;
+                    }
+                    let slant = if italic {
+                        (6 - row) as f32 * scale / 4.0
+                    } else {
+                        0.0
+                    };
+                    self.fill_rect(
+                        color,
+                        Rect {
+                            x: left + column as f32 * scale + slant,
+                            y: top + row as f32 * scale,
+                            width: if bold { scale * 2.0 } else { scale },
+                            height: scale,
+                        },
+                    );
+                }
+            }
+        }
+    }
+
+    /// The pixels as a flat array of RGBA bytes
+    pub fn to_rgba(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.pixels.len() * 4);
+        for pixel in &self.pixels {
+            bytes.extend_from_slice(&[pixel.r, pixel.g, pixel.b, pixel.a]);
+        }
+        bytes
+    }
+
+    /// Encode the canvas as a PNG image and write it to `path`
+    pub fn write_png(&self, path: &str) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = png::Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.to_rgba())?;
+        Ok(())
+    }
+}
+
+// Draw `source` over `backdrop` with alpha compositing.
+fn blend(source: &Color, backdrop: &Color) -> Color {
+    if source.a == 255 {
+        return source.clone();
+    }
+    let alpha = source.a as f32 / 255.0;
+    let backdrop_alpha = backdrop.a as f32 / 255.0 * (1.0 - alpha);
+    let out_alpha = alpha + backdrop_alpha;
+    let channel =
+        |s: u8, b: u8| ((s as f32 * alpha + b as f32 * backdrop_alpha) / out_alpha).round() as u8;
+    Color::from(
+        channel(source.r, backdrop.r),
+        channel(source.g, backdrop.g),
+        channel(source.b, backdrop.b),
+        (out_alpha * 255.0).round() as u8,
+    )
+}
+
+#[test]
+fn test_blend() {
+    let white = Color::from(255, 255, 255, 255);
+    assert_eq!(blend(&black(), &white), black());
+    assert_eq!(
+        blend(&Color::from(255, 0, 0, 128), &white),
+        Color::from(255, 127, 127, 255)
+    );
+}
+
+#[test]
+fn test_paint() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\"><span class=\"b\">Hi</span></div>".to_string(),
+    );
+    let stylesheets = [super::parser::css::parse(
+        ".a { display: block; padding: 10px; border-width: 2px; border-style: solid; border-color: #0000ff; background-color: #ff0000; } .b { color: #00ff00; }"
+            .to_string(), super::parser::css::Origin::Author,
+    )];
+    let style = super::style::style_tree(&dom, &stylesheets, super::style::StyleOptions::default());
+    let mut viewport: super::layout::Dimensions = Default::default();
+    viewport.content.width = 100.0;
+    let root = super::layout::layout_tree(&style, viewport);
+    let canvas = paint(
+        &root,
+        Rect {
+            x: 0.0,
+            y: 0.0,
+            width: 100.0,
+            height: 50.0,
+        },
+    );
+
+    let pixel = |x: usize, y: usize| canvas.pixels[y * canvas.width + x].clone();
+    assert_eq!(pixel(0, 0), Color::from(0, 0, 255, 255));
+    assert_eq!(pixel(5, 5), Color::from(255, 0, 0, 255));
+    assert_eq!(pixel(99, 49), Color::from(255, 255, 255, 255));
+    // some of the text is drawn in green
+    let green = Color::from(0, 255, 0, 255);
+    assert!(canvas.pixels.contains(&green));
+    assert_eq!(canvas.to_rgba().len(), 100 * 50 * 4);
+}
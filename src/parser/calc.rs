@@ -0,0 +1,378 @@
+/// Math expressions
+///
+/// `calc(100% - 2em)`, `min(10px, 5vw)`, `max(...)` and
+/// `clamp(1rem, 2.5vw, 2rem)` compute a length or a number from others in
+/// any unit. The expression is parsed into a tree and simplified once the
+/// font size and viewport are known, which leaves percentages of the
+/// containing block for layout to resolve.
+///
+/// Only numbers can multiply or divide, `2em * 3px` is invalid.
+use super::css::Unit;
+use super::css_tokenizer::{Token, Tokenizer};
+use super::length;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Calc {
+    Number(f32),
+    /// A length or percentage
+    Length(f32, Unit),
+    /// Terms added together, `a - b` is `a + -1 * b`
+    Sum(Vec<Calc>),
+    Product(Box<Calc>, Box<Calc>),
+    /// `a / b`, `b` is a number
+    Quotient(Box<Calc>, Box<Calc>),
+    Min(Vec<Calc>),
+    Max(Vec<Calc>),
+    /// `clamp(min, value, max)`
+    Clamp(Box<Calc>, Box<Calc>, Box<Calc>),
+}
+
+impl Calc {
+    /// Convert the lengths to px with `to_px` and fold what can be, a
+    /// length `to_px` returns `None` for is kept as it is. `None` on a
+    /// division by zero.
+    ///
+    /// When `to_px` converts every length the result is a single number or
+    /// px length.
+    pub fn simplify(&self, to_px: &dyn Fn(f32, Unit) -> Option<f32>) -> Option<Calc> {
+        Some(match self {
+            Calc::Number(number) => Calc::Number(*number),
+            Calc::Length(value, unit) => match to_px(*value, *unit) {
+                Some(px) => Calc::Length(px, Unit::Px),
+                None => Calc::Length(*value, *unit),
+            },
+            Calc::Sum(terms) => add(simplify_all(terms, to_px)?),
+            Calc::Product(a, b) => match (a.simplify(to_px)?, b.simplify(to_px)?) {
+                (Calc::Number(factor), calc) | (calc, Calc::Number(factor)) => scale(calc, factor),
+                (a, b) => Calc::Product(Box::new(a), Box::new(b)),
+            },
+            Calc::Quotient(a, b) => match b.simplify(to_px)? {
+                Calc::Number(divisor) if divisor != 0.0 => scale(a.simplify(to_px)?, 1.0 / divisor),
+                _ => return None,
+            },
+            Calc::Min(arguments) => {
+                let arguments = simplify_all(arguments, to_px)?;
+                fold(&arguments, |values| {
+                    values.iter().copied().fold(f32::INFINITY, f32::min)
+                })
+                .unwrap_or(Calc::Min(arguments))
+            }
+            Calc::Max(arguments) => {
+                let arguments = simplify_all(arguments, to_px)?;
+                fold(&arguments, |values| {
+                    values.iter().copied().fold(f32::NEG_INFINITY, f32::max)
+                })
+                .unwrap_or(Calc::Max(arguments))
+            }
+            Calc::Clamp(min, value, max) => {
+                let arguments = [
+                    min.simplify(to_px)?,
+                    value.simplify(to_px)?,
+                    max.simplify(to_px)?,
+                ];
+                // the minimum wins over the maximum
+                fold(&arguments, |values| values[1].min(values[2]).max(values[0])).unwrap_or_else(
+                    || {
+                        let [min, value, max] = arguments;
+                        Calc::Clamp(Box::new(min), Box::new(value), Box::new(max))
+                    },
+                )
+            }
+        })
+    }
+
+    // Whether the expression is a number or a length, `None` when it mixes
+    // both, like `1px + 2`
+    /// If the expression is a length or percentage rather than a number
+    pub fn is_length(&self) -> bool {
+        self.kind() == Some(Kind::Length)
+    }
+
+    fn kind(&self) -> Option<Kind> {
+        match self {
+            Calc::Number(_) => Some(Kind::Number),
+            Calc::Length(..) => Some(Kind::Length),
+            Calc::Product(a, b) => match (a.kind()?, b.kind()?) {
+                (Kind::Number, kind) | (kind, Kind::Number) => Some(kind),
+                _ => None,
+            },
+            Calc::Quotient(a, b) => match b.kind()? {
+                Kind::Number => a.kind(),
+                Kind::Length => None,
+            },
+            Calc::Sum(arguments) | Calc::Min(arguments) | Calc::Max(arguments) => {
+                same_kind(arguments.iter())
+            }
+            Calc::Clamp(min, value, max) => same_kind([&**min, &**value, &**max].iter().copied()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Number,
+    Length,
+}
+
+fn same_kind<'a>(mut arguments: impl Iterator<Item = &'a Calc>) -> Option<Kind> {
+    let kind = arguments.next()?.kind()?;
+    arguments
+        .try_for_each(|argument| (argument.kind()? == kind).then_some(()))
+        .map(|_| kind)
+}
+
+fn simplify_all(arguments: &[Calc], to_px: &dyn Fn(f32, Unit) -> Option<f32>) -> Option<Vec<Calc>> {
+    arguments
+        .iter()
+        .map(|argument| argument.simplify(to_px))
+        .collect()
+}
+
+// Add up the numbers, the px and the percentages of simplified terms
+fn add(terms: Vec<Calc>) -> Calc {
+    let (mut number, mut px, mut percent) = (None, None, None);
+    let mut others = Vec::new();
+    let mut pending = terms;
+    while let Some(term) = pending.pop() {
+        match term {
+            Calc::Number(value) => *number.get_or_insert(0.0) += value,
+            Calc::Length(value, Unit::Px) => *px.get_or_insert(0.0) += value,
+            Calc::Length(value, Unit::Percent) => *percent.get_or_insert(0.0) += value,
+            Calc::Sum(terms) => pending.extend(terms),
+            term => others.push(term),
+        }
+    }
+    let mut terms: Vec<Calc> = number
+        .map(Calc::Number)
+        .into_iter()
+        .chain(px.map(|px| Calc::Length(px, Unit::Px)))
+        .chain(percent.map(|percent| Calc::Length(percent, Unit::Percent)))
+        .chain(others.into_iter().rev())
+        .collect();
+    match terms.len() {
+        1 => terms.remove(0),
+        _ => Calc::Sum(terms),
+    }
+}
+
+// Multiply a simplified expression by a number
+fn scale(calc: Calc, factor: f32) -> Calc {
+    match calc {
+        Calc::Number(value) => Calc::Number(value * factor),
+        Calc::Length(value, unit) => Calc::Length(value * factor, unit),
+        Calc::Sum(terms) => Calc::Sum(terms.into_iter().map(|term| scale(term, factor)).collect()),
+        calc => Calc::Product(Box::new(Calc::Number(factor)), Box::new(calc)),
+    }
+}
+
+// Apply `f` to the values of simplified arguments that are all numbers or
+// all lengths of the same unit, `None` when they are not
+fn fold(arguments: &[Calc], f: impl Fn(&[f32]) -> f32) -> Option<Calc> {
+    let plain = |calc: &Calc| match *calc {
+        Calc::Number(value) => Some((value, None)),
+        Calc::Length(value, unit) => Some((value, Some(unit))),
+        _ => None,
+    };
+    let plain: Vec<(f32, Option<Unit>)> = arguments.iter().map(plain).collect::<Option<_>>()?;
+    let unit = plain.first()?.1;
+    if plain.iter().any(|(_, other)| *other != unit) {
+        return None;
+    }
+    let values: Vec<f32> = plain.iter().map(|(value, _)| *value).collect();
+    Some(match unit {
+        Some(unit) => Calc::Length(f(&values), unit),
+        None => Calc::Number(f(&values)),
+    })
+}
+
+/// Parse a value that is a single math function, like `calc(100% - 2em)`.
+/// `None` for other values and invalid expressions.
+pub fn parse_calc(text: &str) -> Option<Calc> {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token().1 {
+            Token::Eof => break,
+            Token::Whitespace => {}
+            token => tokens.push(token),
+        }
+    }
+    let mut tokens = tokens.as_slice();
+    let calc = match tokens.split_first()? {
+        (Token::Function(name), rest) => {
+            tokens = rest;
+            parse_function(name, &mut tokens)?
+        }
+        _ => return None,
+    };
+    if !tokens.is_empty() {
+        return None;
+    }
+    calc.kind().map(|_| calc)
+}
+
+// The arguments of a math function up to its `)`
+fn parse_function(name: &str, tokens: &mut &[Token]) -> Option<Calc> {
+    let mut arguments = vec![parse_sum(tokens)?];
+    while tokens.first() == Some(&Token::Comma) {
+        next(tokens);
+        arguments.push(parse_sum(tokens)?);
+    }
+    if next(tokens) != Some(&Token::CloseParen) {
+        return None;
+    }
+    match name.to_ascii_lowercase().as_str() {
+        "calc" if arguments.len() == 1 => arguments.pop(),
+        "min" => Some(Calc::Min(arguments)),
+        "max" => Some(Calc::Max(arguments)),
+        "clamp" if arguments.len() == 3 => {
+            let mut arguments = arguments.into_iter().map(Box::new);
+            Some(Calc::Clamp(
+                arguments.next()?,
+                arguments.next()?,
+                arguments.next()?,
+            ))
+        }
+        _ => None,
+    }
+}
+
+// `product [(+|-) product]*`, `+` and `-` need whitespace around them which
+// the tokenizer takes care of, `1px -2px` is two lengths
+fn parse_sum(tokens: &mut &[Token]) -> Option<Calc> {
+    let mut terms = vec![parse_product(tokens)?];
+    loop {
+        let negate = match tokens.first() {
+            Some(Token::Delim('+')) => false,
+            Some(Token::Delim('-')) => true,
+            _ => break,
+        };
+        next(tokens);
+        let term = parse_product(tokens)?;
+        terms.push(match negate {
+            true => Calc::Product(Box::new(Calc::Number(-1.0)), Box::new(term)),
+            false => term,
+        });
+    }
+    Some(match terms.len() {
+        1 => terms.remove(0),
+        _ => Calc::Sum(terms),
+    })
+}
+
+// `value [(*|/) value]*`
+fn parse_product(tokens: &mut &[Token]) -> Option<Calc> {
+    let mut calc = parse_value(tokens)?;
+    loop {
+        calc = match tokens.first() {
+            Some(Token::Delim('*')) => {
+                next(tokens);
+                Calc::Product(Box::new(calc), Box::new(parse_value(tokens)?))
+            }
+            Some(Token::Delim('/')) => {
+                next(tokens);
+                Calc::Quotient(Box::new(calc), Box::new(parse_value(tokens)?))
+            }
+            _ => return Some(calc),
+        };
+    }
+}
+
+// A number, a length, a nested math function or `(sum)`
+fn parse_value(tokens: &mut &[Token]) -> Option<Calc> {
+    match next(tokens)? {
+        Token::Number(number) => length::parse_number(number).map(Calc::Number),
+        Token::Percentage(number) => {
+            length::parse_number(number).map(|value| Calc::Length(value, Unit::Percent))
+        }
+        Token::Dimension(number, unit) => {
+            let (value, unit) = length::parse_length(&format!("{}{}", number, unit))?;
+            Some(Calc::Length(value, unit))
+        }
+        Token::Function(name) => parse_function(name, tokens),
+        Token::OpenParen => {
+            let calc = parse_sum(tokens)?;
+            match next(tokens)? {
+                Token::CloseParen => Some(calc),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+fn next<'t>(tokens: &mut &'t [Token]) -> Option<&'t Token> {
+    let (token, rest) = tokens.split_first()?;
+    *tokens = rest;
+    Some(token)
+}
+
+#[test]
+fn test_parse_calc() {
+    let px = |text: &str| {
+        let to_px = |value: f32, unit: Unit| match unit {
+            Unit::Px => Some(value),
+            Unit::Em => Some(value * 10.0),
+            _ => None,
+        };
+        parse_calc(text).and_then(|calc| calc.simplify(&to_px))
+    };
+
+    assert_eq!(
+        px("calc(1px + 2em * 3)"),
+        Some(Calc::Length(61.0, Unit::Px))
+    );
+    assert_eq!(
+        px("CALC( (1px + 1px) / 4 - 1em)"),
+        Some(Calc::Length(-9.5, Unit::Px))
+    );
+    assert_eq!(px("calc(2 * 3 / 4)"), Some(Calc::Number(1.5)));
+    assert_eq!(
+        px("min(10px, 2em, 15px)"),
+        Some(Calc::Length(10.0, Unit::Px))
+    );
+    assert_eq!(
+        px("max(1px, calc(1em - 1px))"),
+        Some(Calc::Length(9.0, Unit::Px))
+    );
+    assert_eq!(
+        px("clamp(5px, 1px, 10px)"),
+        Some(Calc::Length(5.0, Unit::Px))
+    );
+    assert_eq!(
+        px("clamp(20px, 1px, 10px)"),
+        Some(Calc::Length(20.0, Unit::Px))
+    );
+    assert_eq!(px("calc(1px / 0)"), None);
+
+    // percentages are kept for layout, the other lengths folded around them
+    assert_eq!(
+        px("calc(100% - 2em + 5px)"),
+        Some(Calc::Sum(vec![
+            Calc::Length(-15.0, Unit::Px),
+            Calc::Length(100.0, Unit::Percent)
+        ]))
+    );
+    let min = px("min(50%, 2em * 2)").unwrap();
+    assert_eq!(
+        min,
+        Calc::Min(vec![
+            Calc::Length(50.0, Unit::Percent),
+            Calc::Length(40.0, Unit::Px)
+        ])
+    );
+    let in_layout = |value: f32, unit: Unit| match unit {
+        Unit::Percent => Some(value * 0.5),
+        _ => Some(value),
+    };
+    assert_eq!(min.simplify(&in_layout), Some(Calc::Length(25.0, Unit::Px)));
+
+    assert_eq!(parse_calc("calc(1px + 2)"), None);
+    assert_eq!(parse_calc("calc(2px * 3px)"), None);
+    assert_eq!(parse_calc("calc(1px / 1px)"), None);
+    assert_eq!(parse_calc("calc(1px 2px)"), None);
+    assert_eq!(parse_calc("calc(1px + 2px) 3px"), None);
+    assert_eq!(parse_calc("calc(1px, 2px)"), None);
+    assert_eq!(parse_calc("clamp(1px, 2px)"), None);
+    assert_eq!(parse_calc("10px"), None);
+}
@@ -1,27 +1,30 @@
 use super::css::Color;
+use super::length;
 use crate::{errors, Result};
+use std::f32::consts::PI;
 
 /// Parsers color value to rgba value
-/// 
-/// Assumes input string will be trimmed and converted to lowercase
+///
+/// Assumes input string will be trimmed and converted to lowercase.
+/// `currentcolor` is not parsed, it stands for the `color` of the element
+/// and is resolved with the computed values.
 pub fn parse_color(color_string: &str) -> Result<Color> {
-    let color = color_string.replace(' ', "");
+    parse_rgba(color_string.trim()).map(Rgba::to_color)
+}
+
+fn parse_rgba(color: &str) -> Result<Rgba> {
     if color.is_empty() {
         return errors::parse_error("invalid color");
     }
 
-    if color.starts_with("#") {
-        return parse_hex(color.as_str());
-    }
-    if color.starts_with("rgba") {
-        return parse_rgba(color.as_str());
+    if color.starts_with('#') {
+        return parse_hex(color).map(|color| Rgba::from_color(&color));
     }
-    if color.starts_with("rgb") {
-        return parse_rgb(color.as_str());
+    if color.ends_with(')') {
+        return parse_function(color);
     }
-    // TODO: parse hsl & hsla
 
-    return parse_color_name(color.as_str());
+    parse_color_name(color).map(|color| Rgba::from_color(&color))
 }
 
 #[test]
@@ -52,7 +55,7 @@ fn parse_hex(hex_string: &str) -> Result<Color> {
     let len = hex_string.len();
     if len == 4 {
         let iv = u64::from_str_radix(&hex_string[1..], 16)?;
-        if !(iv <= 0xfff) {
+        if iv > 0xfff {
             return errors::parse_error("invalid hex color");
         }
 
@@ -66,7 +69,7 @@ fn parse_hex(hex_string: &str) -> Result<Color> {
 
     if len >= 7 {
         let iv = u64::from_str_radix(&hex_string[1..7], 16)?;
-        if !(iv <= 0xffffff) {
+        if iv > 0xffffff {
             return errors::parse_error("invalid hex color");
         }
         let mut color = Color {
@@ -82,7 +85,7 @@ fn parse_hex(hex_string: &str) -> Result<Color> {
         }
         return Ok(color);
     }
-    return errors::parse_error("invalid hex color");
+    errors::parse_error("invalid hex color")
 }
 
 #[test]
@@ -273,54 +276,446 @@ fn test_parse_color_name() {
     );
 }
 
-fn parse_rgb(color: &str) -> Result<Color> {
-    let le = color.len() - 1;
-    let ss: Vec<&str> = color[4..le].split(",").collect();
-    if ss.len() != 3 {
-        return errors::parse_error("invalid rgb value");
+// A color in sRGB, the channels and alpha go from 0 to 1. Colors from
+// other spaces may be out of gamut, they are clamped in `to_color`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Rgba {
+    rgb: [f32; 3],
+    alpha: f32,
+}
+
+impl Rgba {
+    fn from_color(color: &Color) -> Rgba {
+        let channel = |value: u8| value as f32 / 255.0;
+        Rgba {
+            rgb: [channel(color.r), channel(color.g), channel(color.b)],
+            alpha: channel(color.a),
+        }
     }
-    Ok(Color::from(
-        ss[0].parse::<u8>()?,
-        ss[1].parse::<u8>()?,
-        ss[2].parse::<u8>()?,
-        255,
-    ))
+
+    fn to_color(self) -> Color {
+        let channel = |value: f32| (value.clamp(0.0, 1.0) * 255.0).round() as u8;
+        Color::from(
+            channel(self.rgb[0]),
+            channel(self.rgb[1]),
+            channel(self.rgb[2]),
+            (self.alpha.clamp(0.0, 1.0) * 255.0) as u8,
+        )
+    }
+}
+
+// `rgb(...)`, `hsl(...)`, `color-mix(...)` and the other functions
+fn parse_function(color: &str) -> Result<Rgba> {
+    let open = match color.find('(') {
+        Some(open) => open,
+        None => return errors::parse_error("invalid color"),
+    };
+    let name = color[..open].trim();
+    let args = color[open + 1..color.len() - 1].trim();
+    if name == "color-mix" {
+        return parse_color_mix(args);
+    }
+
+    let (mut channels, alpha) = split_channels(args)?;
+    // `color(srgb 1 0 0)` starts with the color space
+    let space = if name == "color" {
+        if channels.is_empty() {
+            return errors::parse_error("missing color space");
+        }
+        channels.remove(0)
+    } else {
+        name
+    };
+    if channels.len() != 3 {
+        return errors::parse_error("invalid number of color channels");
+    }
+    let (c0, c1, c2) = (channels[0], channels[1], channels[2]);
+
+    let rgb = match space {
+        "rgb" | "rgba" => [
+            number(c0, 255.0)? / 255.0,
+            number(c1, 255.0)? / 255.0,
+            number(c2, 255.0)? / 255.0,
+        ],
+        "hsl" | "hsla" => hsl_to_rgb(
+            hue(c0)?,
+            number(c1, 100.0)? / 100.0,
+            number(c2, 100.0)? / 100.0,
+        ),
+        "hwb" => hwb_to_rgb(
+            hue(c0)?,
+            number(c1, 100.0)? / 100.0,
+            number(c2, 100.0)? / 100.0,
+        ),
+        "lab" => lab_to_rgb([number(c0, 100.0)?, number(c1, 125.0)?, number(c2, 125.0)?]),
+        "lch" => lab_to_rgb(lch_to_lab(number(c0, 100.0)?, number(c1, 150.0)?, hue(c2)?)),
+        "oklab" => oklab_to_rgb([number(c0, 1.0)?, number(c1, 0.4)?, number(c2, 0.4)?]),
+        "oklch" => oklab_to_rgb(lch_to_lab(number(c0, 1.0)?, number(c1, 0.4)?, hue(c2)?)),
+        "srgb" => [number(c0, 1.0)?, number(c1, 1.0)?, number(c2, 1.0)?],
+        "srgb-linear" => [number(c0, 1.0)?, number(c1, 1.0)?, number(c2, 1.0)?].map(gamma),
+        _ => return errors::parse_error("unknown color function"),
+    };
+    let alpha = match alpha {
+        Some(alpha) => number(alpha, 1.0)?.clamp(0.0, 1.0),
+        None => 1.0,
+    };
+    Ok(Rgba { rgb, alpha })
+}
+
+// The channels and alpha of `1, 2, 3, 0.5` or `1 2 3 / 50%`
+fn split_channels(args: &str) -> Result<(Vec<&str>, Option<&str>)> {
+    if args.contains(',') {
+        let mut channels: Vec<&str> = args.split(',').map(str::trim).collect();
+        let alpha = if channels.len() == 4 {
+            channels.pop()
+        } else {
+            None
+        };
+        return Ok((channels, alpha));
+    }
+    let (channels, alpha) = match args.split_once('/') {
+        Some((channels, alpha)) => (channels, Some(alpha.trim())),
+        None => (args, None),
+    };
+    Ok((channels.split_whitespace().collect(), alpha))
+}
+
+// A number, or a percentage where 100% is `percent`. `none` is zero.
+fn number(text: &str, percent: f32) -> Result<f32> {
+    if text == "none" {
+        return Ok(0.0);
+    }
+    let value = match text.strip_suffix('%') {
+        Some(text) => length::parse_number(text).map(|value| value * percent / 100.0),
+        None => length::parse_number(text),
+    };
+    match value {
+        Some(value) => Ok(value),
+        None => errors::parse_error("invalid color channel"),
+    }
+}
+
+// An angle in degrees, a number without a unit is in degrees as well
+fn hue(text: &str) -> Result<f32> {
+    // `grad` before `rad`, which it ends with
+    let units = [
+        ("deg", 1.0),
+        ("grad", 0.9),
+        ("rad", 180.0 / PI),
+        ("turn", 360.0),
+    ];
+    for (unit, degrees) in units.iter() {
+        if let Some(angle) = text.strip_suffix(unit) {
+            if let Some(angle) = length::parse_number(angle) {
+                return Ok(angle * degrees);
+            }
+        }
+    }
+    match text {
+        "none" => Ok(0.0),
+        _ => match length::parse_number(text) {
+            Some(angle) => Ok(angle),
+            None => errors::parse_error("invalid hue"),
+        },
+    }
+}
+
+// `color-mix(in oklab, red 40%, blue)`, mixing in srgb, srgb-linear or oklab
+fn parse_color_mix(args: &str) -> Result<Rgba> {
+    let parts = split_arguments(args);
+    let (space, first, second) = match parts.as_slice() {
+        [space, first, second] => (space, first, second),
+        _ => return errors::parse_error("invalid color-mix()"),
+    };
+    let space = match space.strip_prefix("in ").map(str::trim) {
+        Some(space @ "srgb") | Some(space @ "srgb-linear") | Some(space @ "oklab") => space,
+        _ => return errors::parse_error("unsupported color-mix() color space"),
+    };
+    let (first, p1) = mix_component(first)?;
+    let (second, p2) = mix_component(second)?;
+
+    // the percentages are scaled to add up to 100%, when they added up to
+    // less the result is that much transparent
+    let (p1, p2) = match (p1, p2) {
+        (None, None) => (0.5, 0.5),
+        (Some(p1), None) => (p1, 1.0 - p1),
+        (None, Some(p2)) => (1.0 - p2, p2),
+        (Some(p1), Some(p2)) => (p1, p2),
+    };
+    let sum = p1 + p2;
+    if p1 < 0.0 || p2 < 0.0 || sum <= 0.0 {
+        return errors::parse_error("invalid color-mix() percentages");
+    }
+    let (p1, p2) = (p1 / sum, p2 / sum);
+
+    // interpolate with premultiplied alpha
+    let to_space = |color: Rgba| match space {
+        "srgb" => color.rgb,
+        "srgb-linear" => color.rgb.map(linear),
+        _ => rgb_to_oklab(color.rgb),
+    };
+    let (c1, c2) = (to_space(first), to_space(second));
+    let alpha = first.alpha * p1 + second.alpha * p2;
+    let mut mixed = [0.0; 3];
+    for (i, channel) in mixed.iter_mut().enumerate() {
+        let premultiplied = c1[i] * first.alpha * p1 + c2[i] * second.alpha * p2;
+        *channel = if alpha > 0.0 {
+            premultiplied / alpha
+        } else {
+            0.0
+        };
+    }
+    let rgb = match space {
+        "srgb" => mixed,
+        "srgb-linear" => mixed.map(gamma),
+        _ => oklab_to_rgb(mixed),
+    };
+    Ok(Rgba {
+        rgb,
+        alpha: alpha * sum.min(1.0),
+    })
+}
+
+// A color of `color-mix()` with its percentage, before or after it
+fn mix_component(text: &str) -> Result<(Rgba, Option<f32>)> {
+    let percentage = |text: &str| match text.strip_suffix('%') {
+        Some(percent) => length::parse_number(percent).map(|percent| percent / 100.0),
+        None => None,
+    };
+    if let Some((color, percent)) = text.rsplit_once(char::is_whitespace) {
+        if let Some(percent) = percentage(percent) {
+            return Ok((parse_rgba(color.trim())?, Some(percent)));
+        }
+    }
+    if let Some((percent, color)) = text.split_once(char::is_whitespace) {
+        if let Some(percent) = percentage(percent) {
+            return Ok((parse_rgba(color.trim())?, Some(percent)));
+        }
+    }
+    Ok((parse_rgba(text)?, None))
+}
+
+// Split on the commas outside of parentheses
+fn split_arguments(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in args.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+    parts
+}
+
+// The conversions follow CSS Color 4, sRGB is relative to the D65 white
+// point
+
+fn hsl_to_rgb(hue: f32, saturation: f32, lightness: f32) -> [f32; 3] {
+    let a = saturation * lightness.min(1.0 - lightness);
+    let channel = |n: f32| {
+        let k = (n + hue / 30.0).rem_euclid(12.0);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+    [channel(0.0), channel(8.0), channel(4.0)]
+}
+
+fn hwb_to_rgb(hue: f32, whiteness: f32, blackness: f32) -> [f32; 3] {
+    if whiteness + blackness >= 1.0 {
+        let gray = whiteness / (whiteness + blackness);
+        return [gray; 3];
+    }
+    hsl_to_rgb(hue, 1.0, 0.5).map(|channel| channel * (1.0 - whiteness - blackness) + whiteness)
+}
+
+// Lightness, chroma and hue to lightness, a and b
+fn lch_to_lab(lightness: f32, chroma: f32, hue: f32) -> [f32; 3] {
+    let hue = hue.to_radians();
+    [lightness, chroma * hue.cos(), chroma * hue.sin()]
+}
+
+// CIE Lab is relative to the D50 white point
+fn lab_to_rgb([lightness, a, b]: [f32; 3]) -> [f32; 3] {
+    const KAPPA: f32 = 24389.0 / 27.0;
+    const EPSILON: f32 = 216.0 / 24389.0;
+    const D50: [f32; 3] = [0.3457 / 0.3585, 1.0, (1.0 - 0.3457 - 0.3585) / 0.3585];
+    const D50_TO_D65: [[f32; 3]; 3] = [
+        [0.955_473_4, -0.023_098_455, 0.063_259_24],
+        [-0.028_369_71, 1.009_995_4, 0.021_041_441],
+        [0.012_314_015, -0.020_507_65, 1.330_365_9],
+    ];
+    const XYZ_TO_LINEAR_RGB: [[f32; 3]; 3] = [
+        [3.240_97, -1.537_383_2, -0.498_610_76],
+        [-0.969_243_65, 1.875_967_5, 0.041_555_06],
+        [0.055_630_08, -0.203_976_96, 1.056_971_5],
+    ];
+
+    let f1 = (lightness + 16.0) / 116.0;
+    let f0 = a / 500.0 + f1;
+    let f2 = f1 - b / 200.0;
+    let inverse = |f: f32| {
+        if f.powi(3) > EPSILON {
+            f.powi(3)
+        } else {
+            (116.0 * f - 16.0) / KAPPA
+        }
+    };
+    let y = if lightness > KAPPA * EPSILON {
+        f1.powi(3)
+    } else {
+        lightness / KAPPA
+    };
+    let xyz = [inverse(f0) * D50[0], y * D50[1], inverse(f2) * D50[2]];
+    multiply(&XYZ_TO_LINEAR_RGB, multiply(&D50_TO_D65, xyz)).map(gamma)
+}
+
+fn oklab_to_rgb(lab: [f32; 3]) -> [f32; 3] {
+    const OKLAB_TO_LMS: [[f32; 3]; 3] = [
+        [1.0, 0.396_337_78, 0.215_803_76],
+        [1.0, -0.105_561_346, -0.063_854_17],
+        [1.0, -0.089_484_18, -1.291_485_5],
+    ];
+    const LMS_TO_LINEAR_RGB: [[f32; 3]; 3] = [
+        [4.076_741_7, -3.307_711_6, 0.230_969_94],
+        [-1.268_438, 2.609_757_4, -0.341_319_38],
+        [-0.004_196_086_3, -0.703_418_6, 1.707_614_7],
+    ];
+    let lms = multiply(&OKLAB_TO_LMS, lab).map(|value| value.powi(3));
+    multiply(&LMS_TO_LINEAR_RGB, lms).map(gamma)
+}
+
+fn rgb_to_oklab(rgb: [f32; 3]) -> [f32; 3] {
+    const LINEAR_RGB_TO_LMS: [[f32; 3]; 3] = [
+        [0.412_221_46, 0.536_332_55, 0.051_445_995],
+        [0.211_903_5, 0.680_699_5, 0.107_396_96],
+        [0.088_302_46, 0.281_718_85, 0.629_978_7],
+    ];
+    const LMS_TO_OKLAB: [[f32; 3]; 3] = [
+        [0.210_454_26, 0.793_617_8, -0.004_072_047],
+        [1.977_998_5, -2.428_592_2, 0.450_593_7],
+        [0.025_904_037, 0.782_771_77, -0.808_675_77],
+    ];
+    let lms = multiply(&LINEAR_RGB_TO_LMS, rgb.map(linear)).map(f32::cbrt);
+    multiply(&LMS_TO_OKLAB, lms)
+}
+
+fn multiply(matrix: &[[f32; 3]; 3], vector: [f32; 3]) -> [f32; 3] {
+    matrix.map(|row| row[0] * vector[0] + row[1] * vector[1] + row[2] * vector[2])
+}
+
+// The sRGB transfer function, from linear light to the encoded channel
+fn gamma(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let encoded = if magnitude > 0.003_130_8 {
+        1.055 * magnitude.powf(1.0 / 2.4) - 0.055
+    } else {
+        12.92 * magnitude
+    };
+    encoded.copysign(value)
+}
+
+// The inverse of `gamma`
+fn linear(value: f32) -> f32 {
+    let magnitude = value.abs();
+    let decoded = if magnitude > 0.040_45 {
+        ((magnitude + 0.055) / 1.055).powf(2.4)
+    } else {
+        magnitude / 12.92
+    };
+    decoded.copysign(value)
 }
 
 #[test]
 fn test_parse_rgb() {
     assert_eq!(
-        parse_rgb("rgb(20,30,100)").unwrap(),
+        parse_color("rgb(20,30,100)").unwrap(),
         Color::from(20, 30, 100, 255)
     );
     assert_eq!(
-        parse_rgb("rgb(120,255,0)").unwrap(),
+        parse_color("rgb(120,255,0)").unwrap(),
         Color::from(120, 255, 0, 255)
     );
-}
-
-fn parse_rgba(color: &str) -> Result<Color> {
-    let le = color.len() - 1;
-    let ss: Vec<&str> = color[5..le].split(",").collect();
-    if ss.len() != 4 {
-        return errors::parse_error("invalid rgba value");
-    }
-    Ok(Color::from(
-        ss[0].parse::<u8>()?,
-        ss[1].parse::<u8>()?,
-        ss[2].parse::<u8>()?,
-        (ss[3].parse::<f32>()? * 255.0) as u8,
-    ))
+    assert_eq!(
+        parse_color("rgb(120 255 0 / 50%)").unwrap(),
+        Color::from(120, 255, 0, 127)
+    );
+    assert_eq!(
+        parse_color("rgb(100% 50% 0%)").unwrap(),
+        Color::from(255, 128, 0, 255)
+    );
+    assert_eq!(
+        parse_color("color(srgb 1 0.5 none / 0.5)").unwrap(),
+        Color::from(255, 128, 0, 127)
+    );
+    assert!(parse_color("rgb(1 2)").is_err());
+    assert!(parse_color("rgb(1 2 x)").is_err());
 }
 
 #[test]
 fn test_parse_rgba() {
     assert_eq!(
-        parse_rgba("rgba(20,30,100,0.1)").unwrap(),
+        parse_color("rgba(20,30,100,0.1)").unwrap(),
         Color::from(20, 30, 100, 25)
     );
     assert_eq!(
-        parse_rgba("rgba(120,255,0,0.5)").unwrap(),
+        parse_color("rgba(120,255,0,0.5)").unwrap(),
         Color::from(120, 255, 0, 127)
     );
 }
+
+#[test]
+fn test_parse_color_functions() {
+    // within one step of each channel of the reference
+    let close = |color: &str, r: u8, g: u8, b: u8, a: u8| {
+        let parsed = parse_color(color).unwrap();
+        let near = |x: u8, y: u8| (x as i16 - y as i16).abs() <= 1;
+        assert!(
+            near(parsed.r, r) && near(parsed.g, g) && near(parsed.b, b) && parsed.a == a,
+            "{} is {:?}",
+            color,
+            parsed
+        );
+    };
+    close("hsl(120, 100%, 25%)", 0, 128, 0, 255);
+    close("hsla(240 100% 50% / 0.5)", 0, 0, 255, 127);
+    close("hsl(0.5turn 100% 50%)", 0, 255, 255, 255);
+    close("hwb(120 20% 30%)", 51, 179, 51, 255);
+    close("hwb(0 60% 60%)", 128, 128, 128, 255);
+    close("lab(54.29% 80.82 69.91)", 255, 0, 0, 255);
+    close("lab(100 0 0)", 255, 255, 255, 255);
+    close("lch(54.29 106.84 40.85deg)", 255, 0, 0, 255);
+    close("oklab(0.628 0.2249 0.1258)", 255, 0, 0, 255);
+    close("oklch(62.8% 0.2577 29.23)", 255, 0, 0, 255);
+    close("oklch(0.452 0.3132 264.05)", 0, 0, 255, 255);
+    close("color(srgb-linear 0.5 0 1)", 188, 0, 255, 255);
+
+    close("color-mix(in srgb, red, blue)", 128, 0, 128, 255);
+    close("color-mix(in srgb, red 25%, #00f 25%)", 128, 0, 128, 127);
+    close(
+        "color-mix(in srgb, 75% rgb(255 0 0 / 0), white)",
+        255,
+        255,
+        255,
+        63,
+    );
+    close("color-mix(in oklab, white, black)", 99, 99, 99, 255);
+    close(
+        "color-mix(in srgb-linear, white, black)",
+        188,
+        188,
+        188,
+        255,
+    );
+    assert!(parse_color("color-mix(in hsl, red, blue)").is_err());
+    assert!(parse_color("color-mix(in srgb, red 0%, blue 0%)").is_err());
+    assert!(parse_color("color()").is_err());
+    assert!(parse_color("color(  )").is_err());
+    assert_eq!(parse_color("transparent").unwrap(), Color::from(0, 0, 0, 0));
+}
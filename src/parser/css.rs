@@ -7,13 +7,25 @@
 /// #answer { display: none; }
 ///
 /// Each rule has selectors and declarations applied to it
+///
+/// The text is split into tokens by `css_tokenizer`, which are grouped into
+/// rules, blocks and declarations the way CSS Syntax Level 3 describes. A
+/// rule or declaration that can't be parsed is dropped with a diagnostic,
+/// and parsing goes on after it. The rules in `@media` blocks are kept with
+/// their media queries, they apply when the queries match.
+use super::calc::{self, Calc};
 use super::colors;
+use super::css_tokenizer::{Token, Tokenizer};
 use super::length;
-use super::Parser;
+use super::media::{self, MediaEnvironment, MediaQueryList};
+use super::shorthand;
+use super::{Diagnostic, Parser};
+
+/// Properties taking a list of counter names, each with an optional number
+const COUNTER_PROPERTIES: [&str; 2] = ["counter-increment", "counter-reset"];
 
-pub const COLOR_PROPERTIES: [&str; 8] = [
+pub const COLOR_PROPERTIES: [&str; 7] = [
     "background-color",
-    "border-color",
     "border-top-color",
     "border-right-color",
     "border-bottom-color",
@@ -29,61 +41,319 @@ pub type Specificity = (usize, usize, usize);
 
 #[derive(Debug)]
 pub struct Stylesheet {
-    pub rules: Vec<Rule>,
+    pub rules: Vec<CssRule>,
+    pub source: StyleSource,
 }
 
-#[derive(Debug)]
+impl Stylesheet {
+    /// The style rules that apply in `environment`, in the order of the
+    /// sheet, with those of `@media` blocks whose queries match
+    pub fn style_rules(&self, environment: &MediaEnvironment) -> Vec<&Rule> {
+        let mut rules = Vec::new();
+        collect_style_rules(&self.rules, environment, &mut rules);
+        rules
+    }
+}
+
+fn collect_style_rules<'a>(
+    rules: &'a [CssRule],
+    environment: &MediaEnvironment,
+    style_rules: &mut Vec<&'a Rule>,
+) {
+    for rule in rules {
+        match rule {
+            CssRule::Style(rule) => style_rules.push(rule),
+            CssRule::Media(queries, rules) => {
+                if queries.matches(environment) {
+                    collect_style_rules(rules, environment, style_rules);
+                }
+            }
+        }
+    }
+}
+
+/// A rule of a stylesheet, or a group of them applying under a condition
+#[derive(Debug, Clone)]
+pub enum CssRule {
+    Style(Rule),
+    /// `@media <queries> { <rules> }`
+    Media(MediaQueryList, Vec<CssRule>),
+}
+
+/// Where the text of a stylesheet came from
+#[derive(Debug, Clone, PartialEq)]
+pub enum StyleSource {
+    /// The stylesheet built into the browser
+    UserAgent,
+    /// A `<style>` element of the document
+    Inline,
+    /// A sheet fetched from this url
+    Url(String),
+}
+
+#[derive(Debug, Clone)]
 pub struct Rule {
     pub selectors: Vec<Selector>,       // h1, h2, h3
     pub declarations: Vec<Declaration>, // { margin: auto; color: #cc0000; }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Selector {
     Simple(SimpleSelector),
+    /// Simple selectors joined by combinators, e.g `nav > ul li`.
+    ///
+    /// The rightmost selector comes first, followed by the others from right
+    /// to left, each with the combinator on its right: `li`, then
+    /// `(Descendant, ul)` and `(Child, nav)`.
+    Compound(SimpleSelector, Vec<(Combinator, SimpleSelector)>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Combinator {
+    /// `a b`
+    Descendant,
+    /// `a > b`
+    Child,
+    /// `a + b`
+    NextSibling,
+    /// `a ~ b`
+    SubsequentSibling,
 }
 
 impl Selector {
+    /// The pseudo-element the selector styles, `None` for the element itself
+    pub fn pseudo_element(&self) -> Option<PseudoElement> {
+        match *self {
+            Selector::Simple(ref subject) | Selector::Compound(ref subject, _) => {
+                subject.pseudo_element
+            }
+        }
+    }
+
     pub fn specificity(&self) -> Specificity {
-        let Selector::Simple(ref simple) = *self;
-        let a = simple.id.iter().count();
-        let b = simple.class.len();
-        let c = simple.tag_name.iter().count();
-        (a, b, c)
+        match *self {
+            Selector::Simple(ref simple) => simple.specificity(),
+            Selector::Compound(ref subject, ref chain) => chain
+                .iter()
+                .map(|(_, simple)| simple.specificity())
+                .fold(subject.specificity(), add_specificity),
+        }
     }
 }
 
-#[derive(Debug)]
+impl SimpleSelector {
+    fn specificity(&self) -> Specificity {
+        let a = self.id.iter().count();
+        let b = self.class.len() + self.attributes.len();
+        let c = self.tag_name.iter().count() + self.pseudo_element.iter().count();
+        self.pseudo_classes
+            .iter()
+            .map(PseudoClass::specificity)
+            .fold((a, b, c), add_specificity)
+    }
+}
+
+impl PseudoClass {
+    fn specificity(&self) -> Specificity {
+        match *self {
+            // the argument counts, not the pseudo-class itself
+            PseudoClass::Not(ref list) | PseudoClass::Is(ref list) => max_specificity(list),
+            PseudoClass::Where(_) => (0, 0, 0),
+            PseudoClass::NthChild(_, Some(ref list)) => {
+                add_specificity((0, 1, 0), max_specificity(list))
+            }
+            _ => (0, 1, 0),
+        }
+    }
+}
+
+fn add_specificity((a, b, c): Specificity, (x, y, z): Specificity) -> Specificity {
+    (a + x, b + y, c + z)
+}
+
+// The specificity of the most specific selector of a list
+fn max_specificity(list: &[Selector]) -> Specificity {
+    list.iter()
+        .map(Selector::specificity)
+        .max()
+        .unwrap_or((0, 0, 0))
+}
+
+#[derive(Debug, Clone)]
 pub struct SimpleSelector {
     pub tag_name: Option<String>,
     pub id: Option<String>,
     pub class: Vec<String>,
+    pub attributes: Vec<AttributeSelector>,
+    pub pseudo_classes: Vec<PseudoClass>,
+    /// Only allowed on the rightmost simple selector
+    pub pseudo_element: Option<PseudoElement>,
+}
+
+/// A box generated for an element besides its own, `::before` and `::after`
+/// hold the `content` placed before and after the children of the element.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PseudoElement {
+    Before,
+    After,
+}
+
+/// `:name` or `:name(<arguments>)`
+#[derive(Debug, Clone)]
+pub enum PseudoClass {
+    /// `:root`, the element without a parent
+    Root,
+    /// `:empty`, an element without children
+    Empty,
+    FirstChild,
+    LastChild,
+    /// `:nth-child(an+b)` or `:nth-child(an+b of <selectors>)`, where only
+    /// the siblings matching the selectors are counted
+    NthChild(Nth, Option<Vec<Selector>>),
+    /// `:nth-of-type(an+b)`, counting the siblings with the same tag name
+    NthOfType(Nth),
+    /// `:not(<selectors>)`
+    Not(Vec<Selector>),
+    /// `:is(<selectors>)`, as specific as its most specific argument
+    Is(Vec<Selector>),
+    /// `:where(<selectors>)`, like `:is` without specificity
+    Where(Vec<Selector>),
+    Hover,
+    Focus,
+    Checked,
+}
+
+/// The `an+b` argument of `:nth-child`, matches the elements at
+/// position `a*n + b` for some `n >= 0`, counting from 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Nth {
+    pub a: i32,
+    pub b: i32,
+}
+
+impl Nth {
+    pub fn matches(&self, position: i32) -> bool {
+        // in i64 so that `a` and `b` as large as i32 allows can't overflow
+        let a = i64::from(self.a);
+        let offset = i64::from(position) - i64::from(self.b);
+        if a == 0 {
+            offset == 0
+        } else {
+            offset % a == 0 && offset / a >= 0
+        }
+    }
+}
+
+/// `[name]`, or `[name<operator>"value" i]`
+#[derive(Debug, Clone)]
+pub struct AttributeSelector {
+    pub name: String,
+    /// `None` when only the presence of the attribute is checked
+    pub value: Option<(AttrOperator, String)>,
+    /// The `i` flag, values are compared ignoring ASCII case
+    pub case_insensitive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AttrOperator {
+    /// `=` exactly the value
+    Equals,
+    /// `~=` one of the whitespace separated words is the value
+    Includes,
+    /// `|=` the value, or the value followed by `-`
+    DashMatch,
+    /// `^=` starts with the value
+    Prefix,
+    /// `$=` ends with the value
+    Suffix,
+    /// `*=` contains the value
+    Substring,
 }
 
 #[derive(Debug, Clone)]
 pub struct Declaration {
     pub name: String,
     pub value: Value,
+    /// Declared with `!important`
+    pub important: bool,
+    pub origin: Origin,
+}
+
+/// Who wrote a stylesheet, decides its weight in the cascade
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Origin {
+    /// The defaults of the browser
+    UserAgent,
+    /// Preferences of the reader
+    User,
+    /// The page
+    Author,
 }
 
 #[derive(Debug, Clone)]
 pub enum Value {
     Keyword(String),
-    Length(LengthValue, Unit),
-    ColorValue(Color),
-    // Number(f32),
+    Length(f32, Unit),
+    /// A number without a unit, like the `1.5` of `line-height: 1.5`
+    Number(f32),
+    /// `calc()`, `min()`, `max()` or `clamp()`, a length or a number
+    Calc(Calc),
+    Color(Color),
+    /// The value of `content`
+    Content(Vec<ContentItem>),
+    /// Counter names with a number, for `counter-reset` and `counter-increment`
+    Counters(Vec<(String, i32)>),
+    /// The value of a custom property like `--accent: #c00`, the tokens as
+    /// written
+    Custom(Vec<Token>),
+    /// A value using `var()`, parsed once the variables are substituted.
+    /// `property` is the one it was declared for, the shorthand when this
+    /// is the value of one of its longhands.
+    WithVariables {
+        property: String,
+        tokens: Vec<Token>,
+    },
 }
 
-#[derive(Debug, Clone)]
-pub enum LengthValue {
-    Single(i32),
-    All(i32, i32, i32, i32),
+/// A piece of generated content
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContentItem {
+    String(String),
+    /// `attr(name)`, the value of an attribute of the element
+    Attr(String),
+    /// `counter(name)`, the innermost counter, in decimal
+    Counter(String),
+    /// `counters(name, "separator")`, all counters of that name in scope,
+    /// outermost first
+    Counters(String, String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Unit {
     Px,
+    /// The font size of the element, of the parent for `font-size`
     Em,
+    /// The font size of the root element
+    Rem,
+    /// The x-height of the font, taken as half the font size
+    Ex,
+    /// The width of `0` in the font
+    Ch,
+    Percent,
+    /// 1% of the viewport width
+    Vw,
+    /// 1% of the viewport height
+    Vh,
+    Vmin,
+    Vmax,
+    /// 1/72 in
+    Pt,
+    /// 12 pt
+    Pc,
+    /// 96 px
+    In,
+    Cm,
+    Mm,
 }
 
 #[derive(Debug, Clone)]
@@ -96,12 +366,7 @@ pub struct Color {
 
 impl Color {
     pub fn from(r: u8, g: u8, b: u8, a: u8) -> Color {
-        Color {
-            r: r,
-            g: g,
-            b: b,
-            a: a,
-        }
+        Color { r, g, b, a }
     }
 }
 
@@ -111,151 +376,1059 @@ impl PartialEq for Color {
     }
 }
 
-pub fn parse(source: String) -> Stylesheet {
-    let mut parser = Parser {
-        pos: 0,
-        input: source,
+/// Parse the text of a stylesheet, it is taken as coming from a `<style>` element.
+pub fn parse(source: String, origin: Origin) -> Stylesheet {
+    parse_with_diagnostics(source, origin).0
+}
+
+/// Parse a stylesheet, also returning the problems found in it. A rule or
+/// declaration that can't be parsed is dropped, the rest of the sheet
+/// still applies.
+pub fn parse_with_diagnostics(source: String, origin: Origin) -> (Stylesheet, Vec<Diagnostic>) {
+    let mut parser = RuleParser::new(&source, origin);
+    let rules = parser.consume_rules(false);
+
+    let mut diagnostics = parser.diagnostics;
+    diagnostics.append(&mut parser.tokenizer.diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.position);
+    let stylesheet = Stylesheet {
+        rules,
+        source: StyleSource::Inline,
     };
-    Stylesheet {
-        rules: parse_rules(&mut parser),
-    }
+    (stylesheet, diagnostics)
 }
 
-fn parse_rules(parser: &mut Parser) -> Vec<Rule> {
-    let mut rules = Vec::new();
-    loop {
-        parser.skip_whitespace();
-        if parser.eof() {
-            break;
+/// Parse a list of declarations without braces, like the
+/// `style` attribute of an element: `color: red; margin: 4px`
+pub fn parse_declaration_list(source: &str, origin: Origin) -> Vec<Declaration> {
+    RuleParser::new(source, origin).consume_declarations(false)
+}
+
+// A token, or a block or function with everything up to its closing
+// token. The preludes of rules and the values of declarations are made of
+// them.
+#[derive(Debug)]
+enum ComponentValue {
+    Token(Token),
+    /// `(...)`, `[...]` or `{...}`, with its opening character
+    Block(char, Vec<ComponentValue>),
+    Function(String, Vec<ComponentValue>),
+}
+
+// Builds rules and declarations from the tokens of a stylesheet, following
+// the parsing chapter of CSS Syntax Level 3,
+// https://www.w3.org/TR/css-syntax-3/#parsing
+struct RuleParser {
+    tokenizer: Tokenizer,
+    // a token to read again, with its position
+    reconsumed: Option<(usize, Token)>,
+    // position of the last token read
+    position: usize,
+    origin: Origin,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl RuleParser {
+    fn new(source: &str, origin: Origin) -> RuleParser {
+        RuleParser {
+            tokenizer: Tokenizer::new(source),
+            reconsumed: None,
+            position: 0,
+            origin,
+            diagnostics: Vec::new(),
         }
-        rules.push(parse_rule(parser));
     }
-    return rules;
-}
 
-// Parse a rule set: `<selectors> { <declarations> }`
-fn parse_rule(parser: &mut Parser) -> Rule {
-    Rule {
-        selectors: parse_selectors(parser),
-        declarations: parse_declarations(parser),
+    // The tokenizer returns `Eof` again and again at the end of the input
+    fn next(&mut self) -> Token {
+        let (position, token) = match self.reconsumed.take() {
+            Some(reconsumed) => reconsumed,
+            None => self.tokenizer.next_token(),
+        };
+        self.position = position;
+        token
     }
-}
 
-fn parse_selectors(parser: &mut Parser) -> Vec<Selector> {
-    let mut selectors = Vec::new();
+    fn reconsume(&mut self, token: Token) {
+        self.reconsumed = Some((self.position, token));
+    }
 
-    loop {
-        selectors.push(Selector::Simple(parse_simple_selector(parser)));
-        parser.skip_whitespace();
-        match parser.next_char() {
-            ',' => {
-                parser.consume_char();
-                parser.skip_whitespace();
+    fn error(&mut self, position: usize, msg: &'static str) {
+        self.diagnostics.push(Diagnostic::new(position, msg));
+    }
+
+    // The rules of the stylesheet up to the end of the input, or those of
+    // a block up to its `}` when `nested`
+    fn consume_rules(&mut self, nested: bool) -> Vec<CssRule> {
+        let mut rules = Vec::new();
+        loop {
+            match self.next() {
+                Token::Eof => {
+                    if nested {
+                        self.error(self.position, "eof in block");
+                    }
+                    return rules;
+                }
+                Token::CloseCurly if nested => return rules,
+                // `<!--` and `-->` hid the sheet from browsers without CSS
+                Token::Whitespace | Token::Cdo | Token::Cdc => {}
+                Token::AtKeyword(name) => rules.extend(self.consume_at_rule(&name)),
+                token => {
+                    self.reconsume(token);
+                    rules.extend(self.consume_qualified_rule().map(CssRule::Style));
+                }
             }
-            '{' => break, // start of declarations
-            c => panic!("unexpected character in selector parsing: `{}`", c),
         }
     }
 
-    // Return selectors with highest specificity first, for use in matching.
-    selectors.sort_by(|a, b| b.specificity().cmp(&a.specificity()));
+    // The prelude and block of an at-rule, after its at-keyword. Only
+    // `@media` is supported, other at-rules are skipped.
+    fn consume_at_rule(&mut self, name: &str) -> Option<CssRule> {
+        let start = self.position;
+        let mut prelude = Vec::new();
+        let has_block = loop {
+            match self.next() {
+                Token::Semicolon | Token::Eof => break false,
+                Token::OpenCurly => break true,
+                token => prelude.push(self.consume_component_value(token)),
+            }
+        };
+        if name.eq_ignore_ascii_case("media") && has_block {
+            let queries = media::parse_media_query_list(&to_text(&prelude));
+            return Some(CssRule::Media(queries, self.consume_rules(true)));
+        }
+        if has_block {
+            self.consume_block_contents(Token::CloseCurly);
+        }
+        self.error(start, "unsupported at-rule");
+        None
+    }
 
-    return selectors;
-}
+    // `<selectors> { <declarations> }`, dropped when the selectors are invalid
+    fn consume_qualified_rule(&mut self) -> Option<Rule> {
+        let start = self.position;
+        let mut prelude = Vec::new();
+        loop {
+            match self.next() {
+                Token::OpenCurly => break,
+                Token::Eof => {
+                    self.error(start, "eof in rule prelude");
+                    return None;
+                }
+                token => prelude.push(self.consume_component_value(token)),
+            }
+        }
+        let declarations = self.consume_declarations(true);
+        match parse_selector_list(&prelude) {
+            Some(selectors) => Some(Rule {
+                selectors,
+                declarations,
+            }),
+            None => {
+                self.error(start, "invalid selector");
+                None
+            }
+        }
+    }
 
-fn parse_declarations(parser: &mut Parser) -> Vec<Declaration> {
-    let mut declarations = Vec::new();
+    // Declarations separated by `;`, up to the `}` closing the block when
+    // `nested` or else up to the end of the input. An invalid declaration
+    // is skipped up to the next `;`.
+    fn consume_declarations(&mut self, nested: bool) -> Vec<Declaration> {
+        let mut declarations = Vec::new();
+        loop {
+            let token = self.next();
+            let start = self.position;
+            match token {
+                Token::Whitespace | Token::Semicolon => {}
+                Token::CloseCurly if nested => return declarations,
+                Token::Eof => {
+                    if nested {
+                        self.error(start, "eof in declaration block");
+                    }
+                    return declarations;
+                }
+                // no at-rule is allowed in a declaration list
+                Token::AtKeyword(_) => {
+                    self.consume_at_rule("");
+                }
+                token => {
+                    self.reconsume(token);
+                    let values = self.consume_declaration_values(nested);
+                    match parse_declaration(&values, self.origin) {
+                        Some(parsed) => declarations.extend(parsed),
+                        None => self.error(start, "invalid declaration"),
+                    }
+                }
+            }
+        }
+    }
 
-    assert_eq!('{', parser.consume_char()); // start of declaration
+    // Component values up to the `;` ending a declaration, or the `}`
+    // ending the block which is left for the caller
+    fn consume_declaration_values(&mut self, nested: bool) -> Vec<ComponentValue> {
+        let mut values = Vec::new();
+        loop {
+            match self.next() {
+                Token::Semicolon | Token::Eof => return values,
+                Token::CloseCurly if nested => {
+                    self.reconsume(Token::CloseCurly);
+                    return values;
+                }
+                token => values.push(self.consume_component_value(token)),
+            }
+        }
+    }
 
-    loop {
-        parser.skip_whitespace();
-        if parser.next_char() == '}' {
-            break; // end of declaration
+    fn consume_component_value(&mut self, token: Token) -> ComponentValue {
+        match token {
+            Token::OpenParen => {
+                ComponentValue::Block('(', self.consume_block_contents(Token::CloseParen))
+            }
+            Token::OpenSquare => {
+                ComponentValue::Block('[', self.consume_block_contents(Token::CloseSquare))
+            }
+            Token::OpenCurly => {
+                ComponentValue::Block('{', self.consume_block_contents(Token::CloseCurly))
+            }
+            Token::Function(name) => {
+                ComponentValue::Function(name, self.consume_block_contents(Token::CloseParen))
+            }
+            token => ComponentValue::Token(token),
         }
-        declarations.push(parse_declaration(parser));
     }
 
-    assert_eq!('}', parser.consume_char()); // end of declaration
+    // Component values up to `end`, which is consumed. Blocks left open
+    // are closed at the end of the input.
+    fn consume_block_contents(&mut self, end: Token) -> Vec<ComponentValue> {
+        let start = self.position;
+        let mut values = Vec::new();
+        loop {
+            match self.next() {
+                token if token == end => return values,
+                Token::Eof => {
+                    self.error(start, "eof in block");
+                    return values;
+                }
+                token => values.push(self.consume_component_value(token)),
+            }
+        }
+    }
+}
+
+// Selectors separated by `,`, `None` when any of them is invalid
+fn parse_selector_list(values: &[ComponentValue]) -> Option<Vec<Selector>> {
+    let mut selectors = values
+        .split(|value| matches!(value, ComponentValue::Token(Token::Comma)))
+        .map(parse_selector)
+        .collect::<Option<Vec<Selector>>>()?;
 
-    return declarations;
+    // Return selectors with highest specificity first, for use in matching.
+    selectors.sort_by_key(|s| std::cmp::Reverse(s.specificity()));
+
+    Some(selectors)
 }
 
-// selector of format => type#id.class1.class2.class3
-fn parse_simple_selector(parser: &mut Parser) -> SimpleSelector {
+// Simple selectors separated by combinators
+fn parse_selector(values: &[ComponentValue]) -> Option<Selector> {
+    let mut values = trim_whitespace(values);
+    // read left to right, stored right to left
+    let mut subject = parse_simple_selector(&mut values)?;
+    let mut chain = Vec::new();
+    while !values.is_empty() {
+        let whitespace = matches!(values[0], ComponentValue::Token(Token::Whitespace));
+        values = trim_whitespace(values);
+        let combinator = match values[0] {
+            ComponentValue::Token(Token::Delim('>')) => Combinator::Child,
+            ComponentValue::Token(Token::Delim('+')) => Combinator::NextSibling,
+            ComponentValue::Token(Token::Delim('~')) => Combinator::SubsequentSibling,
+            _ if whitespace => Combinator::Descendant,
+            _ => return None,
+        };
+        if combinator != Combinator::Descendant {
+            values = trim_whitespace(&values[1..]);
+        }
+        let next = parse_simple_selector(&mut values)?;
+        chain.push((combinator, std::mem::replace(&mut subject, next)));
+    }
+
+    if chain.is_empty() {
+        Some(Selector::Simple(subject))
+    } else {
+        chain.reverse();
+        Some(Selector::Compound(subject, chain))
+    }
+}
+
+// selector of format => type#id.class1.class2.class3, `None` when it is
+// empty or invalid
+fn parse_simple_selector(values: &mut &[ComponentValue]) -> Option<SimpleSelector> {
     let mut selector = SimpleSelector {
         tag_name: None,
         id: None,
         class: Vec::new(),
+        attributes: Vec::new(),
+        pseudo_classes: Vec::new(),
+        pseudo_element: None,
     };
-    while !parser.eof() {
-        match parser.next_char() {
-            '#' => {
-                parser.consume_char();
-                selector.id = Some(parse_identifier(parser));
+    let start = values.len();
+    match values {
+        [ComponentValue::Token(Token::Ident(name)), rest @ ..] => {
+            selector.tag_name = Some(name.to_ascii_lowercase());
+            *values = rest;
+        }
+        [ComponentValue::Token(Token::Delim('*')), rest @ ..] => *values = rest,
+        _ => {}
+    }
+    loop {
+        match values {
+            [ComponentValue::Token(Token::Hash { value, id: true }), rest @ ..] => {
+                selector.id = Some(value.clone());
+                *values = rest;
             }
-            '.' => {
-                parser.consume_char();
-                selector.class.push(parse_identifier(parser));
+            [ComponentValue::Token(Token::Delim('.')), ComponentValue::Token(Token::Ident(class)), rest @ ..] =>
+            {
+                selector.class.push(class.clone());
+                *values = rest;
             }
-            '*' => {
-                parser.consume_char();
+            [ComponentValue::Block('[', contents), rest @ ..] => {
+                selector
+                    .attributes
+                    .push(parse_attribute_selector(contents)?);
+                *values = rest;
             }
-            c if valid_identifier_char(c) => {
-                selector.tag_name = Some(parse_identifier(parser));
+            // `::before`
+            [ComponentValue::Token(Token::Colon), ComponentValue::Token(Token::Colon), pseudo, rest @ ..] =>
+            {
+                match pseudo {
+                    // other pseudo-elements are not supported, the selector is invalid
+                    ComponentValue::Token(Token::Ident(name)) => {
+                        selector.pseudo_element = Some(pseudo_element(name)?)
+                    }
+                    _ => return None,
+                }
+                *values = rest;
             }
-            _ => break, // mainly `,`
+            [ComponentValue::Token(Token::Colon), pseudo, rest @ ..] => {
+                match pseudo {
+                    // `:before` from CSS 2
+                    ComponentValue::Token(Token::Ident(name)) => match pseudo_element(name) {
+                        Some(pseudo_element) => selector.pseudo_element = Some(pseudo_element),
+                        None => selector.pseudo_classes.push(pseudo_class(name)?),
+                    },
+                    ComponentValue::Function(name, arguments) => selector
+                        .pseudo_classes
+                        .push(functional_pseudo_class(name, arguments)?),
+                    _ => return None,
+                }
+                *values = rest;
+            }
+            _ => break,
         }
     }
-    return selector;
+    if values.len() == start {
+        return None;
+    }
+    Some(selector)
 }
 
-fn parse_declaration(parser: &mut Parser) -> Declaration {
-    parser.skip_whitespace();
+fn pseudo_element(name: &str) -> Option<PseudoElement> {
+    match name.to_ascii_lowercase().as_str() {
+        "before" => Some(PseudoElement::Before),
+        "after" => Some(PseudoElement::After),
+        _ => None,
+    }
+}
 
-    let prop_name = parse_identifier(parser);
-    parser.skip_whitespace();
-    assert_eq!(':', parser.consume_char());
+// `:name`, `None` for a pseudo-class we don't know, which makes the
+// selector invalid
+fn pseudo_class(name: &str) -> Option<PseudoClass> {
+    Some(match name.to_ascii_lowercase().as_str() {
+        "root" => PseudoClass::Root,
+        "empty" => PseudoClass::Empty,
+        "first-child" => PseudoClass::FirstChild,
+        "last-child" => PseudoClass::LastChild,
+        "hover" => PseudoClass::Hover,
+        "focus" => PseudoClass::Focus,
+        "checked" => PseudoClass::Checked,
+        _ => return None,
+    })
+}
 
-    parser.skip_whitespace();
-    let prop_value = parse_property_value(
-        &prop_name,
-        parser
-            .consume_while(|c| c != ';')
-            .trim()
-            .to_ascii_lowercase(),
-    );
-    assert_eq!(';', parser.consume_char());
+// `:name(<arguments>)`
+fn functional_pseudo_class(name: &str, arguments: &[ComponentValue]) -> Option<PseudoClass> {
+    let arguments = trim_whitespace(arguments);
+    Some(match name.to_ascii_lowercase().as_str() {
+        "not" => PseudoClass::Not(parse_selector_list(arguments)?),
+        "is" => PseudoClass::Is(parse_selector_list(arguments)?),
+        "where" => PseudoClass::Where(parse_selector_list(arguments)?),
+        "nth-child" => {
+            // `an+b`, then optionally `of <selectors>`
+            let of = arguments.iter().position(|argument| match argument {
+                ComponentValue::Token(Token::Ident(of)) => of.eq_ignore_ascii_case("of"),
+                _ => false,
+            });
+            match of {
+                Some(of) => PseudoClass::NthChild(
+                    parse_nth(&to_text(&arguments[..of]))?,
+                    Some(parse_selector_list(&arguments[of + 1..])?),
+                ),
+                None => PseudoClass::NthChild(parse_nth(&to_text(arguments))?, None),
+            }
+        }
+        "nth-of-type" => PseudoClass::NthOfType(parse_nth(&to_text(arguments))?),
+        _ => return None,
+    })
+}
+
+// The contents of `[name="value" i]`
+fn parse_attribute_selector(contents: &[ComponentValue]) -> Option<AttributeSelector> {
+    let mut values = contents
+        .iter()
+        .filter(|value| !matches!(value, ComponentValue::Token(Token::Whitespace)));
+    let name = match values.next()? {
+        ComponentValue::Token(Token::Ident(name)) => name.to_ascii_lowercase(),
+        _ => return None,
+    };
+
+    let operator = match values.next() {
+        None => {
+            return Some(AttributeSelector {
+                name,
+                value: None,
+                case_insensitive: false,
+            })
+        }
+        Some(ComponentValue::Token(Token::Delim('='))) => AttrOperator::Equals,
+        Some(ComponentValue::Token(Token::Delim(c))) => {
+            let operator = match c {
+                '~' => AttrOperator::Includes,
+                '|' => AttrOperator::DashMatch,
+                '^' => AttrOperator::Prefix,
+                '$' => AttrOperator::Suffix,
+                '*' => AttrOperator::Substring,
+                _ => return None,
+            };
+            match values.next()? {
+                ComponentValue::Token(Token::Delim('=')) => operator,
+                _ => return None,
+            }
+        }
+        Some(_) => return None,
+    };
+
+    let value = match values.next()? {
+        ComponentValue::Token(Token::Ident(value))
+        | ComponentValue::Token(Token::String(value)) => value.clone(),
+        _ => return None,
+    };
+    let case_insensitive = match values.next() {
+        None => false,
+        Some(ComponentValue::Token(Token::Ident(flag))) if flag.eq_ignore_ascii_case("i") => true,
+        Some(ComponentValue::Token(Token::Ident(flag))) if flag.eq_ignore_ascii_case("s") => false,
+        Some(_) => return None,
+    };
+    if values.next().is_some() {
+        return None;
+    }
+
+    Some(AttributeSelector {
+        name,
+        value: Some((operator, value)),
+        case_insensitive,
+    })
+}
+
+// `odd`, `even`, `b`, `an` or `an+b`, with optional whitespace around the sign
+fn parse_nth(argument: &str) -> Option<Nth> {
+    let argument: String = argument
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_ascii_lowercase();
+    let parse = |number: &str| -> Option<i32> { number.trim_start_matches('+').parse().ok() };
+    match argument.as_str() {
+        "odd" => Some(Nth { a: 2, b: 1 }),
+        "even" => Some(Nth { a: 2, b: 0 }),
+        _ => match argument.find('n') {
+            Some(n) => {
+                let a = match &argument[..n] {
+                    "" | "+" => 1,
+                    "-" => -1,
+                    a => parse(a)?,
+                };
+                let b = match &argument[n + 1..] {
+                    "" => 0,
+                    b => parse(b)?,
+                };
+                Some(Nth { a, b })
+            }
+            None => Some(Nth {
+                a: 0,
+                b: parse(&argument)?,
+            }),
+        },
+    }
+}
+
+// `name: value`, with an optional `!important`. A shorthand gives a
+// declaration for each of its longhands, `None` when the declaration or
+// the value of a shorthand is invalid.
+fn parse_declaration(values: &[ComponentValue], origin: Origin) -> Option<Vec<Declaration>> {
+    let (name, values) = match values.split_first()? {
+        // the names of custom properties are case-sensitive
+        (ComponentValue::Token(Token::Ident(name)), values) if name.starts_with("--") => {
+            (name.clone(), values)
+        }
+        (ComponentValue::Token(Token::Ident(name)), values) => (name.to_ascii_lowercase(), values),
+        _ => return None,
+    };
+    let value = match trim_whitespace(values).split_first()? {
+        (ComponentValue::Token(Token::Colon), value) => trim_whitespace(value),
+        _ => return None,
+    };
+    let (value, important) = strip_important(value);
+    if value.iter().any(is_bad) {
+        return None;
+    }
+    let declaration = |name, value| Declaration {
+        name,
+        value,
+        important,
+        origin,
+    };
+
+    // a custom property may be empty
+    if name.starts_with("--") {
+        return Some(vec![declaration(name, parse_custom_property(value))]);
+    }
+    if value.is_empty() {
+        return None;
+    }
+    // the longhands of a shorthand with `var()` are all parsed from its value
+    if value.iter().any(has_var) {
+        let tokens = to_tokens(value);
+        let longhands = shorthand::longhands(&name).unwrap_or_else(|| vec![name.clone()]);
+        let declarations = longhands
+            .into_iter()
+            .map(|longhand| {
+                let value = Value::WithVariables {
+                    property: name.clone(),
+                    tokens: tokens.clone(),
+                };
+                declaration(longhand, value)
+            })
+            .collect();
+        return Some(declarations);
+    }
+
+    let value = to_text(value);
+    let longhands = shorthand::expand(&name, &value).unwrap_or_else(|| vec![(name, value)]);
+    if longhands.is_empty() {
+        return None;
+    }
+    // a value one of the longhands doesn't accept drops the whole declaration
+    longhands
+        .into_iter()
+        .map(|(name, value)| {
+            let value = parse_property_value(&name, value)?;
+            Some(declaration(name, value))
+        })
+        .collect()
+}
 
-    Declaration {
-        name: prop_name,
-        value: prop_value,
+/// Parse the value of `name` in a declaration of `property` once its
+/// variables are substituted, `None` when it is invalid. `property` is a
+/// shorthand of `name` when it set several longhands.
+pub fn parse_substituted(name: &str, property: &str, tokens: Vec<Token>) -> Option<Value> {
+    let values = group_tokens(&mut tokens.into_iter(), None);
+    let value = trim_whitespace(&values);
+    if value.is_empty() || value.iter().any(is_bad) {
+        return None;
+    }
+    let value = to_text(value);
+    match shorthand::expand(property, &value) {
+        Some(longhands) => longhands
+            .into_iter()
+            .find(|(longhand, _)| longhand == name)
+            .and_then(|(_, value)| parse_property_value(name, value)),
+        None => parse_property_value(name, value),
+    }
+}
+
+// The tokens of a custom property, but the keywords of every property are
+// keywords
+fn parse_custom_property(value: &[ComponentValue]) -> Value {
+    if let [ComponentValue::Token(Token::Ident(keyword))] = value {
+        let keyword = keyword.to_ascii_lowercase();
+        if matches!(keyword.as_str(), "inherit" | "initial" | "unset") {
+            return Value::Keyword(keyword);
+        }
+    }
+    Value::Custom(to_tokens(value))
+}
+
+fn has_var(value: &ComponentValue) -> bool {
+    match value {
+        ComponentValue::Function(name, _) if name.eq_ignore_ascii_case("var") => true,
+        ComponentValue::Function(_, values) | ComponentValue::Block(_, values) => {
+            values.iter().any(has_var)
+        }
+        ComponentValue::Token(_) => false,
     }
 }
 
-fn parse_property_value(property_name: &String, value_string: String) -> Value {
-    if COLOR_PROPERTIES.contains(&property_name.as_ref()) {
+// Split `red ! important` into the value and whether it is important.
+fn strip_important(values: &[ComponentValue]) -> (&[ComponentValue], bool) {
+    if let Some((ComponentValue::Token(Token::Ident(flag)), rest)) = values.split_last() {
+        if flag.eq_ignore_ascii_case("important") {
+            if let Some((ComponentValue::Token(Token::Delim('!')), rest)) =
+                trim_whitespace(rest).split_last()
+            {
+                return (trim_whitespace(rest), true);
+            }
+        }
+    }
+    (values, false)
+}
+
+fn trim_whitespace(values: &[ComponentValue]) -> &[ComponentValue] {
+    let is_whitespace =
+        |value: &ComponentValue| matches!(value, ComponentValue::Token(Token::Whitespace));
+    let start = values
+        .iter()
+        .take_while(|value| is_whitespace(value))
+        .count();
+    let end = values.len()
+        - values[start..]
+            .iter()
+            .rev()
+            .take_while(|value| is_whitespace(value))
+            .count();
+    &values[start..end]
+}
+
+// An unclosed string or malformed url makes the whole value invalid
+fn is_bad(value: &ComponentValue) -> bool {
+    match value {
+        ComponentValue::Token(token) => matches!(token, Token::BadString | Token::BadUrl),
+        ComponentValue::Block(_, values) | ComponentValue::Function(_, values) => {
+            values.iter().any(is_bad)
+        }
+    }
+}
+
+// The tokens of component values, blocks and functions with their closing
+// token
+fn to_tokens(values: &[ComponentValue]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for value in values {
+        match value {
+            ComponentValue::Token(token) => tokens.push(token.clone()),
+            ComponentValue::Block(open, values) => {
+                let (open, close) = match open {
+                    '(' => (Token::OpenParen, Token::CloseParen),
+                    '[' => (Token::OpenSquare, Token::CloseSquare),
+                    _ => (Token::OpenCurly, Token::CloseCurly),
+                };
+                tokens.push(open);
+                tokens.extend(to_tokens(values));
+                tokens.push(close);
+            }
+            ComponentValue::Function(name, values) => {
+                tokens.push(Token::Function(name.clone()));
+                tokens.extend(to_tokens(values));
+                tokens.push(Token::CloseParen);
+            }
+        }
+    }
+    tokens
+}
+
+// Component values from tokens, up to `end` or the last token
+fn group_tokens(
+    tokens: &mut std::vec::IntoIter<Token>,
+    end: Option<&Token>,
+) -> Vec<ComponentValue> {
+    let mut values = Vec::new();
+    while let Some(token) = tokens.next() {
+        let value = match token {
+            token if Some(&token) == end => break,
+            Token::OpenParen => {
+                ComponentValue::Block('(', group_tokens(tokens, Some(&Token::CloseParen)))
+            }
+            Token::OpenSquare => {
+                ComponentValue::Block('[', group_tokens(tokens, Some(&Token::CloseSquare)))
+            }
+            Token::OpenCurly => {
+                ComponentValue::Block('{', group_tokens(tokens, Some(&Token::CloseCurly)))
+            }
+            Token::Function(name) => {
+                ComponentValue::Function(name, group_tokens(tokens, Some(&Token::CloseParen)))
+            }
+            token => ComponentValue::Token(token),
+        };
+        values.push(value);
+    }
+    values
+}
+
+// The text of component values, for the value parsers that work on text.
+// Identifiers are ASCII case-insensitive and written in lower case, strings
+// and urls keep their case.
+fn to_text(values: &[ComponentValue]) -> String {
+    let mut text = String::new();
+    for value in values {
+        match value {
+            ComponentValue::Token(Token::Ident(name)) => text.push_str(&name.to_ascii_lowercase()),
+            ComponentValue::Token(Token::Dimension(number, unit)) => {
+                text.push_str(number);
+                text.push_str(&unit.to_ascii_lowercase());
+            }
+            ComponentValue::Token(token) => text.push_str(&token.to_string()),
+            ComponentValue::Block(open, values) => {
+                let close = match open {
+                    '(' => ')',
+                    '[' => ']',
+                    _ => '}',
+                };
+                text.push(*open);
+                text.push_str(&to_text(values));
+                text.push(close);
+            }
+            ComponentValue::Function(name, values) => {
+                text.push_str(&name.to_ascii_lowercase());
+                text.push('(');
+                text.push_str(&to_text(values));
+                text.push(')');
+            }
+        }
+    }
+    text
+}
+
+// The text of a value has identifiers in lower case already. `None` when
+// the property doesn't accept the value.
+fn parse_property_value(property_name: &str, value_string: String) -> Option<Value> {
+    let value = parse_value(property_name, value_string);
+    if accepts(property_name, &value) {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+fn parse_value(property_name: &str, value_string: String) -> Value {
+    if property_name == "content" {
+        if let Some(content) = parse_content(&value_string) {
+            return Value::Content(content);
+        }
+    }
+    if COUNTER_PROPERTIES.contains(&property_name) && value_string != "none" {
+        if let Some(counters) = parse_counters(property_name, &value_string) {
+            return Value::Counters(counters);
+        }
+    }
+    if COLOR_PROPERTIES.contains(&property_name) {
         let maybe_color = colors::parse_color(value_string.as_ref());
-        match maybe_color {
-            Ok(c) => return Value::ColorValue(c),
-            _ => {} // when color value is inherit, etc.
+        // when color value is inherit, etc. fall through to keyword
+        if let Ok(c) = maybe_color {
+            return Value::Color(c);
         }
     }
-    match length::parse_length(value_string.as_ref()) {
-        Some((le, unit)) => return Value::Length(le, unit),
-        None => {}
+    if let Some(calc) = calc::parse_calc(&value_string) {
+        return Value::Calc(calc);
+    }
+    if let Some((length, unit)) = length::parse_length(value_string.as_ref()) {
+        return Value::Length(length, unit);
+    }
+    if let Some(number) = length::parse_number(value_string.as_ref()) {
+        return Value::Number(number);
     }
 
     Value::Keyword(value_string)
 }
 
+const DISPLAY_KEYWORDS: [&str; 22] = [
+    "inline",
+    "block",
+    "inline-block",
+    "list-item",
+    "none",
+    "contents",
+    "flow-root",
+    "run-in",
+    "flex",
+    "inline-flex",
+    "grid",
+    "inline-grid",
+    "table",
+    "inline-table",
+    "table-row-group",
+    "table-header-group",
+    "table-footer-group",
+    "table-row",
+    "table-cell",
+    "table-column-group",
+    "table-column",
+    "table-caption",
+];
+
+const FONT_SIZE_KEYWORDS: [&str; 9] = [
+    "xx-small", "x-small", "small", "medium", "large", "x-large", "xx-large", "smaller", "larger",
+];
+
+// If `property` accepts `value`. Every property accepts the CSS-wide
+// keywords, the ones the browser doesn't use accept any value.
+fn accepts(property: &str, value: &Value) -> bool {
+    let keyword = |keywords: &[&str]| match value {
+        Value::Keyword(k) => keywords.contains(&k.as_str()),
+        _ => false,
+    };
+    let length = |non_negative: bool| match value {
+        Value::Length(length, _) => !non_negative || *length >= 0.0,
+        // a zero length may leave out the unit
+        Value::Number(number) => *number == 0.0,
+        // a negative `calc()` is clamped rather than invalid
+        Value::Calc(calc) => calc.is_length(),
+        _ => false,
+    };
+    if keyword(&["inherit", "initial", "unset"]) {
+        return true;
+    }
+    match property {
+        "width" | "height" => length(true) || keyword(&["auto"]),
+        "margin-top" | "margin-right" | "margin-bottom" | "margin-left" => {
+            length(false) || keyword(&["auto"])
+        }
+        "padding-top" | "padding-right" | "padding-bottom" | "padding-left" => length(true),
+        "border-top-width" | "border-right-width" | "border-bottom-width" | "border-left-width" => {
+            let percent = matches!(value, Value::Length(_, Unit::Percent));
+            (length(true) && !percent) || keyword(&["thin", "medium", "thick"])
+        }
+        "border-top-style" | "border-right-style" | "border-bottom-style" | "border-left-style" => {
+            keyword(&shorthand::BORDER_STYLES)
+        }
+        "display" => keyword(&DISPLAY_KEYWORDS),
+        "visibility" => keyword(&["visible", "hidden", "collapse"]),
+        "font-size" => length(true) || keyword(&FONT_SIZE_KEYWORDS),
+        "font-weight" => match value {
+            Value::Number(weight) => (1.0..=1000.0).contains(weight),
+            _ => keyword(&["normal", "bold", "bolder", "lighter"]),
+        },
+        "font-style" => match value {
+            Value::Keyword(k) => k == "normal" || k == "italic" || k.starts_with("oblique"),
+            _ => false,
+        },
+        "line-height" => match value {
+            Value::Number(number) => *number >= 0.0,
+            Value::Calc(_) => true,
+            _ => length(true) || keyword(&["normal"]),
+        },
+        "content" => matches!(value, Value::Content(_)) || keyword(&["none", "normal"]),
+        _ if COLOR_PROPERTIES.contains(&property) => {
+            matches!(value, Value::Color(_)) || keyword(&["currentcolor"])
+        }
+        _ if COUNTER_PROPERTIES.contains(&property) => {
+            matches!(value, Value::Counters(_)) || keyword(&["none"])
+        }
+        _ => true,
+    }
+}
+
+// `"text" attr(title) counter(item) counters(item, ".")`, `None` for
+// keywords like `none` or an invalid value
+fn parse_content(value: &str) -> Option<Vec<ContentItem>> {
+    let mut parser = Parser {
+        pos: 0,
+        input: value.to_string(),
+    };
+    let mut items = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.eof() {
+            break;
+        }
+        let item = match parser.next_char() {
+            '"' | '\'' => ContentItem::String(parse_string(&mut parser)?),
+            _ => {
+                let function = parse_identifier(&mut parser).to_ascii_lowercase();
+                if !parser.starts_with("(") {
+                    return None;
+                }
+                parser.consume_char();
+                let arguments = parse_arguments(&mut parser)?;
+                let name = arguments.first()?.to_ascii_lowercase();
+                match (function.as_str(), arguments.len()) {
+                    ("attr", 1) => ContentItem::Attr(name),
+                    // the list style is ignored, counters are always decimal
+                    ("counter", 1) | ("counter", 2) => ContentItem::Counter(name),
+                    ("counters", 2) | ("counters", 3) => {
+                        ContentItem::Counters(name, arguments[1].clone())
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        items.push(item);
+    }
+    if items.is_empty() {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+// Comma separated identifiers and strings up to `)`, which is consumed
+fn parse_arguments(parser: &mut Parser) -> Option<Vec<String>> {
+    let mut arguments = Vec::new();
+    loop {
+        parser.skip_whitespace();
+        if parser.eof() {
+            return None;
+        }
+        arguments.push(match parser.next_char() {
+            '"' | '\'' => parse_string(parser)?,
+            _ => parse_identifier(parser),
+        });
+        parser.skip_whitespace();
+        if parser.eof() {
+            return None;
+        }
+        match parser.consume_char() {
+            ',' => {}
+            ')' => return Some(arguments),
+            _ => return None,
+        }
+    }
+}
+
+// A quoted string, `\` escapes the next character or starts a hex code point
+fn parse_string(parser: &mut Parser) -> Option<String> {
+    let quote = parser.consume_char();
+    let mut string = String::new();
+    loop {
+        if parser.eof() {
+            return None;
+        }
+        match parser.consume_char() {
+            c if c == quote => return Some(string),
+            '\\' if parser.eof() => return None,
+            '\\' if parser.next_char().is_ascii_hexdigit() => {
+                let mut hex = String::new();
+                while hex.len() < 6 && !parser.eof() && parser.next_char().is_ascii_hexdigit() {
+                    hex.push(parser.consume_char());
+                }
+                // a single whitespace ends the escape
+                if !parser.eof() && parser.next_char().is_whitespace() {
+                    parser.consume_char();
+                }
+                let code = u32::from_str_radix(&hex, 16).ok()?;
+                string.push(std::char::from_u32(code).unwrap_or('\u{FFFD}'));
+            }
+            '\\' => string.push(parser.consume_char()),
+            c => string.push(c),
+        }
+    }
+}
+
+// `name [number]`, repeated. The number defaults to 1 for
+// `counter-increment` and to 0 for `counter-reset`.
+fn parse_counters(property_name: &str, value: &str) -> Option<Vec<(String, i32)>> {
+    let default = if property_name == "counter-increment" {
+        1
+    } else {
+        0
+    };
+    let mut counters: Vec<(String, i32)> = Vec::new();
+    for word in value.split_whitespace() {
+        match word.parse() {
+            Ok(number) => counters.last_mut()?.1 = number,
+            Err(_) => counters.push((word.to_string(), default)),
+        }
+    }
+    if counters.is_empty() {
+        None
+    } else {
+        Some(counters)
+    }
+}
+
 fn parse_identifier(parser: &mut Parser) -> String {
     parser.consume_while(valid_identifier_char)
 }
 
 fn valid_identifier_char(c: char) -> bool {
-    match c {
-        'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-' => true,
-        _ => false,
-    }
+    matches!(c, 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '-')
+}
+
+#[test]
+fn test_parse_recovery() {
+    let (stylesheet, diagnostics) = parse_with_diagnostics(
+        "<!-- @import \"a.css\"; @media print { a { color: red } }
+        a /* ; } */ { color: Red; list-style-image: URL(Foo.PNG); content: \"A;}\" }
+        b { color: ; width 10px; margin: 1px 2px 3px 4px 5px; height: 2em !IMPORTANT }
+        c d, e > [f=\"g\"]:not(.h), :nth-child(2n+1 of .i) {}
+        1x, j {} k:nth-child(x) { color: blue }
+        l { font: \"unclosed
+        ; color: green } m {"
+            .to_string(),
+        Origin::Author,
+    );
+    let rules = stylesheet.style_rules(&MediaEnvironment::default());
+    let texts: Vec<Vec<String>> = rules
+        .iter()
+        .map(|rule| {
+            rule.declarations
+                .iter()
+                .map(|declaration| {
+                    format!(
+                        "{}: {:?}{}",
+                        declaration.name,
+                        declaration.value,
+                        if declaration.important { " !" } else { "" }
+                    )
+                })
+                .collect()
+        })
+        .collect();
+    assert_eq!(
+        texts,
+        vec![
+            vec![
+                "color: Color(Color { r: 255, g: 0, b: 0, a: 255 })".to_string(),
+                "list-style-image: Keyword(\"url(Foo.PNG)\")".to_string(),
+                "content: Content([String(\"A;}\")])".to_string(),
+            ],
+            vec!["height: Length(2.0, Em) !".to_string()],
+            vec![],
+            vec!["color: Color(Color { r: 0, g: 128, b: 0, a: 255 })".to_string()],
+            vec![],
+        ]
+    );
+    assert_eq!(rules[2].selectors.len(), 3);
+    let print = MediaEnvironment {
+        media_type: media::MediaType::Print,
+        ..MediaEnvironment::default()
+    };
+    assert_eq!(stylesheet.style_rules(&print).len(), 6);
+
+    let messages: Vec<String> = diagnostics
+        .iter()
+        .map(|diagnostic| diagnostic.error.to_string())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            "unsupported at-rule",
+            "invalid declaration",
+            "invalid declaration",
+            "invalid declaration",
+            "invalid selector",
+            "invalid selector",
+            "invalid declaration",
+            "newline in string",
+            "eof in declaration block",
+        ]
+    );
 }
@@ -0,0 +1,561 @@
+// CSS tokenizer
+//
+// Follows the tokenization chapter of CSS Syntax Level 3,
+// https://www.w3.org/TR/css-syntax-3/#tokenization
+//
+// It reads the input with the shared `Parser` and splits it into
+// identifiers, functions, strings, numbers, punctuation and the like.
+// Comments are dropped and escapes are decoded. Like in HTML, malformed
+// input never stops the tokenizer: an unclosed string or comment ends at
+// the end of the input, and every problem is recorded as a diagnostic.
+use std::fmt;
+
+use super::{Diagnostic, Parser};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    /// An identifier directly followed by `(`, like `rgb(`
+    Function(String),
+    /// `@media`, without the `@`
+    AtKeyword(String),
+    /// `#name`, `id` is set when the name is a valid identifier
+    Hash {
+        value: String,
+        id: bool,
+    },
+    /// A quoted string, without the quotes
+    String(String),
+    /// A string with an unescaped newline
+    BadString,
+    /// An unquoted `url(...)`, without `url(` and `)`
+    Url(String),
+    BadUrl,
+    Delim(char),
+    // Numbers keep the text they were written with, like `+1` or `1.50`,
+    // the value parsers read it when a property needs the number.
+    Number(String),
+    /// `50%`, without the `%`
+    Percentage(String),
+    /// A number and its unit
+    Dimension(String, String),
+    Whitespace,
+    /// `<!--`
+    Cdo,
+    /// `-->`
+    Cdc,
+    Colon,
+    Semicolon,
+    Comma,
+    OpenSquare,
+    CloseSquare,
+    OpenParen,
+    CloseParen,
+    OpenCurly,
+    CloseCurly,
+    Eof,
+}
+
+/// Write the token back as CSS
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Function(name) => write!(f, "{}(", name),
+            Token::AtKeyword(name) => write!(f, "@{}", name),
+            Token::Hash { value, .. } => write!(f, "#{}", value),
+            Token::String(value) => {
+                f.write_str("\"")?;
+                for c in value.chars() {
+                    match c {
+                        '"' | '\\' => write!(f, "\\{}", c)?,
+                        '\n' => f.write_str("\\a ")?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str("\"")
+            }
+            Token::BadString => f.write_str("\""),
+            Token::Url(value) => {
+                f.write_str("url(")?;
+                for c in value.chars() {
+                    match c {
+                        '"' | '\'' | '(' | ')' | '\\' => write!(f, "\\{}", c)?,
+                        c if c.is_whitespace() => write!(f, "\\{:x} ", c as u32)?,
+                        c => write!(f, "{}", c)?,
+                    }
+                }
+                f.write_str(")")
+            }
+            Token::BadUrl => f.write_str("url("),
+            Token::Delim(c) => write!(f, "{}", c),
+            Token::Number(number) => write!(f, "{}", number),
+            Token::Percentage(number) => write!(f, "{}%", number),
+            Token::Dimension(number, unit) => write!(f, "{}{}", number, unit),
+            Token::Whitespace => f.write_str(" "),
+            Token::Cdo => f.write_str("<!--"),
+            Token::Cdc => f.write_str("-->"),
+            Token::Colon => f.write_str(":"),
+            Token::Semicolon => f.write_str(";"),
+            Token::Comma => f.write_str(","),
+            Token::OpenSquare => f.write_str("["),
+            Token::CloseSquare => f.write_str("]"),
+            Token::OpenParen => f.write_str("("),
+            Token::CloseParen => f.write_str(")"),
+            Token::OpenCurly => f.write_str("{"),
+            Token::CloseCurly => f.write_str("}"),
+            Token::Eof => Ok(()),
+        }
+    }
+}
+
+pub struct Tokenizer {
+    parser: Parser,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Tokenizer {
+    pub fn new(source: &str) -> Tokenizer {
+        // newlines and null characters are normalized before tokenizing
+        let input = source
+            .replace("\r\n", "\n")
+            .replace(['\r', '\x0c'], "\n")
+            .replace('\0', "\u{fffd}");
+        Tokenizer {
+            parser: Parser { pos: 0, input },
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Return the next token and its position in the input.
+    pub fn next_token(&mut self) -> (usize, Token) {
+        self.skip_comments();
+        let position = self.parser.pos;
+        let c = match self.consume() {
+            Some(c) => c,
+            None => return (position, Token::Eof),
+        };
+        let token = match c {
+            c if is_whitespace(c) => {
+                self.parser.consume_while(is_whitespace);
+                Token::Whitespace
+            }
+            '"' | '\'' => self.consume_string(c),
+            '#' if self.peek(0).is_some_and(is_ident_char) || self.starts_escape(0) => {
+                Token::Hash {
+                    id: self.starts_ident(0),
+                    value: self.consume_ident_sequence(),
+                }
+            }
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            '[' => Token::OpenSquare,
+            ']' => Token::CloseSquare,
+            '{' => Token::OpenCurly,
+            '}' => Token::CloseCurly,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            ';' => Token::Semicolon,
+            '+' | '-' | '.' if self.starts_number_after(c) => self.consume_numeric(position),
+            '-' if self.parser.starts_with("->") => {
+                self.parser.pos += 2;
+                Token::Cdc
+            }
+            '-' if self.starts_ident_after(c) => self.consume_ident_like(position),
+            '<' if self.parser.starts_with("!--") => {
+                self.parser.pos += 3;
+                Token::Cdo
+            }
+            '@' if self.starts_ident(0) => Token::AtKeyword(self.consume_ident_sequence()),
+            '\\' if self.starts_escape_after(c) => self.consume_ident_like(position),
+            '\\' => {
+                self.error("invalid escape");
+                Token::Delim(c)
+            }
+            c if c.is_ascii_digit() => self.consume_numeric(position),
+            c if is_ident_start(c) => self.consume_ident_like(position),
+            c => Token::Delim(c),
+        };
+        (position, token)
+    }
+
+    fn error(&mut self, msg: &'static str) {
+        self.diagnostics.push(Diagnostic::new(self.parser.pos, msg));
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        if self.parser.eof() {
+            None
+        } else {
+            Some(self.parser.consume_char())
+        }
+    }
+
+    // The character `n` characters after the next one, without consuming it
+    fn peek(&self, n: usize) -> Option<char> {
+        self.parser.input[self.parser.pos..].chars().nth(n)
+    }
+
+    fn skip_comments(&mut self) {
+        while self.parser.starts_with("/*") {
+            match self.parser.input[self.parser.pos + 2..].find("*/") {
+                Some(end) => self.parser.pos += 2 + end + 2,
+                None => {
+                    self.parser.pos = self.parser.input.len();
+                    self.error("eof in comment");
+                }
+            }
+        }
+    }
+
+    // Whether a `\` and the character after it start an escape
+    fn starts_escape(&self, n: usize) -> bool {
+        self.peek(n) == Some('\\') && self.peek(n + 1).is_some_and(|c| c != '\n')
+    }
+
+    // Like `starts_escape`, with `first` consumed already
+    fn starts_escape_after(&self, first: char) -> bool {
+        first == '\\' && self.peek(0).is_some_and(|c| c != '\n')
+    }
+
+    // Whether the next characters start an identifier
+    fn starts_ident(&self, n: usize) -> bool {
+        match self.peek(n) {
+            Some('-') => {
+                self.peek(n + 1)
+                    .is_some_and(|c| is_ident_start(c) || c == '-')
+                    || self.starts_escape(n + 1)
+            }
+            Some('\\') => self.starts_escape(n),
+            Some(c) => is_ident_start(c),
+            None => false,
+        }
+    }
+
+    // Like `starts_ident`, with `first` consumed already
+    fn starts_ident_after(&self, first: char) -> bool {
+        match first {
+            '-' => {
+                self.peek(0).is_some_and(|c| is_ident_start(c) || c == '-') || self.starts_escape(0)
+            }
+            '\\' => self.starts_escape_after(first),
+            c => is_ident_start(c),
+        }
+    }
+
+    // Whether `first`, consumed already, and the next characters start a number
+    fn starts_number_after(&self, first: char) -> bool {
+        let digit = |n| self.peek(n).is_some_and(|c: char| c.is_ascii_digit());
+        match first {
+            '+' | '-' => digit(0) || (self.peek(0) == Some('.') && digit(1)),
+            '.' => digit(0),
+            c => c.is_ascii_digit(),
+        }
+    }
+
+    // A number starting at `start`, followed by a unit or `%`
+    fn consume_numeric(&mut self, start: usize) -> Token {
+        let digit = |c: char| c.is_ascii_digit();
+        self.parser.pos = start;
+        if let Some('+') | Some('-') = self.peek(0) {
+            self.parser.pos += 1;
+        }
+        self.parser.consume_while(digit);
+        if self.peek(0) == Some('.') && self.peek(1).is_some_and(digit) {
+            self.parser.pos += 1;
+            self.parser.consume_while(digit);
+        }
+        if let Some('e') | Some('E') = self.peek(0) {
+            let sign = matches!(self.peek(1), Some('+') | Some('-'));
+            let exponent = if sign { 2 } else { 1 };
+            if self.peek(exponent).is_some_and(digit) {
+                self.parser.pos += exponent;
+                self.parser.consume_while(digit);
+            }
+        }
+        let number = self.parser.input[start..self.parser.pos].to_string();
+
+        if self.starts_ident(0) {
+            Token::Dimension(number, self.consume_ident_sequence())
+        } else if self.parser.starts_with("%") {
+            self.parser.pos += 1;
+            Token::Percentage(number)
+        } else {
+            Token::Number(number)
+        }
+    }
+
+    // An identifier starting at `start`, a function or an unquoted url
+    fn consume_ident_like(&mut self, start: usize) -> Token {
+        self.parser.pos = start;
+        let name = self.consume_ident_sequence();
+        if !self.parser.starts_with("(") {
+            return Token::Ident(name);
+        }
+        self.parser.pos += 1;
+        if !name.eq_ignore_ascii_case("url") {
+            return Token::Function(name);
+        }
+
+        // a quoted url is a function taking a string
+        let after_whitespace =
+            self.parser.input[self.parser.pos..].trim_start_matches(is_whitespace);
+        if after_whitespace.starts_with(['"', '\'']) {
+            return Token::Function(name);
+        }
+        self.consume_url()
+    }
+
+    // The part of `url(...)` after the `(`
+    fn consume_url(&mut self) -> Token {
+        let mut url = String::new();
+        self.parser.consume_while(is_whitespace);
+        loop {
+            match self.consume() {
+                Some(')') => return Token::Url(url),
+                None => {
+                    self.error("eof in url");
+                    return Token::Url(url);
+                }
+                Some(c) if is_whitespace(c) => {
+                    self.parser.consume_while(is_whitespace);
+                    match self.consume() {
+                        Some(')') => return Token::Url(url),
+                        None => {
+                            self.error("eof in url");
+                            return Token::Url(url);
+                        }
+                        Some(_) => {
+                            self.error("whitespace in url");
+                            return self.consume_bad_url();
+                        }
+                    }
+                }
+                Some('"') | Some('\'') | Some('(') => {
+                    self.error("unexpected character in url");
+                    return self.consume_bad_url();
+                }
+                Some(c) if is_non_printable(c) => {
+                    self.error("non-printable character in url");
+                    return self.consume_bad_url();
+                }
+                Some('\\') if self.starts_escape_after('\\') => url.push(self.consume_escape()),
+                Some('\\') => {
+                    self.error("invalid escape in url");
+                    return self.consume_bad_url();
+                }
+                Some(c) => url.push(c),
+            }
+        }
+    }
+
+    // Skip the rest of an invalid url, up to its `)`
+    fn consume_bad_url(&mut self) -> Token {
+        loop {
+            match self.consume() {
+                Some(')') | None => return Token::BadUrl,
+                Some('\\') if self.starts_escape_after('\\') => {
+                    self.consume_escape();
+                }
+                Some(_) => {}
+            }
+        }
+    }
+
+    // The part of a string after the opening quote
+    fn consume_string(&mut self, quote: char) -> Token {
+        let mut value = String::new();
+        loop {
+            match self.consume() {
+                Some(c) if c == quote => return Token::String(value),
+                None => {
+                    self.error("eof in string");
+                    return Token::String(value);
+                }
+                Some('\n') => {
+                    self.error("newline in string");
+                    self.parser.pos -= 1;
+                    return Token::BadString;
+                }
+                Some('\\') => match self.peek(0) {
+                    None => {}
+                    // an escaped newline continues the string on the next line
+                    Some('\n') => self.parser.pos += 1,
+                    Some(_) => value.push(self.consume_escape()),
+                },
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    // Identifier characters and escapes
+    fn consume_ident_sequence(&mut self) -> String {
+        let mut name = String::new();
+        loop {
+            match self.peek(0) {
+                Some(c) if is_ident_char(c) => {
+                    self.parser.consume_char();
+                    name.push(c);
+                }
+                Some('\\') if self.starts_escape(0) => {
+                    self.parser.pos += 1;
+                    name.push(self.consume_escape());
+                }
+                _ => return name,
+            }
+        }
+    }
+
+    // The part of an escape after the `\`: up to 6 hex digits and a single
+    // whitespace, or any other character as itself
+    fn consume_escape(&mut self) -> char {
+        let c = match self.consume() {
+            Some(c) => c,
+            None => {
+                self.error("eof in escape");
+                return '\u{fffd}';
+            }
+        };
+        if !c.is_ascii_hexdigit() {
+            return c;
+        }
+        let mut hex = c.to_string();
+        while hex.len() < 6 && self.peek(0).is_some_and(|c| c.is_ascii_hexdigit()) {
+            hex.push(self.parser.consume_char());
+        }
+        if self.peek(0).is_some_and(is_whitespace) {
+            self.parser.consume_char();
+        }
+        match u32::from_str_radix(&hex, 16) {
+            Ok(0) | Err(_) => '\u{fffd}',
+            Ok(code) => std::char::from_u32(code).unwrap_or('\u{fffd}'),
+        }
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, ' ' | '\t' | '\n')
+}
+
+fn is_ident_start(c: char) -> bool {
+    c.is_ascii_alphabetic() || c == '_' || !c.is_ascii()
+}
+
+fn is_ident_char(c: char) -> bool {
+    is_ident_start(c) || c.is_ascii_digit() || c == '-'
+}
+
+fn is_non_printable(c: char) -> bool {
+    matches!(c, '\0'..='\x08' | '\x0b' | '\x0e'..='\x1f' | '\x7f')
+}
+
+#[cfg(test)]
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::new(source);
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token().1 {
+            Token::Eof => return tokens,
+            token => tokens.push(token),
+        }
+    }
+}
+
+#[cfg(test)]
+fn ident(name: &str) -> Token {
+    Token::Ident(name.to_string())
+}
+
+#[test]
+fn test_tokens() {
+    assert_eq!(
+        tokenize("a#b.c:hover>d, [e~=\"f\"] {}"),
+        vec![
+            ident("a"),
+            Token::Hash {
+                value: "b".to_string(),
+                id: true
+            },
+            Token::Delim('.'),
+            ident("c"),
+            Token::Colon,
+            ident("hover"),
+            Token::Delim('>'),
+            ident("d"),
+            Token::Comma,
+            Token::Whitespace,
+            Token::OpenSquare,
+            ident("e"),
+            Token::Delim('~'),
+            Token::Delim('='),
+            Token::String("f".to_string()),
+            Token::CloseSquare,
+            Token::Whitespace,
+            Token::OpenCurly,
+            Token::CloseCurly,
+        ]
+    );
+    assert_eq!(
+        tokenize("-1.5em/* ; */+2 50% .5e1 -x rgb( @media #1a"),
+        vec![
+            Token::Dimension("-1.5".to_string(), "em".to_string()),
+            Token::Number("+2".to_string()),
+            Token::Whitespace,
+            Token::Percentage("50".to_string()),
+            Token::Whitespace,
+            Token::Number(".5e1".to_string()),
+            Token::Whitespace,
+            ident("-x"),
+            Token::Whitespace,
+            Token::Function("rgb".to_string()),
+            Token::Whitespace,
+            Token::AtKeyword("media".to_string()),
+            Token::Whitespace,
+            Token::Hash {
+                value: "1a".to_string(),
+                id: false
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_strings_urls_and_escapes() {
+    assert_eq!(
+        tokenize("'a;\"b' \"c\\\"\\41 d\" url( Foo.PNG ) url(\"x\") \\31 0"),
+        vec![
+            Token::String("a;\"b".to_string()),
+            Token::Whitespace,
+            Token::String("c\"Ad".to_string()),
+            Token::Whitespace,
+            Token::Url("Foo.PNG".to_string()),
+            Token::Whitespace,
+            Token::Function("url".to_string()),
+            Token::String("x".to_string()),
+            Token::CloseParen,
+            Token::Whitespace,
+            ident("10"),
+        ]
+    );
+
+    let mut tokenizer = Tokenizer::new("\"a\nb url(a b) /* c");
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token() {
+            (_, Token::Eof) => break,
+            (position, token) => tokens.push((position, token)),
+        }
+    }
+    assert_eq!(
+        tokens,
+        vec![
+            (0, Token::BadString),
+            (2, Token::Whitespace),
+            (3, ident("b")),
+            (4, Token::Whitespace),
+            (5, Token::BadUrl),
+            (13, Token::Whitespace),
+        ]
+    );
+    assert_eq!(tokenizer.diagnostics.len(), 3);
+}
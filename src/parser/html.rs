@@ -1,91 +1,811 @@
 // HTML parser
+//
+// The tokenizer splits the input into tags, text and comments which are
+// fed to a tree builder. Like the HTML5 tree construction algorithm, the builder never
+// gives up on malformed markup: missing end tags are implied, void
+// elements never get children, mis-nested formatting elements are
+// closed and reopened, and stray end tags are dropped. Every problem is
+// recorded as a diagnostic.
 use std::collections::hash_map::HashMap;
 
 use super::dom;
-use super::Parser;
+use super::dom::AttrMap;
+use super::html_tokenizer::{Token, Tokenizer};
+use super::Diagnostic;
+
+/// Elements that can't have any content and never have an end tag
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+/// Elements that are closed implicitly when their parent is closed
+const IMPLIED_END_TAGS: [&str; 10] = [
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc",
+];
+
+/// Elements that may still be open at the end of the input
+const OPTIONAL_END_TAGS: [&str; 19] = [
+    "dd", "dt", "li", "optgroup", "option", "p", "rb", "rp", "rt", "rtc", "tbody", "td", "tfoot",
+    "th", "thead", "tr", "body", "head", "html",
+];
+
+/// Elements that belong in `<head>`, anything else closes it
+const HEAD_ELEMENTS: [&str; 9] = [
+    "base", "link", "meta", "noscript", "script", "style", "template", "title", "head",
+];
+
+/// Start tags that close an open `<p>`
+const CLOSES_P: [&str; 34] = [
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "center",
+    "details",
+    "dialog",
+    "dir",
+    "div",
+    "dl",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hgroup",
+    "hr",
+    "main",
+    "menu",
+    "nav",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "ul",
+];
+
+const HEADINGS: [&str; 6] = ["h1", "h2", "h3", "h4", "h5", "h6"];
+
+/// Inline elements that are reopened when closed out of order
+const FORMATTING_ELEMENTS: [&str; 14] = [
+    "a", "b", "big", "code", "em", "font", "i", "nobr", "s", "small", "strike", "strong", "tt", "u",
+];
+
+/// Elements that stop formatting elements from leaking into them
+const MARKER_ELEMENTS: [&str; 6] = ["applet", "caption", "marquee", "object", "td", "th"];
+
+/// Elements a stray end tag can't close past
+const SPECIAL_ELEMENTS: [&str; 65] = [
+    "address",
+    "applet",
+    "area",
+    "article",
+    "aside",
+    "base",
+    "blockquote",
+    "body",
+    "br",
+    "button",
+    "caption",
+    "center",
+    "col",
+    "colgroup",
+    "dd",
+    "details",
+    "dir",
+    "div",
+    "dl",
+    "dt",
+    "embed",
+    "fieldset",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "head",
+    "header",
+    "hr",
+    "html",
+    "iframe",
+    "img",
+    "input",
+    "li",
+    "link",
+    "main",
+    "marquee",
+    "menu",
+    "meta",
+    "nav",
+    "object",
+    "ol",
+    "p",
+    "pre",
+    "script",
+    "section",
+    "select",
+    "source",
+    "style",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "textarea",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
+/// Elements that end the default scope when searching the open elements
+const SCOPE_BOUNDARIES: [&str; 9] = [
+    "applet", "caption", "html", "table", "td", "th", "marquee", "object", "template",
+];
 
 // parse html
 pub fn parse(source: String) -> dom::Node {
-    let mut parser = Parser {
-        pos: 0,
-        input: source,
-    };
-    let mut nodes = parse_nodes(&mut parser);
-
-    if nodes.len() == 1 {
-        nodes.swap_remove(0)
-    } else {
-        dom::create_element(String::from("html"), HashMap::new(), nodes)
-    }
+    parse_with_diagnostics(source).0
 }
 
-fn parse_nodes(parser: &mut Parser) -> Vec<dom::Node> {
-    let mut nodes = Vec::new();
+/// Parse html, also returning the problems found in the markup
+pub fn parse_with_diagnostics(source: String) -> (dom::Node, Vec<Diagnostic>) {
+    let mut tokenizer = Tokenizer::new(source);
+    let mut builder = TreeBuilder::new();
+
     loop {
-        parser.skip_whitespace();
-        if parser.eof() || parser.starts_with("</") {
-            break;
+        tokenizer.allow_cdata = builder.in_foreign_content();
+        let (position, token) = tokenizer.next_token();
+        builder.position = position;
+        match token {
+            Token::Eof => break,
+            token => builder.process(token),
         }
-        nodes.push(parse_node(parser));
     }
-    return nodes;
+
+    let (root, mut diagnostics) = builder.finish();
+    diagnostics.append(&mut tokenizer.diagnostics);
+    diagnostics.sort_by_key(|diagnostic| diagnostic.position);
+    (root, diagnostics)
 }
 
-// Parse a single node.
-fn parse_node(parser: &mut Parser) -> dom::Node {
-    match parser.next_char() {
-        '<' => parse_element(parser),
-        _ => parse_text(parser),
-    }
+/// An element that has been opened but not closed yet
+struct OpenElement {
+    id: usize,
+    name: String,
+    attributes: AttrMap,
+    children: Vec<dom::Node>,
 }
 
-fn parse_text(parser: &mut Parser) -> dom::Node {
-    dom::create_text(parser.consume_while(|c| c != '<'))
+/// A formatting element that applies to the content being parsed,
+/// it is reopened whenever its element has been closed too early
+enum FormattingEntry {
+    Marker,
+    Element {
+        // id of the open element created for it
+        id: usize,
+        name: String,
+        attributes: AttrMap,
+    },
 }
 
-fn parse_element(parser: &mut Parser) -> dom::Node {
-    // opening tag
-    assert!('<' == parser.consume_char());
-    let tag = parser.parse_tag_name();
-    let attributes = parse_attributes(parser);
-    assert!('>' == parser.consume_char());
+struct TreeBuilder {
+    // finished top level nodes
+    document: Vec<dom::Node>,
+    open_elements: Vec<OpenElement>,
+    active_formatting: Vec<FormattingEntry>,
+    next_id: usize,
+    // position of the token being processed
+    position: usize,
+    // a newline right after <pre> is not part of the content
+    ignore_newline: bool,
+    diagnostics: Vec<Diagnostic>,
+}
 
-    // parse nodes
-    let children = parse_nodes(parser);
+impl TreeBuilder {
+    fn new() -> TreeBuilder {
+        TreeBuilder {
+            document: Vec::new(),
+            open_elements: Vec::new(),
+            active_formatting: Vec::new(),
+            next_id: 0,
+            position: 0,
+            ignore_newline: false,
+            diagnostics: Vec::new(),
+        }
+    }
 
-    // closing tag
-    assert!('<' == parser.consume_char());
-    assert!('/' == parser.consume_char());
-    assert!(parser.parse_tag_name() == tag);
-    assert!('>' == parser.consume_char());
+    /// CDATA sections are only allowed in svg and math content
+    fn in_foreign_content(&self) -> bool {
+        self.open_elements
+            .iter()
+            .any(|element| element.name == "svg" || element.name == "math")
+    }
 
-    return dom::create_element(tag, attributes, children);
-}
+    fn error(&mut self, msg: &'static str) {
+        self.diagnostics.push(Diagnostic::new(self.position, msg));
+    }
+
+    fn process(&mut self, token: Token) {
+        let ignore_newline = std::mem::replace(&mut self.ignore_newline, false);
+        match token {
+            Token::Doctype(name) => {
+                if !self.document.is_empty() || !self.open_elements.is_empty() {
+                    self.error("unexpected doctype");
+                } else if name != "html" {
+                    self.error("unknown doctype");
+                }
+            }
+            Token::Comment(_) => {}
+            Token::Text(mut text) => {
+                if ignore_newline && text.starts_with('\n') {
+                    text.remove(0);
+                }
+                if !text.is_empty() {
+                    self.insert_text(text);
+                }
+            }
+            Token::StartTag {
+                name,
+                attributes,
+                self_closing,
+            } => {
+                if self_closing && !VOID_ELEMENTS.contains(&name.as_str()) {
+                    self.error("self-closing syntax on a non-void element");
+                }
+                self.ignore_newline = matches!(name.as_str(), "pre" | "listing" | "textarea");
+                self.process_start_tag(name, attributes);
+            }
+            Token::EndTag { name } => self.process_end_tag(&name),
+            Token::Eof => {}
+        }
+    }
 
-fn parse_attributes(parser: &mut Parser) -> HashMap<String, String> {
-    let mut attributes = HashMap::new();
+    fn process_start_tag(&mut self, name: String, attributes: AttrMap) {
+        let tag = name.as_str();
+        if self.current_is(&["head"]) && !HEAD_ELEMENTS.contains(&tag) {
+            self.pop();
+        }
+        match tag {
+            "html" | "head" | "body" if self.is_open(tag) => {
+                self.error("duplicate document element");
+            }
+            "li" => {
+                self.close_list_item(&["li"]);
+                self.close_p_in_button_scope();
+                self.open(name, attributes);
+            }
+            "dd" | "dt" => {
+                self.close_list_item(&["dd", "dt"]);
+                self.close_p_in_button_scope();
+                self.open(name, attributes);
+            }
+            _ if HEADINGS.contains(&tag) => {
+                self.close_p_in_button_scope();
+                if self.current_is(&HEADINGS) {
+                    self.error("nested heading");
+                    self.pop();
+                }
+                self.open(name, attributes);
+            }
+            _ if CLOSES_P.contains(&tag) => {
+                self.close_p_in_button_scope();
+                if VOID_ELEMENTS.contains(&tag) {
+                    self.insert_void(name, attributes);
+                } else {
+                    self.open(name, attributes);
+                }
+            }
+            "option" | "optgroup" => {
+                if self.current_is(&["option"]) {
+                    self.pop();
+                }
+                if tag == "optgroup" && self.current_is(&["optgroup"]) {
+                    self.pop();
+                }
+                self.reconstruct_formatting();
+                self.open(name, attributes);
+            }
+            "tbody" | "thead" | "tfoot" => {
+                self.close_table_parts(&["td", "th", "tr", "tbody", "thead", "tfoot"]);
+                self.open(name, attributes);
+            }
+            "tr" => {
+                self.close_table_parts(&["td", "th", "tr"]);
+                self.open(name, attributes);
+            }
+            "td" | "th" => {
+                self.close_table_parts(&["td", "th"]);
+                self.open(name, attributes);
+            }
+            _ if FORMATTING_ELEMENTS.contains(&tag) => {
+                if tag == "a" && self.active_formatting_element("a").is_some() {
+                    self.error("nested link");
+                    self.close_formatting_element("a");
+                }
+                self.reconstruct_formatting();
+                let id = self.open(name.clone(), attributes.clone());
+                self.active_formatting.push(FormattingEntry::Element {
+                    id,
+                    name,
+                    attributes,
+                });
+            }
+            _ if VOID_ELEMENTS.contains(&tag) => {
+                self.reconstruct_formatting();
+                self.insert_void(name, attributes);
+            }
+            _ => {
+                self.reconstruct_formatting();
+                self.open(name, attributes);
+            }
+        }
+    }
 
-    loop {
-        parser.skip_whitespace();
-        if parser.next_char() == '>' {
-            break;
+    fn process_end_tag(&mut self, tag: &str) {
+        match tag {
+            // the document elements are closed at the end of the input
+            "html" | "body" if self.is_open(tag) => {}
+            "head" if self.is_open(tag) => self.close_element(&["head"]),
+            "p" => {
+                if !self.in_scope(&["p"], &["button"]) {
+                    // an end tag without a start tag makes an empty paragraph
+                    self.error("no p element in scope");
+                    self.open("p".to_string(), HashMap::new());
+                }
+                self.close_p();
+            }
+            "br" => {
+                self.error("end tag for a void element");
+                self.reconstruct_formatting();
+                self.insert_void("br".to_string(), HashMap::new());
+            }
+            "li" | "dd" | "dt" => {
+                let scope: &[&str] = if tag == "li" { &["ol", "ul"] } else { &[] };
+                if !self.in_scope(&[tag], scope) {
+                    self.error("stray end tag");
+                    return;
+                }
+                self.close_element(&[tag]);
+            }
+            _ if HEADINGS.contains(&tag) => {
+                if !self.in_scope(&HEADINGS, &[]) {
+                    self.error("stray end tag");
+                    return;
+                }
+                self.close_element(&HEADINGS);
+            }
+            "table" | "tbody" | "thead" | "tfoot" | "tr" | "td" | "th" => {
+                if !self.in_table_scope(tag) {
+                    self.error("stray end tag");
+                    return;
+                }
+                // rows and cells inside are closed along with it
+                let parts: &[&str] = match tag {
+                    "table" => &["td", "th", "tr", "tbody", "thead", "tfoot", "caption"],
+                    "tbody" | "thead" | "tfoot" => &["td", "th", "tr"],
+                    "tr" => &["td", "th"],
+                    _ => &[],
+                };
+                self.close_table_parts(parts);
+                self.close_element(&[tag]);
+            }
+            _ if FORMATTING_ELEMENTS.contains(&tag) => self.close_formatting_element(tag),
+            _ if SPECIAL_ELEMENTS.contains(&tag) => {
+                if !self.in_scope(&[tag], &[]) {
+                    self.error("stray end tag");
+                    return;
+                }
+                self.close_element(&[tag]);
+            }
+            _ => self.close_any_element(tag),
         }
+    }
 
-        let (name, value) = parse_attr(parser);
-        attributes.insert(name, value);
+    fn current_is(&self, names: &[&str]) -> bool {
+        match self.open_elements.last() {
+            Some(element) => names.contains(&element.name.as_str()),
+            None => false,
+        }
+    }
+
+    fn is_open(&self, name: &str) -> bool {
+        self.open_elements
+            .iter()
+            .any(|element| element.name == name)
+    }
+
+    /// Is one of `names` open, without a scope boundary between it and the current element?
+    fn in_scope(&self, names: &[&str], extra_boundaries: &[&str]) -> bool {
+        for element in self.open_elements.iter().rev() {
+            let name = element.name.as_str();
+            if names.contains(&name) {
+                return true;
+            }
+            if SCOPE_BOUNDARIES.contains(&name) || extra_boundaries.contains(&name) {
+                return false;
+            }
+        }
+        false
+    }
+
+    /// Is `name` open inside the innermost table?
+    fn in_table_scope(&self, name: &str) -> bool {
+        for element in self.open_elements.iter().rev() {
+            if element.name == name {
+                return true;
+            }
+            if ["html", "table", "template"].contains(&element.name.as_str()) {
+                return false;
+            }
+        }
+        false
+    }
+
+    fn open(&mut self, name: String, attributes: AttrMap) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        if MARKER_ELEMENTS.contains(&name.as_str()) {
+            self.active_formatting.push(FormattingEntry::Marker);
+        }
+        self.open_elements.push(OpenElement {
+            id,
+            name,
+            attributes,
+            children: Vec::new(),
+        });
+        id
+    }
+
+    fn insert_void(&mut self, name: String, attributes: AttrMap) {
+        self.insert_node(dom::create_element(name, attributes, Vec::new()));
+    }
+
+    fn insert_node(&mut self, node: dom::Node) {
+        match self.open_elements.last_mut() {
+            Some(parent) => parent.children.push(node),
+            None => self.document.push(node),
+        }
+    }
+
+    fn insert_text(&mut self, text: String) {
+        if text.trim().is_empty() {
+            // whitespace outside of any element is dropped
+            if self.open_elements.is_empty() {
+                return;
+            }
+        } else {
+            if self.current_is(&["head"]) {
+                self.pop();
+            }
+            self.reconstruct_formatting();
+        }
+
+        // text next to text is merged into a single node
+        let siblings = match self.open_elements.last_mut() {
+            Some(parent) => &mut parent.children,
+            None => &mut self.document,
+        };
+        if let Some(dom::Node {
+            node_type: dom::NodeType::Text(previous),
+            ..
+        }) = siblings.last_mut()
+        {
+            previous.push_str(&text);
+            return;
+        }
+        siblings.push(dom::create_text(text));
+    }
+
+    // Close the current element and attach it to its parent.
+    fn pop(&mut self) {
+        let element = match self.open_elements.pop() {
+            Some(element) => element,
+            None => return,
+        };
+        if MARKER_ELEMENTS.contains(&element.name.as_str()) {
+            self.clear_formatting_to_marker();
+        }
+        let node = dom::create_element(element.name, element.attributes, element.children);
+        self.insert_node(node);
+    }
+
+    // Pop elements until one of `names` has been popped.
+    fn pop_until(&mut self, names: &[&str]) {
+        while let Some(element) = self.open_elements.last() {
+            let done = names.contains(&element.name.as_str());
+            self.pop();
+            if done {
+                break;
+            }
+        }
     }
 
-    return attributes;
+    // Close elements whose end tag can be left out, except for `except`.
+    fn generate_implied_end_tags(&mut self, except: &[&str]) {
+        while let Some(element) = self.open_elements.last() {
+            let name = element.name.as_str();
+            if !IMPLIED_END_TAGS.contains(&name) || except.contains(&name) {
+                break;
+            }
+            self.pop();
+        }
+    }
+
+    // Close the innermost of `names`, reporting elements closed along with it.
+    fn close_element(&mut self, names: &[&str]) {
+        self.generate_implied_end_tags(names);
+        if !self.current_is(names) {
+            self.error("end tag doesn't match the current element");
+        }
+        self.pop_until(names);
+    }
+
+    fn close_p(&mut self) {
+        self.close_element(&["p"]);
+    }
+
+    fn close_p_in_button_scope(&mut self) {
+        if self.in_scope(&["p"], &["button"]) {
+            self.close_p();
+        }
+    }
+
+    // A new list item closes the previous item of the same list.
+    fn close_list_item(&mut self, names: &[&str]) {
+        for element in self.open_elements.iter().rev() {
+            let name = element.name.as_str();
+            if names.contains(&name) {
+                self.close_element(names);
+                return;
+            }
+            if SPECIAL_ELEMENTS.contains(&name) && !["address", "div", "p"].contains(&name) {
+                return;
+            }
+        }
+    }
+
+    // A new table row or cell closes the previous one.
+    fn close_table_parts(&mut self, names: &[&str]) {
+        while self
+            .open_elements
+            .iter()
+            .rev()
+            .take_while(|element| element.name != "table")
+            .any(|element| names.contains(&element.name.as_str()))
+        {
+            self.generate_implied_end_tags(&[]);
+            self.pop_until(names);
+        }
+    }
+
+    /// End tag of an element without special rules: close the innermost element
+    /// with that name, unless an element that can't be closed implicitly is in the way.
+    fn close_any_element(&mut self, tag: &str) {
+        for element in self.open_elements.iter().rev() {
+            let name = element.name.as_str();
+            if name == tag {
+                self.close_element(&[tag]);
+                return;
+            }
+            if SPECIAL_ELEMENTS.contains(&name) {
+                break;
+            }
+        }
+        self.error("stray end tag");
+    }
+
+    fn active_formatting_element(&self, tag: &str) -> Option<usize> {
+        for (i, entry) in self.active_formatting.iter().enumerate().rev() {
+            match entry {
+                FormattingEntry::Marker => return None,
+                FormattingEntry::Element { name, .. } if name == tag => return Some(i),
+                FormattingEntry::Element { .. } => {}
+            }
+        }
+        None
+    }
+
+    /// End tag of a formatting element, a simplified adoption agency algorithm.
+    ///
+    /// Formatting elements opened inside it are closed as well,
+    /// but stay active and are reopened for the content that follows,
+    /// so `<b>1<i>2</b>3</i>` becomes `<b>1<i>2</i></b><i>3</i>`.
+    fn close_formatting_element(&mut self, tag: &str) {
+        let index = match self.active_formatting_element(tag) {
+            Some(index) => index,
+            None => return self.close_any_element(tag),
+        };
+        let id = match self.active_formatting[index] {
+            FormattingEntry::Element { id, .. } => id,
+            FormattingEntry::Marker => return,
+        };
+
+        let position = match self.open_elements.iter().position(|e| e.id == id) {
+            Some(position) => position,
+            None => {
+                self.error("formatting element was already closed");
+                self.active_formatting.remove(index);
+                return;
+            }
+        };
+        if !self.in_scope(&[tag], &[]) {
+            self.error("formatting element is not in scope");
+            return;
+        }
+        if position + 1 != self.open_elements.len() {
+            self.error("misnested formatting element");
+        }
+
+        self.active_formatting.remove(index);
+        while self.open_elements.len() > position {
+            self.pop();
+        }
+    }
+
+    // Reopen the active formatting elements that were closed too early.
+    fn reconstruct_formatting(&mut self) {
+        let first_closed = self
+            .active_formatting
+            .iter()
+            .rposition(|entry| match entry {
+                FormattingEntry::Marker => true,
+                FormattingEntry::Element { id, .. } => {
+                    self.open_elements.iter().any(|e| e.id == *id)
+                }
+            })
+            .map_or(0, |i| i + 1);
+
+        for i in first_closed..self.active_formatting.len() {
+            let (name, attributes) = match &self.active_formatting[i] {
+                FormattingEntry::Element {
+                    name, attributes, ..
+                } => (name.clone(), attributes.clone()),
+                FormattingEntry::Marker => continue,
+            };
+            let new_id = self.open(name, attributes);
+            if let FormattingEntry::Element { id, .. } = &mut self.active_formatting[i] {
+                *id = new_id;
+            }
+        }
+    }
+
+    fn clear_formatting_to_marker(&mut self) {
+        while let Some(entry) = self.active_formatting.pop() {
+            if let FormattingEntry::Marker = entry {
+                break;
+            }
+        }
+    }
+
+    fn finish(mut self) -> (dom::Node, Vec<Diagnostic>) {
+        if self
+            .open_elements
+            .iter()
+            .any(|element| !OPTIONAL_END_TAGS.contains(&element.name.as_str()))
+        {
+            self.error("unclosed element at end of input");
+        }
+        while !self.open_elements.is_empty() {
+            self.pop();
+        }
+
+        let mut nodes = self.document;
+        let root = if nodes.len() == 1 {
+            nodes.swap_remove(0)
+        } else {
+            dom::create_element(String::from("html"), HashMap::new(), nodes)
+        };
+        (root, self.diagnostics)
+    }
+}
+
+#[cfg(test)]
+fn to_html(node: &dom::Node) -> String {
+    match node.node_type {
+        dom::NodeType::Text(ref text) => text.clone(),
+        dom::NodeType::Element(ref element) => {
+            let children: String = node.children.iter().map(to_html).collect();
+            if VOID_ELEMENTS.contains(&element.tag_name.as_str()) {
+                format!("<{}>", element.tag_name)
+            } else {
+                format!("<{0}>{1}</{0}>", element.tag_name, children)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn parse_to_html(source: &str) -> (String, usize) {
+    let (root, diagnostics) = parse_with_diagnostics(source.to_string());
+    (to_html(&root), diagnostics.len())
+}
+
+#[test]
+fn test_parse_well_formed() {
+    assert_eq!(
+        parse_to_html("<div><p>a <b>b</b></p><br/></div>"),
+        ("<div><p>a <b>b</b></p><br></div>".to_string(), 0)
+    );
 }
 
-fn parse_attr(parser: &mut Parser) -> (String, String) {
-    // parse name
-    let name = parser.consume_while(|c| c != '=');
-    assert!(parser.consume_char() == '=');
-    // parse value
-    let open_comma = parser.consume_char(); // " or '
-    let value = parser.consume_while(|c| c != open_comma);
-    assert!(parser.consume_char() == open_comma);
+#[test]
+fn test_parse_implied_end_tags() {
+    assert_eq!(
+        parse_to_html("<div><p>one<p>two<ul><li>a<li>b</ul></div>"),
+        (
+            "<div><p>one</p><p>two</p><ul><li>a</li><li>b</li></ul></div>".to_string(),
+            0
+        )
+    );
+    assert_eq!(
+        parse_to_html("<table><tr><td>1<td>2<tr><td>3</table>"),
+        (
+            "<table><tr><td>1</td><td>2</td></tr><tr><td>3</td></tr></table>".to_string(),
+            0
+        )
+    );
+}
+
+#[test]
+fn test_parse_void_elements() {
+    assert_eq!(
+        parse_to_html("<p>a<br>b<img src=\"x\">c</p>"),
+        ("<p>a<br>b<img>c</p>".to_string(), 0)
+    );
+}
+
+#[test]
+fn test_parse_misnested_formatting() {
+    assert_eq!(
+        parse_to_html("<div><b>1<i>2</b>3</i></div>"),
+        ("<div><b>1<i>2</i></b><i>3</i></div>".to_string(), 1)
+    );
+    assert_eq!(
+        parse_to_html("<div><p><b>x<p>y</b></div>"),
+        ("<div><p><b>x</b></p><p><b>y</b></p></div>".to_string(), 1)
+    );
+}
+
+#[test]
+fn test_parse_stray_and_missing_tags() {
+    assert_eq!(
+        parse_to_html("<div>a</span>b</p></div>"),
+        ("<div>ab<p></p></div>".to_string(), 2)
+    );
+    assert_eq!(
+        parse_to_html("<div><span>a"),
+        ("<div><span>a</span></div>".to_string(), 1)
+    );
+    assert_eq!(
+        parse_to_html("<div a=\"1"),
+        ("<html></html>".to_string(), 1)
+    );
+}
 
-    return (name, value);
+#[test]
+fn test_parse_comments_and_raw_text() {
+    assert_eq!(
+        parse_to_html(
+            "<!DOCTYPE html><div><!-- <p> --><style>a>b{}</style><pre>\n x&lt;</pre></div>"
+        ),
+        (
+            "<div><style>a>b{}</style><pre> x<</pre></div>".to_string(),
+            0
+        )
+    );
 }
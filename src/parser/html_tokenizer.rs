@@ -0,0 +1,926 @@
+// HTML tokenizer
+//
+// A state machine following the tokenization chapter of the HTML standard,
+// https://html.spec.whatwg.org/multipage/parsing.html#tokenization
+//
+// It reads the input with the shared `Parser` and splits it into tags, text,
+// comments and doctypes. Character references like `&amp;` or `&#x263A;`
+// are decoded in text and attribute values. The content of `<script>`,
+// `<style>`, `<textarea>` and `<title>` is read as raw text up to the
+// matching end tag.
+use std::collections::hash_map::{Entry, HashMap};
+use std::collections::VecDeque;
+
+use super::dom::AttrMap;
+use super::entities;
+use super::{Diagnostic, Parser};
+
+#[derive(Debug, PartialEq)]
+pub enum Token {
+    Doctype(String),
+    StartTag {
+        name: String,
+        attributes: AttrMap,
+        self_closing: bool,
+    },
+    EndTag {
+        name: String,
+    },
+    Comment(String),
+    Text(String),
+    Eof,
+}
+
+/// Elements whose content is text up to their end tag
+const RAW_TEXT_ELEMENTS: [&str; 6] = ["iframe", "noembed", "noframes", "script", "style", "xmp"];
+
+/// Like raw text elements, but character references are decoded
+const ESCAPABLE_RAW_TEXT_ELEMENTS: [&str; 2] = ["textarea", "title"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Data,
+    RcData,
+    RawText,
+    PlainText,
+    TagOpen,
+    EndTagOpen,
+    TagName,
+    BeforeAttributeName,
+    AttributeName,
+    AfterAttributeName,
+    BeforeAttributeValue,
+    AttributeValueDoubleQuoted,
+    AttributeValueSingleQuoted,
+    AttributeValueUnquoted,
+    AfterAttributeValueQuoted,
+    SelfClosingStartTag,
+    BogusComment,
+    MarkupDeclarationOpen,
+    CommentStart,
+    CommentStartDash,
+    Comment,
+    CommentEndDash,
+    CommentEnd,
+    CommentEndBang,
+    Doctype,
+    CdataSection,
+}
+
+/// The tag being read
+#[derive(Default)]
+struct Tag {
+    end: bool,
+    name: String,
+    attributes: Vec<(String, String)>,
+    self_closing: bool,
+}
+
+pub struct Tokenizer {
+    parser: Parser,
+    state: State,
+    // tokens ready to be returned, with their position in the input
+    tokens: VecDeque<(usize, Token)>,
+    // text read since the last token
+    text: String,
+    text_start: usize,
+    tag: Tag,
+    tag_start: usize,
+    comment: String,
+    // name of the last start tag, the end tag of raw text has to match it
+    last_start_tag: String,
+    /// CDATA sections are only allowed inside svg and math content
+    pub allow_cdata: bool,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Tokenizer {
+    pub fn new(source: String) -> Tokenizer {
+        // newlines are normalized before tokenizing
+        let input = source.replace("\r\n", "\n").replace('\r', "\n");
+        Tokenizer {
+            parser: Parser { pos: 0, input },
+            state: State::Data,
+            tokens: VecDeque::new(),
+            text: String::new(),
+            text_start: 0,
+            tag: Default::default(),
+            tag_start: 0,
+            comment: String::new(),
+            last_start_tag: String::new(),
+            allow_cdata: false,
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Return the next token and its position in the input.
+    pub fn next_token(&mut self) -> (usize, Token) {
+        while self.tokens.is_empty() {
+            self.step();
+        }
+        self.tokens.pop_front().unwrap()
+    }
+
+    fn error(&mut self, msg: &'static str) {
+        self.diagnostics.push(Diagnostic::new(self.parser.pos, msg));
+    }
+
+    fn consume(&mut self) -> Option<char> {
+        if self.parser.eof() {
+            None
+        } else {
+            Some(self.parser.consume_char())
+        }
+    }
+
+    // Go back one character, so it is read again in the next state.
+    fn reconsume(&mut self, c: Option<char>) {
+        if let Some(c) = c {
+            self.parser.pos -= c.len_utf8();
+        }
+    }
+
+    fn push_text(&mut self, c: char) {
+        if self.text.is_empty() {
+            self.text_start = self.parser.pos - c.len_utf8();
+        }
+        self.text.push(c);
+    }
+
+    fn push_text_str(&mut self, s: &str) {
+        if self.text.is_empty() {
+            self.text_start = self.parser.pos;
+        }
+        self.text.push_str(s);
+    }
+
+    fn emit(&mut self, position: usize, token: Token) {
+        if !self.text.is_empty() {
+            let text = std::mem::take(&mut self.text);
+            self.tokens.push_back((self.text_start, Token::Text(text)));
+        }
+        self.tokens.push_back((position, token));
+    }
+
+    fn emit_eof(&mut self) {
+        let position = self.parser.pos;
+        self.emit(position, Token::Eof);
+    }
+
+    fn emit_comment(&mut self) {
+        let comment = std::mem::take(&mut self.comment);
+        let position = self.tag_start;
+        self.emit(position, Token::Comment(comment));
+    }
+
+    fn start_tag(&mut self, end: bool) {
+        self.tag = Tag {
+            end,
+            ..Default::default()
+        };
+    }
+
+    fn start_attribute(&mut self) {
+        self.tag.attributes.push((String::new(), String::new()));
+    }
+
+    fn push_attr_name(&mut self, c: char) {
+        if let Some(attr) = self.tag.attributes.last_mut() {
+            attr.0.push(c);
+        }
+    }
+
+    fn push_attr_value(&mut self, s: &str) {
+        if let Some(attr) = self.tag.attributes.last_mut() {
+            attr.1.push_str(s);
+        }
+    }
+
+    fn emit_tag(&mut self) {
+        let tag = std::mem::take(&mut self.tag);
+        let position = self.tag_start;
+        self.state = State::Data;
+        if tag.end {
+            if !tag.attributes.is_empty() {
+                self.error("end tag with attributes");
+            }
+            if tag.self_closing {
+                self.error("end tag with trailing solidus");
+            }
+            self.emit(position, Token::EndTag { name: tag.name });
+            return;
+        }
+
+        // the content of some elements isn't markup
+        let name = tag.name.as_str();
+        self.state = if RAW_TEXT_ELEMENTS.contains(&name) {
+            State::RawText
+        } else if ESCAPABLE_RAW_TEXT_ELEMENTS.contains(&name) {
+            State::RcData
+        } else if name == "plaintext" {
+            State::PlainText
+        } else {
+            State::Data
+        };
+        self.last_start_tag = tag.name.clone();
+
+        // the first of duplicated attributes wins
+        let mut attributes = HashMap::new();
+        for (name, value) in tag.attributes {
+            match attributes.entry(name) {
+                Entry::Occupied(_) => self.error("duplicate attribute"),
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+            }
+        }
+        self.emit(
+            position,
+            Token::StartTag {
+                name: tag.name,
+                attributes,
+                self_closing: tag.self_closing,
+            },
+        );
+    }
+
+    // Run the state machine on the next character of the input.
+    fn step(&mut self) {
+        let c = self.consume();
+        match self.state {
+            State::Data => match c {
+                Some('&') => self.text_char_ref(),
+                Some('<') => {
+                    self.tag_start = self.parser.pos - 1;
+                    self.state = State::TagOpen;
+                }
+                Some('\0') => self.error("unexpected null character"),
+                Some(c) => self.push_text(c),
+                None => self.emit_eof(),
+            },
+            State::RcData | State::RawText => match c {
+                Some('&') if self.state == State::RcData => self.text_char_ref(),
+                Some('<') => self.raw_text_end_tag(),
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.push_text('\u{fffd}');
+                }
+                Some(c) => self.push_text(c),
+                None => self.emit_eof(),
+            },
+            State::PlainText => match c {
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.push_text('\u{fffd}');
+                }
+                Some(c) => self.push_text(c),
+                None => self.emit_eof(),
+            },
+            State::TagOpen => match c {
+                Some('!') => self.state = State::MarkupDeclarationOpen,
+                Some('/') => self.state = State::EndTagOpen,
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(false);
+                    self.reconsume(Some(c));
+                    self.state = State::TagName;
+                }
+                Some('?') => {
+                    self.error("unexpected question mark instead of tag name");
+                    self.comment.clear();
+                    self.reconsume(c);
+                    self.state = State::BogusComment;
+                }
+                None => {
+                    self.error("eof before tag name");
+                    self.push_text_str("<");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error("invalid first character of tag name");
+                    self.push_text_str("<");
+                    self.reconsume(c);
+                    self.state = State::Data;
+                }
+            },
+            State::EndTagOpen => match c {
+                Some(c) if c.is_ascii_alphabetic() => {
+                    self.start_tag(true);
+                    self.reconsume(Some(c));
+                    self.state = State::TagName;
+                }
+                Some('>') => {
+                    self.error("missing end tag name");
+                    self.state = State::Data;
+                }
+                None => {
+                    self.error("eof before tag name");
+                    self.push_text_str("</");
+                    self.emit_eof();
+                }
+                Some(_) => {
+                    self.error("invalid first character of tag name");
+                    self.comment.clear();
+                    self.reconsume(c);
+                    self.state = State::BogusComment;
+                }
+            },
+            State::TagName => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.tag.name.push('\u{fffd}');
+                }
+                Some(c) => self.tag.name.push(c.to_ascii_lowercase()),
+                None => self.eof_in_tag(),
+            },
+            State::BeforeAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') | Some('>') | None => {
+                    self.reconsume(c);
+                    self.state = State::AfterAttributeName;
+                }
+                Some('=') => {
+                    self.error("unexpected equals sign before attribute name");
+                    self.start_attribute();
+                    self.push_attr_name('=');
+                    self.state = State::AttributeName;
+                }
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(c);
+                    self.state = State::AttributeName;
+                }
+            },
+            State::AttributeName => match c {
+                Some(c) if is_whitespace(c) || c == '/' || c == '>' => {
+                    self.reconsume(Some(c));
+                    self.state = State::AfterAttributeName;
+                }
+                None => self.state = State::AfterAttributeName,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.push_attr_name('\u{fffd}');
+                }
+                Some(c) => {
+                    if c == '"' || c == '\'' || c == '<' {
+                        self.error("unexpected character in attribute name");
+                    }
+                    self.push_attr_name(c.to_ascii_lowercase());
+                }
+            },
+            State::AfterAttributeName => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('=') => self.state = State::BeforeAttributeValue,
+                Some('>') => self.emit_tag(),
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.start_attribute();
+                    self.reconsume(c);
+                    self.state = State::AttributeName;
+                }
+            },
+            State::BeforeAttributeValue => match c {
+                Some(c) if is_whitespace(c) => {}
+                Some('"') => self.state = State::AttributeValueDoubleQuoted,
+                Some('\'') => self.state = State::AttributeValueSingleQuoted,
+                Some('>') => {
+                    self.error("missing attribute value");
+                    self.emit_tag();
+                }
+                _ => {
+                    self.reconsume(c);
+                    self.state = State::AttributeValueUnquoted;
+                }
+            },
+            State::AttributeValueDoubleQuoted | State::AttributeValueSingleQuoted => {
+                let quote = if self.state == State::AttributeValueDoubleQuoted {
+                    '"'
+                } else {
+                    '\''
+                };
+                match c {
+                    Some(c) if c == quote => self.state = State::AfterAttributeValueQuoted,
+                    Some('&') => self.attribute_char_ref(),
+                    Some('\0') => {
+                        self.error("unexpected null character");
+                        self.push_attr_value("\u{fffd}");
+                    }
+                    Some(c) => self.push_attr_value(c.encode_utf8(&mut [0; 4])),
+                    None => self.eof_in_tag(),
+                }
+            }
+            State::AttributeValueUnquoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('&') => self.attribute_char_ref(),
+                Some('>') => self.emit_tag(),
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.push_attr_value("\u{fffd}");
+                }
+                Some(c) => {
+                    if c == '"' || c == '\'' || c == '<' || c == '=' || c == '`' {
+                        self.error("unexpected character in unquoted attribute value");
+                    }
+                    self.push_attr_value(c.encode_utf8(&mut [0; 4]));
+                }
+                None => self.eof_in_tag(),
+            },
+            State::AfterAttributeValueQuoted => match c {
+                Some(c) if is_whitespace(c) => self.state = State::BeforeAttributeName,
+                Some('/') => self.state = State::SelfClosingStartTag,
+                Some('>') => self.emit_tag(),
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.error("missing whitespace between attributes");
+                    self.reconsume(c);
+                    self.state = State::BeforeAttributeName;
+                }
+            },
+            State::SelfClosingStartTag => match c {
+                Some('>') => {
+                    self.tag.self_closing = true;
+                    self.emit_tag();
+                }
+                None => self.eof_in_tag(),
+                Some(_) => {
+                    self.error("unexpected solidus in tag");
+                    self.reconsume(c);
+                    self.state = State::BeforeAttributeName;
+                }
+            },
+            State::BogusComment => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.comment.push('\u{fffd}');
+                }
+                Some(c) => self.comment.push(c),
+                None => {
+                    self.emit_comment();
+                    self.emit_eof();
+                }
+            },
+            State::MarkupDeclarationOpen => {
+                self.reconsume(c);
+                self.markup_declaration_open();
+            }
+            State::CommentStart => match c {
+                Some('-') => self.state = State::CommentStartDash,
+                Some('>') => {
+                    self.error("abrupt closing of empty comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                _ => {
+                    self.reconsume(c);
+                    self.state = State::Comment;
+                }
+            },
+            State::CommentStartDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                Some('>') => {
+                    self.error("abrupt closing of empty comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(c);
+                    self.state = State::Comment;
+                }
+            },
+            State::Comment => match c {
+                Some('-') => self.state = State::CommentEndDash,
+                Some('\0') => {
+                    self.error("unexpected null character");
+                    self.comment.push('\u{fffd}');
+                }
+                Some(c) => self.comment.push(c),
+                None => self.eof_in_comment(),
+            },
+            State::CommentEndDash => match c {
+                Some('-') => self.state = State::CommentEnd,
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push('-');
+                    self.reconsume(c);
+                    self.state = State::Comment;
+                }
+            },
+            State::CommentEnd => match c {
+                Some('>') => {
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                Some('!') => self.state = State::CommentEndBang,
+                Some('-') => self.comment.push('-'),
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push_str("--");
+                    self.reconsume(c);
+                    self.state = State::Comment;
+                }
+            },
+            State::CommentEndBang => match c {
+                Some('-') => {
+                    self.comment.push_str("--!");
+                    self.state = State::CommentEndDash;
+                }
+                Some('>') => {
+                    self.error("incorrectly closed comment");
+                    self.state = State::Data;
+                    self.emit_comment();
+                }
+                None => self.eof_in_comment(),
+                Some(_) => {
+                    self.comment.push_str("--!");
+                    self.reconsume(c);
+                    self.state = State::Comment;
+                }
+            },
+            State::Doctype => {
+                self.reconsume(c);
+                self.doctype();
+            }
+            State::CdataSection => {
+                self.reconsume(c);
+                self.cdata_section();
+            }
+        }
+    }
+
+    fn eof_in_tag(&mut self) {
+        self.error("eof in tag");
+        self.emit_eof();
+    }
+
+    fn eof_in_comment(&mut self) {
+        self.error("eof in comment");
+        self.emit_comment();
+        self.emit_eof();
+    }
+
+    // After `<` in raw text, only the end tag of the element ends the text.
+    fn raw_text_end_tag(&mut self) {
+        let rest = &self.parser.input[self.parser.pos..];
+        let name_len = self.last_start_tag.len();
+        let is_end_tag = rest.starts_with('/')
+            && rest
+                .get(1..1 + name_len)
+                .is_some_and(|name| name.eq_ignore_ascii_case(&self.last_start_tag))
+            && rest[1 + name_len..]
+                .chars()
+                .next()
+                .is_some_and(|c| is_whitespace(c) || c == '/' || c == '>');
+
+        if is_end_tag {
+            self.tag_start = self.parser.pos - 1;
+            self.parser.pos += 1 + name_len;
+            self.start_tag(true);
+            self.tag.name = self.last_start_tag.clone();
+            self.state = State::TagName;
+        } else {
+            self.push_text('<');
+        }
+    }
+
+    fn markup_declaration_open(&mut self) {
+        if self.parser.starts_with("--") {
+            self.parser.pos += 2;
+            self.comment.clear();
+            self.state = State::CommentStart;
+        } else if self
+            .parser
+            .input
+            .get(self.parser.pos..self.parser.pos + 7)
+            .is_some_and(|s| s.eq_ignore_ascii_case("doctype"))
+        {
+            self.parser.pos += 7;
+            self.state = State::Doctype;
+        } else if self.parser.starts_with("[CDATA[") {
+            self.parser.pos += 7;
+            if self.allow_cdata {
+                self.state = State::CdataSection;
+            } else {
+                self.error("cdata in html content");
+                self.comment = "[CDATA[".to_string();
+                self.state = State::BogusComment;
+            }
+        } else {
+            self.error("incorrectly opened comment");
+            self.comment.clear();
+            self.state = State::BogusComment;
+        }
+    }
+
+    // Only the name of a doctype is kept, public and system identifiers are skipped.
+    fn doctype(&mut self) {
+        self.parser.consume_while(is_whitespace);
+        let name = self
+            .parser
+            .consume_while(|c| !is_whitespace(c) && c != '>')
+            .to_ascii_lowercase();
+        if name.is_empty() {
+            self.error("missing doctype name");
+        }
+        self.parser.consume_while(|c| c != '>');
+        let position = self.tag_start;
+        self.state = State::Data;
+        if self.parser.eof() {
+            self.error("eof in doctype");
+            self.emit(position, Token::Doctype(name));
+            self.emit_eof();
+            return;
+        }
+        self.parser.consume_char();
+        self.emit(position, Token::Doctype(name));
+    }
+
+    fn cdata_section(&mut self) {
+        let rest = &self.parser.input[self.parser.pos..];
+        let (text, len) = match rest.find("]]>") {
+            Some(end) => (rest[..end].to_string(), end + 3),
+            None => (rest.to_string(), rest.len()),
+        };
+        self.push_text_str(&text);
+        self.parser.pos += len;
+        self.state = State::Data;
+        if self.parser.eof() && len == text.len() {
+            self.error("eof in cdata");
+        }
+    }
+
+    fn text_char_ref(&mut self) {
+        match self.consume_char_ref(false) {
+            Some(text) => self.push_text_str(&text),
+            None => self.push_text('&'),
+        }
+    }
+
+    fn attribute_char_ref(&mut self) {
+        let text = self
+            .consume_char_ref(true)
+            .unwrap_or_else(|| "&".to_string());
+        self.push_attr_value(&text);
+    }
+
+    /// Read a character reference following `&` and return its text,
+    /// `None` when the `&` doesn't start a reference.
+    fn consume_char_ref(&mut self, in_attribute: bool) -> Option<String> {
+        let rest = &self.parser.input[self.parser.pos..];
+        if rest.starts_with('#') {
+            return self.consume_numeric_char_ref();
+        }
+
+        let alphanumeric = rest
+            .bytes()
+            .take_while(|b| b.is_ascii_alphanumeric())
+            .count();
+        if alphanumeric == 0 {
+            return None;
+        }
+        let with_semicolon = alphanumeric + rest[alphanumeric..].starts_with(';') as usize;
+
+        // the longest name matches, `&notit;` is `¬it;`
+        let max_len = with_semicolon.min(entities::LONGEST_NAME);
+        let found = (1..=max_len)
+            .rev()
+            .find_map(|len| entities::lookup(&rest[..len]).map(|text| (len, text)));
+
+        match found {
+            Some((len, text)) => {
+                let name = &rest[..len];
+                let next = rest[len..].chars().next();
+                if !name.ends_with(';') {
+                    // historical attribute values like `?a=1&copy=2` are left alone
+                    if in_attribute && next.is_some_and(|c| c == '=' || c.is_ascii_alphanumeric()) {
+                        return None;
+                    }
+                    self.error("missing semicolon after character reference");
+                }
+                self.parser.pos += len;
+                Some(text.to_string())
+            }
+            None => {
+                if with_semicolon > alphanumeric {
+                    self.error("unknown named character reference");
+                }
+                None
+            }
+        }
+    }
+
+    fn consume_numeric_char_ref(&mut self) -> Option<String> {
+        let start = self.parser.pos;
+        self.parser.pos += 1; // #
+        let hex = self.parser.starts_with("x") || self.parser.starts_with("X");
+        if hex {
+            self.parser.pos += 1;
+        }
+        let digits = if hex {
+            self.parser.consume_while(|c| c.is_ascii_hexdigit())
+        } else {
+            self.parser.consume_while(|c| c.is_ascii_digit())
+        };
+        if digits.is_empty() {
+            self.error("absence of digits in numeric character reference");
+            self.parser.pos = start;
+            return None;
+        }
+        if self.parser.starts_with(";") {
+            self.parser.pos += 1;
+        } else {
+            self.error("missing semicolon after character reference");
+        }
+
+        let code = u32::from_str_radix(&digits, if hex { 16 } else { 10 }).unwrap_or(0x11_0000);
+        let c = match code {
+            0 => {
+                self.error("null character reference");
+                '\u{fffd}'
+            }
+            0xd800..=0xdfff => {
+                self.error("surrogate character reference");
+                '\u{fffd}'
+            }
+            0x80..=0x9f => {
+                self.error("control character reference");
+                windows_1252(code)
+            }
+            _ => match std::char::from_u32(code) {
+                Some(c) => {
+                    if c.is_control() && !is_whitespace(c) {
+                        self.error("control character reference");
+                    }
+                    c
+                }
+                None => {
+                    self.error("character reference outside unicode range");
+                    '\u{fffd}'
+                }
+            },
+        };
+        Some(c.to_string())
+    }
+}
+
+fn is_whitespace(c: char) -> bool {
+    matches!(c, '\t' | '\n' | '\x0c' | ' ')
+}
+
+/// References to C1 controls mean the character at that position in windows-1252.
+fn windows_1252(code: u32) -> char {
+    let c = match code {
+        0x80 => 0x20ac,
+        0x82 => 0x201a,
+        0x83 => 0x0192,
+        0x84 => 0x201e,
+        0x85 => 0x2026,
+        0x86 => 0x2020,
+        0x87 => 0x2021,
+        0x88 => 0x02c6,
+        0x89 => 0x2030,
+        0x8a => 0x0160,
+        0x8b => 0x2039,
+        0x8c => 0x0152,
+        0x8e => 0x017d,
+        0x91 => 0x2018,
+        0x92 => 0x2019,
+        0x93 => 0x201c,
+        0x94 => 0x201d,
+        0x95 => 0x2022,
+        0x96 => 0x2013,
+        0x97 => 0x2014,
+        0x98 => 0x02dc,
+        0x99 => 0x2122,
+        0x9a => 0x0161,
+        0x9b => 0x203a,
+        0x9c => 0x0153,
+        0x9e => 0x017e,
+        0x9f => 0x0178,
+        _ => code,
+    };
+    std::char::from_u32(c).unwrap_or('\u{fffd}')
+}
+
+#[cfg(test)]
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokenizer = Tokenizer::new(source.to_string());
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token().1 {
+            Token::Eof => return tokens,
+            token => tokens.push(token),
+        }
+    }
+}
+
+#[cfg(test)]
+fn text(s: &str) -> Token {
+    Token::Text(s.to_string())
+}
+
+#[test]
+fn test_character_references() {
+    assert_eq!(
+        tokenize("&amp; &lt;b&gt; a&nbsp;b &#x263A; &#9731; &copy &notit; &foo; &#128;"),
+        vec![text(
+            "& <b> a\u{a0}b \u{263a} \u{2603} \u{a9} \u{ac}it; &foo; \u{20ac}"
+        )]
+    );
+    assert_eq!(tokenize("&#0; &#; &"), vec![text("\u{fffd} &#; &")]);
+}
+
+#[test]
+fn test_comments_and_doctype() {
+    assert_eq!(
+        tokenize("<!DOCTYPE html><!-- a <b> -- c -->x<!-->"),
+        vec![
+            Token::Doctype("html".to_string()),
+            Token::Comment(" a <b> -- c ".to_string()),
+            text("x"),
+            Token::Comment(String::new()),
+        ]
+    );
+    assert_eq!(
+        tokenize("<![CDATA[x<y]]>"),
+        vec![Token::Comment("[CDATA[x<y]]".to_string())]
+    );
+}
+
+#[test]
+fn test_raw_text_elements() {
+    let style = Token::StartTag {
+        name: "style".to_string(),
+        attributes: HashMap::new(),
+        self_closing: false,
+    };
+    assert_eq!(
+        tokenize("<STYLE>a < b { content: \"</p>&amp;\" }</style >"),
+        vec![
+            style,
+            text("a < b { content: \"</p>&amp;\" }"),
+            Token::EndTag {
+                name: "style".to_string()
+            },
+        ]
+    );
+
+    let title = Token::StartTag {
+        name: "title".to_string(),
+        attributes: HashMap::new(),
+        self_closing: false,
+    };
+    assert_eq!(
+        tokenize("<title><b>&amp;</title>"),
+        vec![
+            title,
+            text("<b>&"),
+            Token::EndTag {
+                name: "title".to_string()
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_attribute_values() {
+    let mut attributes = HashMap::new();
+    attributes.insert("href".to_string(), "?a=1&copy=2&\u{a9}".to_string());
+    attributes.insert("title".to_string(), "it's".to_string());
+    assert_eq!(
+        tokenize("<a href=\"?a=1&copy=2&amp;&copy\" title='it&apos;s'/>"),
+        vec![Token::StartTag {
+            name: "a".to_string(),
+            attributes,
+            self_closing: true,
+        }]
+    );
+}
+
+#[test]
+fn test_attribute_syntaxes() {
+    let mut tokenizer =
+        Tokenizer::new("<INPUT Disabled TYPE=checkbox value=a&amp;b type=\"radio\">".to_string());
+    let mut attributes = HashMap::new();
+    attributes.insert("disabled".to_string(), String::new());
+    attributes.insert("type".to_string(), "checkbox".to_string());
+    attributes.insert("value".to_string(), "a&b".to_string());
+    assert_eq!(
+        tokenizer.next_token().1,
+        Token::StartTag {
+            name: "input".to_string(),
+            attributes,
+            self_closing: false,
+        }
+    );
+    assert_eq!(tokenizer.diagnostics.len(), 1);
+}
@@ -1,65 +1,101 @@
-use super::css::{LengthValue, Unit};
+/// Numbers and lengths in CSS values
+///
+/// A number is an optional sign, then digits with an optional fraction and
+/// exponent: `12`, `-4`, `1.5`, `.5`, `+2e3`. A length is a number directly
+/// followed by a unit, `%` counts as one.
+use super::css::Unit;
 
-use regex::Regex;
+/// A length like `1.5em`, shorthands split their values beforehand.
+/// A number without a unit is not a length.
+pub fn parse_length(text: &str) -> Option<(f32, Unit)> {
+    let (number, unit) = parse_dimension(text.trim())?;
+    Some((number, parse_unit(&unit.to_ascii_lowercase())?))
+}
 
-lazy_static! {
-    static ref LENGTH_RE: Regex = Regex::new(r"((\d+)(px|em))+").unwrap();
+/// A number without a unit, like `1.5`
+pub fn parse_number(text: &str) -> Option<f32> {
+    match parse_dimension(text.trim())? {
+        (number, "") => Some(number),
+        _ => None,
+    }
 }
 
-pub fn parse_length(text: &str) -> Option<(LengthValue, Unit)> {
-    let res: Vec<_> = LENGTH_RE
-        .captures_iter(text)
-        .map(|cap| match (cap.get(2), cap.get(3)) {
-            (Some(num), Some(unit)) => Some((num.as_str(), unit.as_str())),
-            _ => None,
-        })
-        .filter(|c| !c.is_none())
-        .collect();
+// Split a number from the text after it, `None` if `text` doesn't start
+// with a number
+fn parse_dimension(text: &str) -> Option<(f32, &str)> {
+    let bytes = text.as_bytes();
+    let digits = |from: usize| {
+        from + bytes[from..]
+            .iter()
+            .take_while(|b| b.is_ascii_digit())
+            .count()
+    };
+    let is_digit = |at: usize| bytes.get(at).is_some_and(u8::is_ascii_digit);
 
-    match res.len() {
-        1 => {
-            let u = res[0].unwrap();
-            return Some((LengthValue::Single(parse_num(u.0)), parse_unit(u.1)));
-        }
-        2 => {
-            let u = res[0].unwrap();
-            let v = res[1].unwrap();
-            return Some((
-                LengthValue::All(
-                    parse_num(u.0),
-                    parse_num(v.0),
-                    parse_num(u.0),
-                    parse_num(v.0),
-                ),
-                parse_unit(u.1),
-            ));
-        }
-        4 => {
-            let u = res[0].unwrap();
-            let v = res[1].unwrap();
-            let w = res[2].unwrap();
-            let z = res[3].unwrap();
-            return Some((
-                LengthValue::All(
-                    parse_num(u.0),
-                    parse_num(v.0),
-                    parse_num(w.0),
-                    parse_num(z.0),
-                ),
-                parse_unit(u.1),
-            ));
+    let sign = match bytes.first() {
+        Some(b'+') | Some(b'-') => 1,
+        _ => 0,
+    };
+    let mut end = digits(sign);
+    // a `.` must be followed by digits, `1.em` is not a length
+    if bytes.get(end) == Some(&b'.') && is_digit(end + 1) {
+        end = digits(end + 1);
+    } else if end == sign {
+        return None;
+    }
+    // `1e3` has an exponent, but `1em` has a unit
+    if let Some(b'e') | Some(b'E') = bytes.get(end) {
+        let exponent_sign = match bytes.get(end + 1) {
+            Some(b'+') | Some(b'-') => 1,
+            _ => 0,
+        };
+        if is_digit(end + 1 + exponent_sign) {
+            end = digits(end + 1 + exponent_sign);
         }
-        _ => return None,
     }
+
+    let number = text[..end].parse().ok()?;
+    Some((number, &text[end..]))
 }
 
-fn parse_unit(unit_text: &str) -> Unit {
-    match unit_text {
+fn parse_unit(unit_text: &str) -> Option<Unit> {
+    Some(match unit_text {
+        "px" => Unit::Px,
         "em" => Unit::Em,
-        _ => Unit::Px,
-    }
+        "rem" => Unit::Rem,
+        "ex" => Unit::Ex,
+        "ch" => Unit::Ch,
+        "%" => Unit::Percent,
+        "vw" => Unit::Vw,
+        "vh" => Unit::Vh,
+        "vmin" => Unit::Vmin,
+        "vmax" => Unit::Vmax,
+        "pt" => Unit::Pt,
+        "pc" => Unit::Pc,
+        "in" => Unit::In,
+        "cm" => Unit::Cm,
+        "mm" => Unit::Mm,
+        _ => return None,
+    })
 }
 
-fn parse_num(num_text: &str) -> i32 {
-    num_text.parse::<i32>().unwrap_or(0)
+#[test]
+fn test_parse_length() {
+    assert_eq!(parse_length("1.5em"), Some((1.5, Unit::Em)));
+    assert_eq!(parse_length("-4px"), Some((-4.0, Unit::Px)));
+    assert_eq!(parse_length("50%"), Some((50.0, Unit::Percent)));
+    assert_eq!(parse_length(".5REM"), Some((0.5, Unit::Rem)));
+    assert_eq!(parse_length("+1e1vw"), Some((10.0, Unit::Vw)));
+    assert_eq!(parse_length("2ex"), Some((2.0, Unit::Ex)));
+    assert_eq!(parse_length("12pt"), Some((12.0, Unit::Pt)));
+    assert_eq!(parse_length("0"), None);
+    assert_eq!(parse_length("1.em"), None);
+    assert_eq!(parse_length("10 px"), None);
+    assert_eq!(parse_length("auto"), None);
+
+    assert_eq!(parse_number("0"), Some(0.0));
+    assert_eq!(parse_number("-1.25"), Some(-1.25));
+    assert_eq!(parse_number("3e-1"), Some(0.3));
+    assert_eq!(parse_number("1px"), None);
+    assert_eq!(parse_number("."), None);
 }
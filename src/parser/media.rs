@@ -0,0 +1,645 @@
+/// Media queries
+///
+/// The prelude of `@media screen and (min-width: 600px), print { ... }` is a
+/// list of queries, the rules of the block apply while any of them matches
+/// the `MediaEnvironment` the page is shown in. A query is a media type, a
+/// condition on media features, or both joined by `and`.
+///
+/// Features are compared in the old `(min-width: 600px)` form or with the
+/// range syntax, `(400px < width <= 700px)`. A feature or function the
+/// browser doesn't know is neither true nor false, and a query that can't
+/// be parsed matches nothing, like `not all`.
+use super::css::Unit;
+use super::css_tokenizer::{Token, Tokenizer};
+use super::length;
+use crate::computed::{self, Environment};
+use crate::font;
+
+/// What media queries are evaluated against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MediaEnvironment {
+    pub media_type: MediaType,
+    /// Size of the viewport in px
+    pub width: f32,
+    pub height: f32,
+    /// Device pixels per px
+    pub resolution: f32,
+    pub color_scheme: ColorScheme,
+}
+
+impl Default for MediaEnvironment {
+    fn default() -> MediaEnvironment {
+        MediaEnvironment {
+            media_type: MediaType::Screen,
+            width: 800.0,
+            height: 600.0,
+            resolution: 1.0,
+            color_scheme: ColorScheme::Light,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MediaType {
+    Screen,
+    Print,
+}
+
+/// The color scheme the reader prefers, for `prefers-color-scheme`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+/// Queries separated by `,`, an empty list matches everything
+#[derive(Debug, Clone)]
+pub struct MediaQueryList(Vec<MediaQuery>);
+
+impl MediaQueryList {
+    pub fn matches(&self, environment: &MediaEnvironment) -> bool {
+        self.0.is_empty() || self.0.iter().any(|query| query.matches(environment))
+    }
+}
+
+/// `[not | only] <type> [and <condition>]`, or a condition alone
+#[derive(Debug, Clone)]
+struct MediaQuery {
+    negated: bool,
+    /// `None` for all media types
+    media_type: Option<String>,
+    condition: Option<MediaCondition>,
+}
+
+impl MediaQuery {
+    // `not all`
+    fn nothing() -> MediaQuery {
+        MediaQuery {
+            negated: true,
+            media_type: None,
+            condition: None,
+        }
+    }
+
+    fn matches(&self, environment: &MediaEnvironment) -> bool {
+        let media_type = match self.media_type.as_deref() {
+            None | Some("all") => true,
+            Some("screen") => environment.media_type == MediaType::Screen,
+            Some("print") => environment.media_type == MediaType::Print,
+            // `tv`, `handheld` and the other types of CSS 2 match nothing
+            Some(_) => false,
+        };
+        let condition = match self.condition {
+            // an unknown result is false here
+            Some(ref condition) => condition.evaluate(environment) == Some(true),
+            None => true,
+        };
+        (media_type && condition) != self.negated
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MediaCondition {
+    Feature(MediaFeature),
+    Not(Box<MediaCondition>),
+    And(Vec<MediaCondition>),
+    Or(Vec<MediaCondition>),
+    /// A feature or function we don't know
+    Unknown,
+}
+
+impl MediaCondition {
+    // `None` when the result is unknown, `not` of an unknown condition is
+    // still unknown
+    fn evaluate(&self, environment: &MediaEnvironment) -> Option<bool> {
+        match self {
+            MediaCondition::Feature(feature) => Some(feature.evaluate(environment)),
+            MediaCondition::Not(condition) => condition.evaluate(environment).map(|result| !result),
+            MediaCondition::And(conditions) => {
+                let results: Vec<Option<bool>> = conditions
+                    .iter()
+                    .map(|condition| condition.evaluate(environment))
+                    .collect();
+                if results.contains(&Some(false)) {
+                    Some(false)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(true)
+                }
+            }
+            MediaCondition::Or(conditions) => {
+                let results: Vec<Option<bool>> = conditions
+                    .iter()
+                    .map(|condition| condition.evaluate(environment))
+                    .collect();
+                if results.contains(&Some(true)) {
+                    Some(true)
+                } else if results.contains(&None) {
+                    None
+                } else {
+                    Some(false)
+                }
+            }
+            MediaCondition::Unknown => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MediaFeature {
+    /// A feature compared with values, `(min-width: 600px)` is
+    /// `width >= 600px` and `(width)` alone is `width > 0`
+    Range(RangeFeature, Vec<(Comparison, FeatureValue)>),
+    /// `None` for `(orientation)` alone, which is always true
+    Orientation(Option<Orientation>),
+    PrefersColorScheme(Option<ColorScheme>),
+}
+
+impl MediaFeature {
+    fn evaluate(&self, environment: &MediaEnvironment) -> bool {
+        match self {
+            MediaFeature::Range(feature, comparisons) => {
+                let value = match feature {
+                    RangeFeature::Width => environment.width,
+                    RangeFeature::Height => environment.height,
+                    RangeFeature::Resolution => environment.resolution,
+                };
+                comparisons.iter().all(|(comparison, other)| {
+                    other
+                        .to_f32(environment)
+                        .is_some_and(|other| comparison.holds(value, other))
+                })
+            }
+            MediaFeature::Orientation(orientation) => {
+                let actual = if environment.height >= environment.width {
+                    Orientation::Portrait
+                } else {
+                    Orientation::Landscape
+                };
+                orientation.is_none_or(|orientation| orientation == actual)
+            }
+            MediaFeature::PrefersColorScheme(scheme) => {
+                scheme.is_none_or(|scheme| scheme == environment.color_scheme)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RangeFeature {
+    Width,
+    Height,
+    Resolution,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FeatureValue {
+    Length(f32, Unit),
+    /// In dppx
+    Resolution(f32),
+}
+
+impl FeatureValue {
+    // Lengths in px, relative ones are relative to the initial font size
+    fn to_f32(self, environment: &MediaEnvironment) -> Option<f32> {
+        match self {
+            FeatureValue::Length(value, unit) => computed::absolute_length(
+                value,
+                unit,
+                font::DEFAULT_FONT_SIZE,
+                &Environment::new(environment.width, environment.height),
+            ),
+            FeatureValue::Resolution(dppx) => Some(dppx),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Orientation {
+    Portrait,
+    Landscape,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Equal,
+    GreaterOrEqual,
+    Greater,
+}
+
+impl Comparison {
+    // `a <= b` becomes `b >= a`
+    fn flip(self) -> Comparison {
+        match self {
+            Comparison::Less => Comparison::Greater,
+            Comparison::LessOrEqual => Comparison::GreaterOrEqual,
+            Comparison::Equal => Comparison::Equal,
+            Comparison::GreaterOrEqual => Comparison::LessOrEqual,
+            Comparison::Greater => Comparison::Less,
+        }
+    }
+
+    fn holds(self, a: f32, b: f32) -> bool {
+        match self {
+            Comparison::Less => a < b,
+            Comparison::LessOrEqual => a <= b,
+            Comparison::Equal => a == b,
+            Comparison::GreaterOrEqual => a >= b,
+            Comparison::Greater => a > b,
+        }
+    }
+}
+
+/// Parse the prelude of `@media`
+pub fn parse_media_query_list(text: &str) -> MediaQueryList {
+    let mut tokenizer = Tokenizer::new(text);
+    let mut tokens = Vec::new();
+    loop {
+        match tokenizer.next_token().1 {
+            Token::Eof => break,
+            token => tokens.push(token),
+        }
+    }
+    if tokens.iter().all(|token| *token == Token::Whitespace) {
+        return MediaQueryList(Vec::new());
+    }
+    let queries = split_top_level(&tokens)
+        .into_iter()
+        .map(|query| parse_media_query(query).unwrap_or_else(MediaQuery::nothing))
+        .collect();
+    MediaQueryList(queries)
+}
+
+// A piece of a query: a word, or what is inside a pair of parentheses
+#[derive(Debug)]
+enum Part<'t> {
+    Word(String),
+    Parens(&'t [Token]),
+    /// A function, which no feature is
+    Function,
+    Other,
+}
+
+fn parse_media_query(tokens: &[Token]) -> Option<MediaQuery> {
+    let parts = parts(tokens)?;
+    let word = |index: usize| match parts.get(index) {
+        Some(Part::Word(word)) => Some(word.as_str()),
+        _ => None,
+    };
+
+    // `not (color)` is a condition, `not screen` a negated media type
+    let (negated, type_index) = match (word(0), word(1)) {
+        (Some("not"), Some(_)) => (true, 1),
+        (Some("only"), Some(_)) => (false, 1),
+        (Some("not"), None) => return condition(&parts).map(condition_query),
+        (Some(_), _) => (false, 0),
+        (None, _) => return condition(&parts).map(condition_query),
+    };
+    let media_type = word(type_index)?;
+    if matches!(media_type, "not" | "only" | "and" | "or") {
+        return None;
+    }
+    let condition = match parts.get(type_index + 1) {
+        None => None,
+        Some(Part::Word(and)) if and == "and" => Some(condition(&parts[type_index + 2..])?),
+        Some(_) => return None,
+    };
+    Some(MediaQuery {
+        negated,
+        media_type: Some(media_type.to_string()),
+        condition,
+    })
+}
+
+fn condition_query(condition: MediaCondition) -> MediaQuery {
+    MediaQuery {
+        negated: false,
+        media_type: None,
+        condition: Some(condition),
+    }
+}
+
+// `not <in parens>`, or conditions in parentheses all joined by `and` or
+// all joined by `or`
+fn condition(parts: &[Part]) -> Option<MediaCondition> {
+    match parts {
+        [Part::Word(not), part] if not == "not" => {
+            return Some(MediaCondition::Not(Box::new(in_parens(part)?)))
+        }
+        [] => return None,
+        _ => {}
+    }
+
+    let mut conditions = vec![in_parens(&parts[0])?];
+    let mut connective = None;
+    for pair in parts[1..].chunks(2) {
+        let (word, part) = match pair {
+            [Part::Word(word), part] if word == "and" || word == "or" => (word, part),
+            _ => return None,
+        };
+        if *connective.get_or_insert(word) != word {
+            return None;
+        }
+        conditions.push(in_parens(part)?);
+    }
+    Some(match connective.map(String::as_str) {
+        None => conditions.remove(0),
+        Some("and") => MediaCondition::And(conditions),
+        _ => MediaCondition::Or(conditions),
+    })
+}
+
+// `(<condition>)` or `(<feature>)`, anything else in parentheses is unknown
+fn in_parens(part: &Part) -> Option<MediaCondition> {
+    match part {
+        Part::Parens(tokens) => {
+            if let Some(feature) = feature(tokens) {
+                return Some(feature);
+            }
+            match parts(tokens).as_deref().and_then(condition) {
+                Some(condition) => Some(condition),
+                None => Some(MediaCondition::Unknown),
+            }
+        }
+        Part::Function => Some(MediaCondition::Unknown),
+        _ => None,
+    }
+}
+
+// `name`, `name: value` or a range like `value < name <= value`. `None` when
+// it isn't a feature at all, an unknown feature or value is `Unknown`.
+fn feature(tokens: &[Token]) -> Option<MediaCondition> {
+    let tokens: Vec<&Token> = tokens
+        .iter()
+        .filter(|token| **token != Token::Whitespace)
+        .collect();
+    let known = |feature: Option<MediaFeature>| {
+        Some(feature.map_or(MediaCondition::Unknown, MediaCondition::Feature))
+    };
+    match tokens.as_slice() {
+        [Token::Ident(name)] => known(boolean_feature(&name.to_ascii_lowercase())),
+        [Token::Ident(name), Token::Colon, value] => {
+            known(plain_feature(&name.to_ascii_lowercase(), value))
+        }
+        [Token::Ident(_), Token::Colon, ..] => Some(MediaCondition::Unknown),
+        _ => range(&tokens),
+    }
+}
+
+fn boolean_feature(name: &str) -> Option<MediaFeature> {
+    Some(match name {
+        "orientation" => MediaFeature::Orientation(None),
+        "prefers-color-scheme" => MediaFeature::PrefersColorScheme(None),
+        name => {
+            let feature = range_feature(name)?;
+            let zero = match feature {
+                RangeFeature::Resolution => FeatureValue::Resolution(0.0),
+                _ => FeatureValue::Length(0.0, Unit::Px),
+            };
+            MediaFeature::Range(feature, vec![(Comparison::Greater, zero)])
+        }
+    })
+}
+
+// `(min-width: 600px)` and the like
+fn plain_feature(name: &str, value: &Token) -> Option<MediaFeature> {
+    let keyword = match value {
+        Token::Ident(keyword) => keyword.to_ascii_lowercase(),
+        _ => String::new(),
+    };
+    match name {
+        "orientation" => {
+            let orientation = match keyword.as_str() {
+                "portrait" => Orientation::Portrait,
+                "landscape" => Orientation::Landscape,
+                _ => return None,
+            };
+            return Some(MediaFeature::Orientation(Some(orientation)));
+        }
+        "prefers-color-scheme" => {
+            let scheme = match keyword.as_str() {
+                "light" => ColorScheme::Light,
+                "dark" => ColorScheme::Dark,
+                _ => return None,
+            };
+            return Some(MediaFeature::PrefersColorScheme(Some(scheme)));
+        }
+        _ => {}
+    }
+    let (comparison, name) = if let Some(name) = name.strip_prefix("min-") {
+        (Comparison::GreaterOrEqual, name)
+    } else if let Some(name) = name.strip_prefix("max-") {
+        (Comparison::LessOrEqual, name)
+    } else {
+        (Comparison::Equal, name)
+    };
+    let feature = range_feature(name)?;
+    let value = feature_value(feature, value)?;
+    Some(MediaFeature::Range(feature, vec![(comparison, value)]))
+}
+
+// `name < value`, `value < name` or `value < name < value`, with `<`,
+// `<=`, `>`, `>=` or `=`
+fn range(tokens: &[&Token]) -> Option<MediaCondition> {
+    let mut items: Vec<&Token> = Vec::new();
+    let mut comparisons = Vec::new();
+    let mut rest = tokens;
+    loop {
+        let (item, after) = rest.split_first()?;
+        items.push(item);
+        if after.is_empty() {
+            break;
+        }
+        let (comparison, after) = match after {
+            [Token::Delim('<'), Token::Delim('='), after @ ..] => (Comparison::LessOrEqual, after),
+            [Token::Delim('>'), Token::Delim('='), after @ ..] => {
+                (Comparison::GreaterOrEqual, after)
+            }
+            [Token::Delim('<'), after @ ..] => (Comparison::Less, after),
+            [Token::Delim('>'), after @ ..] => (Comparison::Greater, after),
+            [Token::Delim('='), after @ ..] => (Comparison::Equal, after),
+            _ => return None,
+        };
+        comparisons.push(comparison);
+        rest = after;
+    }
+
+    let name = |token: &Token| match token {
+        Token::Ident(name) => Some(name.to_ascii_lowercase()),
+        _ => None,
+    };
+    let (name, constraints) = match (items.as_slice(), comparisons.as_slice()) {
+        ([a, b], [comparison]) => match name(a) {
+            Some(name) => (name, vec![(*comparison, *b)]),
+            None => (name(b)?, vec![(comparison.flip(), *a)]),
+        },
+        ([a, b, c], [first, second]) => (name(b)?, vec![(first.flip(), *a), (*second, *c)]),
+        _ => return None,
+    };
+
+    let feature = match range_feature(&name) {
+        Some(feature) => feature,
+        None => return Some(MediaCondition::Unknown),
+    };
+    let mut comparisons = Vec::new();
+    for (comparison, value) in constraints {
+        match feature_value(feature, value) {
+            Some(value) => comparisons.push((comparison, value)),
+            None => return Some(MediaCondition::Unknown),
+        }
+    }
+    Some(MediaCondition::Feature(MediaFeature::Range(
+        feature,
+        comparisons,
+    )))
+}
+
+fn range_feature(name: &str) -> Option<RangeFeature> {
+    match name {
+        "width" => Some(RangeFeature::Width),
+        "height" => Some(RangeFeature::Height),
+        "resolution" => Some(RangeFeature::Resolution),
+        _ => None,
+    }
+}
+
+fn feature_value(feature: RangeFeature, value: &Token) -> Option<FeatureValue> {
+    let (number, unit) = match value {
+        Token::Number(number) if length::parse_number(number) == Some(0.0) => {
+            return match feature {
+                RangeFeature::Resolution => None,
+                _ => Some(FeatureValue::Length(0.0, Unit::Px)),
+            }
+        }
+        Token::Dimension(number, unit) => (length::parse_number(number)?, unit),
+        _ => return None,
+    };
+    if feature == RangeFeature::Resolution {
+        return match unit.to_ascii_lowercase().as_str() {
+            "dppx" | "x" => Some(FeatureValue::Resolution(number)),
+            "dpi" => Some(FeatureValue::Resolution(number / 96.0)),
+            "dpcm" => Some(FeatureValue::Resolution(number * 2.54 / 96.0)),
+            _ => None,
+        };
+    }
+    match length::parse_length(&format!("{}{}", number, unit))? {
+        (_, Unit::Percent) => None,
+        (number, unit) => Some(FeatureValue::Length(number, unit)),
+    }
+}
+
+// Words, parenthesized groups and functions, `None` when the parentheses
+// don't match
+fn parts(tokens: &[Token]) -> Option<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut index = 0;
+    while index < tokens.len() {
+        let part = match &tokens[index] {
+            Token::Whitespace => {
+                index += 1;
+                continue;
+            }
+            Token::OpenParen | Token::Function(_) => {
+                let close = index + closing_paren(&tokens[index + 1..])? + 1;
+                let part = match tokens[index] {
+                    Token::OpenParen => Part::Parens(&tokens[index + 1..close]),
+                    _ => Part::Function,
+                };
+                index = close;
+                part
+            }
+            Token::Ident(word) => Part::Word(word.to_ascii_lowercase()),
+            _ => Part::Other,
+        };
+        parts.push(part);
+        index += 1;
+    }
+    Some(parts)
+}
+
+// Position of the `)` closing a group, over nested ones
+fn closing_paren(tokens: &[Token]) -> Option<usize> {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::OpenParen | Token::Function(_) => depth += 1,
+            Token::CloseParen if depth == 0 => return Some(index),
+            Token::CloseParen => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
+
+// Split on the commas outside of parentheses
+fn split_top_level(tokens: &[Token]) -> Vec<&[Token]> {
+    let mut queries = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::OpenParen | Token::Function(_) => depth += 1,
+            Token::CloseParen => depth -= 1,
+            Token::Comma if depth == 0 => {
+                queries.push(&tokens[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    queries.push(&tokens[start..]);
+    queries
+}
+
+#[test]
+fn test_media_queries() {
+    let screen = MediaEnvironment::default();
+    let print = MediaEnvironment {
+        media_type: MediaType::Print,
+        width: 500.0,
+        height: 700.0,
+        resolution: 2.0,
+        color_scheme: ColorScheme::Dark,
+    };
+    let matches = |query: &str| {
+        let list = parse_media_query_list(query);
+        (list.matches(&screen), list.matches(&print))
+    };
+
+    assert_eq!(matches(""), (true, true));
+    assert_eq!(matches("all"), (true, true));
+    assert_eq!(matches("screen"), (true, false));
+    assert_eq!(matches("only print"), (false, true));
+    assert_eq!(matches("not screen"), (false, true));
+    assert_eq!(matches("tv, Print"), (false, true));
+    assert_eq!(matches("(max-width: 600px)"), (false, true));
+    assert_eq!(matches("screen and (min-width: 50em)"), (true, false));
+    assert_eq!(matches("(width >= 600px)"), (true, false));
+    assert_eq!(matches("(400px < width <= 500px)"), (false, true));
+    assert_eq!(
+        matches("(800px <= width) and (height > 7in)"),
+        (false, false)
+    );
+    assert_eq!(matches("(orientation: portrait)"), (false, true));
+    assert_eq!(matches("(orientation)"), (true, true));
+    assert_eq!(matches("(prefers-color-scheme: dark)"), (false, true));
+    assert_eq!(matches("(min-resolution: 2dppx)"), (false, true));
+    assert_eq!(matches("(resolution: 96dpi)"), (true, false));
+    assert_eq!(
+        matches("not ((width < 600px) or (orientation: landscape))"),
+        (false, false)
+    );
+    assert_eq!(matches("screen and not (width < 600px)"), (true, false));
+
+    // unknown features are neither true nor false, invalid queries match nothing
+    assert_eq!(matches("(hover: hover)"), (false, false));
+    assert_eq!(matches("not (hover: hover)"), (false, false));
+    assert_eq!(matches("(width > 1px) or (hover)"), (true, true));
+    assert_eq!(matches("(width: 50%)"), (false, false));
+    assert_eq!(matches("screen and"), (false, false));
+    assert_eq!(matches("(color) and (width) or (height)"), (false, false));
+    assert_eq!(matches("garbage!, print"), (false, true));
+}
@@ -1,9 +1,41 @@
+pub mod calc;
 mod colors;
-mod length;
 pub mod css;
+pub mod css_tokenizer;
+mod entities;
 pub mod html;
+mod html_tokenizer;
+mod length;
+pub mod media;
+mod shorthand;
 
 use super::dom;
+use super::errors;
+
+use std::fmt;
+
+/// A problem found in the input, the parser recovers from it and goes on
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// byte offset in the input where the problem was found
+    pub position: usize,
+    pub error: errors::Error,
+}
+
+impl Diagnostic {
+    fn new(position: usize, msg: &'static str) -> Diagnostic {
+        Diagnostic {
+            position,
+            error: errors::Error::Parse(msg),
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (at byte {})", self.error, self.position)
+    }
+}
 
 struct Parser {
     pos: usize,
@@ -30,9 +62,9 @@ impl Parser {
     fn consume_char(&mut self) -> char {
         let mut iter = self.input[self.pos..].char_indices();
         let (_, cur_char) = iter.next().unwrap();
-        let (next_pos, _) = iter.next().unwrap_or((1, ' '));
+        let (next_pos, _) = iter.next().unwrap_or((cur_char.len_utf8(), ' '));
         self.pos += next_pos;
-        return cur_char;
+        cur_char
     }
 
     // skip characters until test fn returns true
@@ -47,11 +79,4 @@ impl Parser {
     fn skip_whitespace(&mut self) {
         self.consume_while(char::is_whitespace);
     }
-
-    fn parse_tag_name(&mut self) -> String {
-        self.consume_while(|c| match c {
-            'a'..='z' | 'A'..='Z' | '0'..='9' => true,
-            _ => false,
-        })
-    }
 }
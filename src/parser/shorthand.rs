@@ -0,0 +1,294 @@
+/// Shorthand properties
+///
+/// A shorthand like `margin: 1px 2px` sets several longhand properties at
+/// once. It is expanded into `margin-top`, `margin-right`, ... when parsed,
+/// so the cascade only deals with longhands and a `margin-left` in another
+/// rule combines with it.
+use super::calc;
+use super::colors;
+use super::length;
+
+const SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+pub const BORDER_STYLES: [&str; 10] = [
+    "none", "hidden", "dotted", "dashed", "solid", "double", "groove", "ridge", "inset", "outset",
+];
+
+/// Split the value of a shorthand into the values of its longhands.
+///
+/// Returns `None` if `name` is not a shorthand, and no longhands at all
+/// when the value is invalid. Longhands left out of the value are set to
+/// their initial value.
+pub fn expand(name: &str, value: &str) -> Option<Vec<(String, String)>> {
+    let longhands = longhands(name)?;
+    let components = split_components(value);
+    // the keywords for every property set all the longhands
+    if let [keyword] = components.as_slice() {
+        let keyword = keyword.to_ascii_lowercase();
+        if matches!(keyword.as_str(), "inherit" | "initial" | "unset") {
+            return Some(
+                longhands
+                    .into_iter()
+                    .map(|longhand| (longhand, keyword.clone()))
+                    .collect(),
+            );
+        }
+    }
+
+    let values = match name {
+        "margin" | "padding" | "border-width" | "border-style" | "border-color" => {
+            box_sides(&components)
+        }
+        "border" => border(&components)
+            .map(|values| std::iter::repeat_n(values, SIDES.len()).flatten().collect()),
+        "background" => background(&components),
+        "font" => font(&components),
+        _ => border(&components),
+    };
+    let values = values.unwrap_or_default();
+    Some(longhands.into_iter().zip(values).collect())
+}
+
+/// The longhands a shorthand sets, `None` if `name` is not a shorthand
+pub fn longhands(name: &str) -> Option<Vec<String>> {
+    Some(match name {
+        "margin" | "padding" => side_names(name, ""),
+        "border-width" | "border-style" | "border-color" => side_names("border", &name[6..]),
+        "border" => border_names(&SIDES),
+        "border-top" | "border-right" | "border-bottom" | "border-left" => {
+            border_names(&[&name[7..]])
+        }
+        "background" => vec!["background-color".to_string()],
+        "font" => FONT_LONGHANDS.iter().map(|name| name.to_string()).collect(),
+        _ => return None,
+    })
+}
+
+// `margin-top`, `margin-right`, ... or `border-top-width`, ...
+fn side_names(prefix: &str, suffix: &str) -> Vec<String> {
+    SIDES
+        .iter()
+        .map(|side| format!("{}-{}{}", prefix, side, suffix))
+        .collect()
+}
+
+fn border_names(sides: &[&str]) -> Vec<String> {
+    sides
+        .iter()
+        .flat_map(|side| {
+            ["width", "style", "color"]
+                .iter()
+                .map(move |part| format!("border-{}-{}", side, part))
+        })
+        .collect()
+}
+
+// One to four values for the top, right, bottom and left sides:
+// `a` is all sides, `a b` top and bottom then left and right,
+// `a b c` top, left and right, bottom
+fn box_sides(components: &[&str]) -> Option<Vec<String>> {
+    let (top, right, bottom, left) = match *components {
+        [all] => (all, all, all, all),
+        [vertical, horizontal] => (vertical, horizontal, vertical, horizontal),
+        [top, horizontal, bottom] => (top, horizontal, bottom, horizontal),
+        [top, right, bottom, left] => (top, right, bottom, left),
+        _ => return None,
+    };
+    Some(vec![
+        top.to_string(),
+        right.to_string(),
+        bottom.to_string(),
+        left.to_string(),
+    ])
+}
+
+// `<width> || <style> || <color>` in any order, as width, style and color
+fn border(components: &[&str]) -> Option<Vec<String>> {
+    let (mut width, mut style, mut color) = (None, None, None);
+    for component in components {
+        let lower = component.to_ascii_lowercase();
+        let slot = if BORDER_STYLES.contains(&lower.as_str()) {
+            &mut style
+        } else if is_border_width(&lower) {
+            &mut width
+        } else if is_color(&lower) {
+            &mut color
+        } else {
+            return None;
+        };
+        if slot.replace(component.to_string()).is_some() {
+            return None;
+        }
+    }
+    Some(vec![
+        width.unwrap_or_else(|| "medium".to_string()),
+        style.unwrap_or_else(|| "none".to_string()),
+        color.unwrap_or_else(|| "currentcolor".to_string()),
+    ])
+}
+
+// Only the color of a background is supported, images, positions and
+// the like are skipped.
+fn background(components: &[&str]) -> Option<Vec<String>> {
+    let mut colors = components
+        .iter()
+        .filter(|component| is_color(&component.to_ascii_lowercase()));
+    let color = colors.next().map_or("transparent", |color| *color);
+    if colors.next().is_some() {
+        return None;
+    }
+    Some(vec![color.to_string()])
+}
+
+const FONT_LONGHANDS: [&str; 6] = [
+    "font-style",
+    "font-variant",
+    "font-weight",
+    "font-size",
+    "line-height",
+    "font-family",
+];
+
+// `[<style> || <variant> || <weight>]? <size>[/<line-height>]? <family>`
+fn font(components: &[&str]) -> Option<Vec<String>> {
+    let (mut style, mut variant, mut weight) = (None, None, None);
+    let mut rest = components.iter();
+    let size = loop {
+        let component = *rest.next()?;
+        let lower = component.to_ascii_lowercase();
+        let slot = match lower.as_str() {
+            // sets nothing, the others stay normal as well
+            "normal" => continue,
+            "italic" | "oblique" => &mut style,
+            "small-caps" => &mut variant,
+            "bold" | "bolder" | "lighter" => &mut weight,
+            number if number.parse::<u16>().is_ok() => &mut weight,
+            _ => break component,
+        };
+        if slot.replace(component.to_string()).is_some() {
+            return None;
+        }
+    };
+
+    // `12px/1.5`, `12px / 1.5` or `12px /1.5`
+    let mut rest: Vec<&str> = rest.copied().collect();
+    let (size, mut line_height) = match size.split_once('/') {
+        Some((size, line_height)) => (size, Some(line_height)),
+        None => (size, None),
+    };
+    if line_height.is_none() && rest.first().is_some_and(|next| next.starts_with('/')) {
+        let next = rest.remove(0);
+        line_height = Some(&next[1..]);
+    }
+    if line_height == Some("") {
+        if rest.is_empty() {
+            return None;
+        }
+        line_height = Some(rest.remove(0));
+    }
+
+    // the family is required
+    if size.is_empty() || rest.is_empty() {
+        return None;
+    }
+    let normal = || "normal".to_string();
+    Some(vec![
+        style.unwrap_or_else(normal),
+        variant.unwrap_or_else(normal),
+        weight.unwrap_or_else(normal),
+        size.to_string(),
+        line_height.map_or_else(normal, str::to_string),
+        rest.join(" "),
+    ])
+}
+
+fn is_border_width(value: &str) -> bool {
+    matches!(value, "thin" | "medium" | "thick")
+        || length::parse_length(value).is_some()
+        || length::parse_number(value) == Some(0.0)
+        || calc::parse_calc(value).is_some()
+}
+
+fn is_color(value: &str) -> bool {
+    value == "currentcolor" || colors::parse_color(value).is_ok()
+}
+
+// Split a value on whitespace, except inside parentheses and quotes
+fn split_components(value: &str) -> Vec<&str> {
+    let mut components = Vec::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut start = None;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if quote == Some(c) => quote = None,
+            _ if quote.is_some() => {}
+            '"' | '\'' => quote = Some(c),
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            c if c.is_whitespace() && depth == 0 => {
+                if let Some(start) = start.take() {
+                    components.push(&value[start..i]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        start.get_or_insert(i);
+    }
+    if let Some(start) = start {
+        components.push(&value[start..]);
+    }
+    components
+}
+
+#[test]
+fn test_expand() {
+    let expand = |name: &str, value: &str| -> Vec<String> {
+        expand(name, value)
+            .unwrap()
+            .into_iter()
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect()
+    };
+    assert_eq!(
+        expand("margin", "1px 2px 3px"),
+        vec![
+            "margin-top: 1px",
+            "margin-right: 2px",
+            "margin-bottom: 3px",
+            "margin-left: 2px"
+        ]
+    );
+    assert_eq!(
+        expand("border-left", "rgb(1, 2, 3) 2px"),
+        vec![
+            "border-left-width: 2px",
+            "border-left-style: none",
+            "border-left-color: rgb(1, 2, 3)"
+        ]
+    );
+    assert_eq!(expand("border", "solid red").len(), 12);
+    assert_eq!(
+        expand("font", "italic bold 12px / 1.5 \"Times New Roman\", serif"),
+        vec![
+            "font-style: italic",
+            "font-variant: normal",
+            "font-weight: bold",
+            "font-size: 12px",
+            "line-height: 1.5",
+            "font-family: \"Times New Roman\", serif"
+        ]
+    );
+    assert_eq!(
+        expand("background", "url(a b.png) no-repeat #fff"),
+        vec!["background-color: #fff"]
+    );
+    assert_eq!(expand("padding", "inherit").len(), 4);
+
+    // invalid values set nothing
+    assert!(expand("margin", "1px 2px 3px 4px 5px").is_empty());
+    assert!(expand("border", "1px 2px").is_empty());
+    assert!(expand("font", "bold 12px").is_empty());
+    assert_eq!(super::shorthand::expand("color", "red"), None);
+}
@@ -1,12 +1,19 @@
 /// This module takes DOM Tree and CSS Style sheet
-/// and calculates style tree i.e actual values of css 
+/// and calculates style tree i.e actual values of css
 /// properties
-/// 
+///
 /// It returns a one to one mapping tree with DOM tree
-
+use super::computed::{self, ComputedStyle};
 use super::dom;
 use super::parser::css;
+use super::parser::media::MediaEnvironment;
+use super::variables;
+use rayon::prelude::*;
+use std::borrow::Cow;
 use std::collections::hash_map::HashMap;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 // Map from CSS property names to values.
 type PropertyMap = HashMap<String, css::Value>;
@@ -15,15 +22,41 @@ type MatchedRule<'a> = (css::Specificity, &'a css::Rule);
 
 // A node with associated style data.
 pub struct StyledNode<'a> {
-    node: &'a dom::Node, // pointer to a DOM node
-    specified_values: PropertyMap,
-    children: Vec<StyledNode<'a>>,
+    // pointer to a DOM node, or a node generated by the style tree
+    // for a pseudo-element and its content
+    pub node: Cow<'a, dom::Node>,
+    // shared with the elements that have the same style
+    pub specified_values: Arc<PropertyMap>,
+    pub children: Vec<StyledNode<'a>>,
+    computed: ComputedStyle,
+    // set on the nodes generated for pseudo-elements
+    pseudo_element: Option<css::PseudoElement>,
+}
+
+/// Value of the `display` property, decides which box a node generates
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Display {
+    Inline,
+    Block,
+    None,
+}
+
+impl<'a> StyledNode<'a> {
+    /// The computed values of the node, inherited properties come from the parent.
+    pub fn computed_style(&self) -> &ComputedStyle {
+        &self.computed
+    }
+
+    /// The value of the `display` property (defaults to inline).
+    pub fn display(&self) -> Display {
+        self.computed.display
+    }
 }
 
 impl<'a> std::fmt::Display for StyledNode<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.node.node_type)?;
-        write!(f, "AP {:#?}\n", self.specified_values)?;
+        writeln!(f, "AP {:#?}", self.specified_values)?;
         for child in self.children.iter() {
             write!(f, "{}", child)?;
         }
@@ -31,63 +64,770 @@ impl<'a> std::fmt::Display for StyledNode<'a> {
     }
 }
 
-// Apply a stylesheet to an entire DOM tree, returning a StyledNode tree.
-pub fn style_tree<'a>(root: &'a dom::Node, stylesheet: &'a css::Stylesheet) -> StyledNode<'a> {
+/// The default styles of HTML elements, in the user agent origin
+pub fn user_agent_stylesheet() -> css::Stylesheet {
+    let mut stylesheet = css::parse(include_str!("ua.css").to_string(), css::Origin::UserAgent);
+    stylesheet.source = css::StyleSource::UserAgent;
+    stylesheet
+}
+
+/// How the style tree is built
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StyleOptions {
+    /// Style sibling subtrees on the threads of a work-stealing pool, the
+    /// tree is the same as when styled on a single thread
+    pub parallel: bool,
+    /// What `@media` rules are evaluated against, its viewport size is
+    /// also the one `vw` and `vh` lengths refer to
+    pub media: MediaEnvironment,
+}
+
+/// Counts of a styling pass
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StyleStats {
+    /// Elements styled
+    pub elements: usize,
+    /// Elements that reused the style of a sibling or cousin instead of
+    /// matching rules
+    pub shared: usize,
+}
+
+impl std::fmt::Display for StyleStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let rate = if self.elements == 0 {
+            0.0
+        } else {
+            100.0 * self.shared as f64 / self.elements as f64
+        };
+        write!(
+            f,
+            "{} elements styled, {} shared a style ({:.1}% hit rate)",
+            self.elements, self.shared, rate
+        )
+    }
+}
+
+// Apply the stylesheets to an entire DOM tree, returning a StyledNode tree.
+//
+// The sheets are given in document order, for rules of equal specificity
+// the later one wins. Rules in `@media` blocks only apply when the queries
+// match `options.media`.
+pub fn style_tree<'a>(
+    root: &'a dom::Node,
+    stylesheets: &'a [css::Stylesheet],
+    options: StyleOptions,
+) -> StyledNode<'a> {
+    style_tree_with_stats(root, stylesheets, options).0
+}
+
+/// Like `style_tree`, also counting how often the style sharing cache hit.
+pub fn style_tree_with_stats<'a>(
+    root: &'a dom::Node,
+    stylesheets: &'a [css::Stylesheet],
+    options: StyleOptions,
+) -> (StyledNode<'a>, StyleStats) {
+    let indexes: Vec<RuleIndex> = stylesheets
+        .iter()
+        .map(|stylesheet| RuleIndex::new(stylesheet, &options.media))
+        .collect();
+    let context = StyleContext {
+        shared_attributes: shared_attributes(&indexes),
+        indexes,
+        options,
+        elements: AtomicUsize::new(0),
+        shared: AtomicUsize::new(0),
+    };
+    // the parent of the root has the initial values
+    let initial = Arc::new(ElementStyle {
+        specified: Arc::default(),
+        computed: ComputedStyle::initial(),
+        before: None,
+        after: None,
+    });
+    let env = computed::Environment::new(options.media.width, options.media.height);
+    let mut tree = style_node(
+        dom::ElementRef::root(root),
+        &context,
+        &initial,
+        &env,
+        &mut SharingCache::default(),
+    );
+    // A counter depends on every element before it in document order,
+    // so the generated content is filled in afterwards in a single pass.
+    generate_content(&mut tree, &mut Counters::default());
+
+    let stats = StyleStats {
+        elements: context.elements.into_inner(),
+        shared: context.shared.into_inner(),
+    };
+    (tree, stats)
+}
+
+// What every node of a tree is styled with
+struct StyleContext<'i> {
+    indexes: Vec<RuleIndex<'i>>,
+    options: StyleOptions,
+    // the attributes selectors look at, `None` when elements can't share
+    // styles at all
+    shared_attributes: Option<Vec<&'i str>>,
+    // counts for `StyleStats`, the tree may be styled on several threads
+    elements: AtomicUsize,
+    shared: AtomicUsize,
+}
+
+// The style of an element and its pseudo-elements, which is what elements
+// share. `before` and `after` are only set when they have a `content`.
+struct ElementStyle {
+    specified: Arc<PropertyMap>,
+    computed: ComputedStyle,
+    before: Option<Arc<PropertyMap>>,
+    after: Option<Arc<PropertyMap>>,
+}
+
+// `node` is the position of the node in the tree, so selectors can look
+// at its ancestors and siblings.
+fn style_node<'a>(
+    node: dom::ElementRef<'a, '_>,
+    context: &StyleContext,
+    parent: &Arc<ElementStyle>,
+    env: &computed::Environment,
+    cache: &mut SharingCache<'a>,
+) -> StyledNode<'a> {
+    if let dom::NodeType::Text(_) = node.node.node_type {
+        return text_node(Cow::Borrowed(node.node), &parent.computed, env);
+    }
+
+    let style = context.element_style(&node, parent, env, cache);
+    // rem lengths below the root are relative to its font size
+    let root_env;
+    let env = match node.parent() {
+        Some(_) => env,
+        None => {
+            root_env = computed::Environment {
+                root_font_size: style.computed.font_size,
+                ..*env
+            };
+            &root_env
+        }
+    };
+
+    // ::before and ::after are the first and last child
+    let mut children: Vec<StyledNode<'a>> = pseudo_element_node(
+        &style.before,
+        css::PseudoElement::Before,
+        &style.computed,
+        env,
+    )
+    .into_iter()
+    .collect();
+    let count = node.node.children.len();
+    if context.options.parallel {
+        // each job of the pool has a cache of its own
+        children.par_extend((0..count).into_par_iter().map_init(
+            SharingCache::default,
+            |cache, index| {
+                let child = dom::ElementRef::child(&node, index);
+                style_node(child, context, &style, env, cache)
+            },
+        ));
+    } else {
+        children.extend((0..count).map(|index| {
+            let child = dom::ElementRef::child(&node, index);
+            style_node(child, context, &style, env, cache)
+        }));
+    }
+    children.extend(pseudo_element_node(
+        &style.after,
+        css::PseudoElement::After,
+        &style.computed,
+        env,
+    ));
+
     StyledNode {
-        node: root,
-        specified_values: match root.node_type {
-            dom::NodeType::Element(ref elem) => specified_values(elem, stylesheet),
-            dom::NodeType::Text(_) => HashMap::new()
-        },
-        children: root.children.iter().map(|child| style_tree(child, stylesheet)).collect(),
+        node: Cow::Borrowed(node.node),
+        children,
+        specified_values: Arc::clone(&style.specified),
+        computed: style.computed.clone(),
+        pseudo_element: None,
     }
 }
 
-// Apply styles to a single element, returning the specified values.
-fn specified_values(elem: &dom::ElementData, stylesheet: &css::Stylesheet) -> PropertyMap {
-    let mut values = HashMap::new();
-    let mut rules = matching_rules(elem, stylesheet);
+impl<'i> StyleContext<'i> {
+    // Match the rules for an element, or reuse the style of an element
+    // styled before with the same sharing key.
+    fn element_style<'a>(
+        &self,
+        elem: &dom::ElementRef<'a, '_>,
+        parent: &Arc<ElementStyle>,
+        env: &computed::Environment,
+        cache: &mut SharingCache<'a>,
+    ) -> Arc<ElementStyle> {
+        self.elements.fetch_add(1, Ordering::Relaxed);
+        let key = self.sharing_key(elem.data(), parent);
+        if let Some(style) = key.as_ref().and_then(|key| cache.get(key)) {
+            self.shared.fetch_add(1, Ordering::Relaxed);
+            return style;
+        }
 
-    // TODO: also consider inline style tag
+        let mut specified = specified_values(elem, &self.indexes, None);
+        variables::substitute(&mut specified, &parent.computed.custom_properties);
+        let computed = computed::compute(&specified, &parent.computed, env);
+        // pseudo-elements inherit the custom properties of the element
+        let pseudo_element_values = |pseudo_element| {
+            let mut values = specified_values(elem, &self.indexes, Some(pseudo_element));
+            variables::substitute(&mut values, &computed.custom_properties);
+            match values.get("content") {
+                Some(css::Value::Content(_)) => Some(Arc::new(values)),
+                _ => None,
+            }
+        };
+        let style = Arc::new(ElementStyle {
+            before: pseudo_element_values(css::PseudoElement::Before),
+            after: pseudo_element_values(css::PseudoElement::After),
+            computed,
+            specified: Arc::new(specified),
+        });
+        if let Some(key) = key {
+            cache.insert(key, Arc::clone(&style));
+        }
+        style
+    }
 
-    // Go through the rules from lowest to highest specificity.
-    rules.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
-    for (_, rule) in rules {
+    // An element with an id or a style attribute has a style of its own.
+    fn sharing_key<'a>(
+        &self,
+        elem: &'a dom::ElementData,
+        parent: &Arc<ElementStyle>,
+    ) -> Option<SharingKey<'a>> {
+        let shared_attributes = self.shared_attributes.as_ref()?;
+        if elem.id().is_some() || elem.attr("style").is_some() {
+            return None;
+        }
+        let mut classes: Vec<&str> = elem.classes().into_iter().collect();
+        classes.sort_unstable();
+        Some(SharingKey {
+            parent: Arc::clone(parent),
+            tag_name: &elem.tag_name,
+            classes,
+            attributes: shared_attributes
+                .iter()
+                .map(|name| elem.attr(name))
+                .collect(),
+            state: elem.state,
+        })
+    }
+}
+
+/// What two elements need in common to have the same style.
+///
+/// The parent must have the very same style, which is the case for siblings
+/// and for cousins whose parents shared their style in turn. So going up
+/// from both elements, every pair of ancestors matches the same selectors.
+/// Selectors that look at siblings or children would still tell them apart,
+/// with those in the stylesheets no style is shared.
+struct SharingKey<'a> {
+    parent: Arc<ElementStyle>,
+    tag_name: &'a str,
+    // sorted
+    classes: Vec<&'a str>,
+    // values of the attributes of attribute selectors
+    attributes: Vec<Option<&'a String>>,
+    state: dom::ElementState,
+}
+
+impl<'a> PartialEq for SharingKey<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.parent, &other.parent)
+            && self.tag_name == other.tag_name
+            && self.classes == other.classes
+            && self.attributes == other.attributes
+            && self.state == other.state
+    }
+}
+
+const SHARING_CACHE_SIZE: usize = 16;
+
+/// The last styles matched, most recently used first
+#[derive(Default)]
+struct SharingCache<'a> {
+    entries: VecDeque<(SharingKey<'a>, Arc<ElementStyle>)>,
+}
+
+impl<'a> SharingCache<'a> {
+    fn get(&mut self, key: &SharingKey<'a>) -> Option<Arc<ElementStyle>> {
+        let position = self.entries.iter().position(|(other, _)| other == key)?;
+        let entry = self.entries.remove(position)?;
+        let style = Arc::clone(&entry.1);
+        self.entries.push_front(entry);
+        Some(style)
+    }
+
+    fn insert(&mut self, key: SharingKey<'a>, style: Arc<ElementStyle>) {
+        if self.entries.len() == SHARING_CACHE_SIZE {
+            self.entries.pop_back();
+        }
+        self.entries.push_front((key, style));
+    }
+}
+
+// The attributes looked at by the selectors of the rules that apply, or
+// `None` when a selector depends on the siblings or children of an element.
+fn shared_attributes<'a>(indexes: &[RuleIndex<'a>]) -> Option<Vec<&'a str>> {
+    let mut attributes = Vec::new();
+    let selectors = indexes
+        .iter()
+        .flat_map(|index| &index.rules)
+        .flat_map(|rule| &rule.selectors);
+    for selector in selectors {
+        if !collect_attributes(selector, &mut attributes) {
+            return None;
+        }
+    }
+    attributes.sort_unstable();
+    attributes.dedup();
+    Some(attributes)
+}
+
+fn collect_attributes<'i>(selector: &'i css::Selector, attributes: &mut Vec<&'i str>) -> bool {
+    let (subject, chain) = match selector {
+        css::Selector::Simple(subject) => (subject, &[][..]),
+        css::Selector::Compound(subject, chain) => (subject, &chain[..]),
+    };
+    let siblings = chain.iter().any(|(combinator, _)| {
+        matches!(
+            combinator,
+            css::Combinator::NextSibling | css::Combinator::SubsequentSibling
+        )
+    });
+    if siblings {
+        return false;
+    }
+
+    let simple_selectors = std::iter::once(subject).chain(chain.iter().map(|(_, simple)| simple));
+    for simple in simple_selectors {
+        attributes.extend(
+            simple
+                .attributes
+                .iter()
+                .map(|attribute| attribute.name.as_str()),
+        );
+        for pseudo_class in &simple.pseudo_classes {
+            let shareable = match pseudo_class {
+                css::PseudoClass::FirstChild
+                | css::PseudoClass::LastChild
+                | css::PseudoClass::NthChild(..)
+                | css::PseudoClass::NthOfType(_)
+                | css::PseudoClass::Empty => false,
+                css::PseudoClass::Not(list)
+                | css::PseudoClass::Is(list)
+                | css::PseudoClass::Where(list) => list
+                    .iter()
+                    .all(|selector| collect_attributes(selector, attributes)),
+                // the state is compared along with the attributes
+                css::PseudoClass::Root
+                | css::PseudoClass::Hover
+                | css::PseudoClass::Focus
+                | css::PseudoClass::Checked => true,
+            };
+            if !shareable {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn text_node<'a>(
+    node: Cow<'a, dom::Node>,
+    parent: &ComputedStyle,
+    env: &computed::Environment,
+) -> StyledNode<'a> {
+    let specified_values: Arc<PropertyMap> = Arc::default();
+    StyledNode {
+        computed: computed::compute(&specified_values, parent, env),
+        node,
+        specified_values,
+        children: Vec::new(),
+        pseudo_element: None,
+    }
+}
+
+// The box of a pseudo-element, an element holding the generated content as
+// its only text. The text is left empty until `generate_content`.
+fn pseudo_element_node<'a>(
+    specified_values: &Option<Arc<PropertyMap>>,
+    pseudo_element: css::PseudoElement,
+    parent: &ComputedStyle,
+    env: &computed::Environment,
+) -> Option<StyledNode<'a>> {
+    let specified_values = Arc::clone(specified_values.as_ref()?);
+    let computed = computed::compute(&specified_values, parent, env);
+    let name = match pseudo_element {
+        css::PseudoElement::Before => "::before",
+        css::PseudoElement::After => "::after",
+    };
+    Some(StyledNode {
+        node: Cow::Owned(dom::create_element(
+            name.to_string(),
+            dom::AttrMap::new(),
+            Vec::new(),
+        )),
+        children: vec![text_node(
+            Cow::Owned(dom::create_text(String::new())),
+            &computed,
+            env,
+        )],
+        specified_values,
+        computed,
+        pseudo_element: Some(pseudo_element),
+    })
+}
+
+// Walk the styled tree in document order, updating the counters and
+// filling in the text of the pseudo-elements. Elements that are not
+// displayed don't touch the counters.
+fn generate_content(node: &mut StyledNode, counters: &mut Counters) {
+    let elem = match node.node.node_type {
+        dom::NodeType::Element(ref elem) if node.computed.display != Display::None => elem,
+        _ => return,
+    };
+    counters.update(&node.specified_values);
+
+    let scope = counters.enter();
+    for child in node.children.iter_mut() {
+        if child.pseudo_element.is_none() {
+            generate_content(child, counters);
+            continue;
+        }
+        if child.computed.display != Display::None {
+            counters.update(&child.specified_values);
+        }
+        let content = match child.specified_values.get("content") {
+            Some(css::Value::Content(content)) => content,
+            _ => continue,
+        };
+        let text: String = content
+            .iter()
+            .map(|item| match item {
+                css::ContentItem::String(string) => string.clone(),
+                css::ContentItem::Attr(name) => elem.attr(name).cloned().unwrap_or_default(),
+                css::ContentItem::Counter(name) => counters.value(name).to_string(),
+                css::ContentItem::Counters(name, separator) => counters
+                    .values(name)
+                    .iter()
+                    .map(i32::to_string)
+                    .collect::<Vec<_>>()
+                    .join(separator),
+            })
+            .collect();
+        child.children[0].node = Cow::Owned(dom::create_text(text));
+    }
+    counters.leave(scope);
+}
+
+/// The CSS counters in scope while the styled tree is walked in document order.
+///
+/// A `counter-reset` creates a counter seen by the element, its following
+/// siblings and all their descendants. A reset of a counter made on the
+/// same level replaces it, a reset on a deeper level nests a new one.
+#[derive(Default)]
+struct Counters {
+    // innermost last, with the depth of the element that reset them
+    counters: Vec<(String, i32, usize)>,
+    depth: usize,
+}
+
+impl Counters {
+    // Apply `counter-reset`, then `counter-increment`
+    fn update(&mut self, specified_values: &PropertyMap) {
+        if let Some(css::Value::Counters(resets)) = specified_values.get("counter-reset") {
+            for (name, value) in resets {
+                self.reset(name, *value);
+            }
+        }
+        if let Some(css::Value::Counters(increments)) = specified_values.get("counter-increment") {
+            for (name, by) in increments {
+                if self.innermost(name).is_none() {
+                    // incrementing a counter that isn't in scope creates it
+                    self.reset(name, 0);
+                }
+                if let Some(index) = self.innermost(name) {
+                    self.counters[index].1 += by;
+                }
+            }
+        }
+    }
+
+    fn reset(&mut self, name: &str, value: i32) {
+        match self.innermost(name) {
+            Some(index) if self.counters[index].2 == self.depth => self.counters[index].1 = value,
+            _ => self.counters.push((name.to_string(), value, self.depth)),
+        }
+    }
+
+    fn innermost(&self, name: &str) -> Option<usize> {
+        self.counters.iter().rposition(|counter| counter.0 == name)
+    }
+
+    fn value(&self, name: &str) -> i32 {
+        self.innermost(name)
+            .map_or(0, |index| self.counters[index].1)
+    }
+
+    // outermost first
+    fn values(&self, name: &str) -> Vec<i32> {
+        self.counters
+            .iter()
+            .filter(|counter| counter.0 == name)
+            .map(|counter| counter.1)
+            .collect()
+    }
+
+    // Go down to the children, the returned scope is given back to `leave`
+    fn enter(&mut self) -> usize {
+        self.depth += 1;
+        self.counters.len()
+    }
+
+    // Counters reset by the children go out of scope with their parent
+    fn leave(&mut self, scope: usize) {
+        self.depth -= 1;
+        self.counters.truncate(scope);
+    }
+}
+
+/// Rank of a declaration in the cascade, for each property the
+/// declaration with the highest priority wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+struct CascadePriority {
+    level: u8,
+    // the style attribute beats every selector on the same level
+    style_attribute: bool,
+    specificity: css::Specificity,
+    // position in the stylesheets, the later declaration wins a tie
+    order: usize,
+}
+
+// From weakest to strongest: normal user agent, user and author
+// declarations, then important author, user and user agent declarations.
+fn cascade_level(declaration: &css::Declaration) -> u8 {
+    match (declaration.important, declaration.origin) {
+        (false, css::Origin::UserAgent) => 0,
+        (false, css::Origin::User) => 1,
+        (false, css::Origin::Author) => 2,
+        (true, css::Origin::Author) => 3,
+        (true, css::Origin::User) => 4,
+        (true, css::Origin::UserAgent) => 5,
+    }
+}
+
+// Apply styles to a single element or one of its pseudo-elements,
+// returning the specified values.
+fn specified_values(
+    elem: &dom::ElementRef,
+    indexes: &[RuleIndex],
+    pseudo_element: Option<css::PseudoElement>,
+) -> PropertyMap {
+    let rules: Vec<MatchedRule> = indexes
+        .iter()
+        .flat_map(|index| matching_rules(elem, index, pseudo_element))
+        .collect();
+    let inline_style = match elem.data().attr("style") {
+        Some(style) if pseudo_element.is_none() => {
+            css::parse_declaration_list(style, css::Origin::Author)
+        }
+        _ => Vec::new(),
+    };
+
+    let mut declarations: Vec<(CascadePriority, &css::Declaration)> = Vec::new();
+    for (specificity, rule) in rules {
         for declaration in &rule.declarations {
-            values.insert(declaration.name.clone(), declaration.value.clone());
+            let priority = CascadePriority {
+                level: cascade_level(declaration),
+                style_attribute: false,
+                specificity,
+                order: declarations.len(),
+            };
+            declarations.push((priority, declaration));
         }
     }
-    return values;
+    for declaration in &inline_style {
+        let priority = CascadePriority {
+            level: cascade_level(declaration),
+            style_attribute: true,
+            specificity: (0, 0, 0),
+            order: declarations.len(),
+        };
+        declarations.push((priority, declaration));
+    }
+
+    // Go through the declarations from lowest to highest priority.
+    declarations.sort_by_key(|&(priority, _)| priority);
+    let mut values = HashMap::new();
+    for (_, declaration) in declarations {
+        values.insert(declaration.name.clone(), declaration.value.clone());
+    }
+    values
 }
 
-/// Find all CSS rules that match the given element.
-/// 
-/// We can speed this up by storing the rules in multiple hash tables based
-/// on tag name, id, class, etc.
-fn matching_rules<'a>(elem: &dom::ElementData, stylesheet: &'a css::Stylesheet) -> Vec<MatchedRule<'a>> {
-    stylesheet.rules.iter().filter_map(|rule| match_rule(elem, rule)).collect()
+/// Find all CSS rules of a stylesheet that match the given element, in
+/// source order. Only the rules in the buckets of the element are tried.
+fn matching_rules<'a>(
+    elem: &dom::ElementRef,
+    index: &RuleIndex<'a>,
+    pseudo_element: Option<css::PseudoElement>,
+) -> Vec<MatchedRule<'a>> {
+    index
+        .candidates(elem.data())
+        .into_iter()
+        .filter_map(|rule| match_rule(elem, rule, pseudo_element))
+        .collect()
+}
+
+/// The rules of a stylesheet that apply to the media, bucketed by the
+/// rightmost simple selector of their selectors, so an element is only
+/// matched against the rules that could apply to it.
+///
+/// A selector goes in the bucket of its id, or else of its first class,
+/// or else of its tag name. The others, like `*` or `[href]`, are tried
+/// on every element.
+struct RuleIndex<'a> {
+    rules: Vec<&'a css::Rule>,
+    // positions in `rules`
+    by_id: HashMap<&'a str, Vec<usize>>,
+    by_class: HashMap<&'a str, Vec<usize>>,
+    by_tag: HashMap<&'a str, Vec<usize>>,
+    universal: Vec<usize>,
+}
+
+impl<'a> RuleIndex<'a> {
+    fn new(stylesheet: &'a css::Stylesheet, media: &MediaEnvironment) -> RuleIndex<'a> {
+        let rules = stylesheet.style_rules(media);
+        let mut index = RuleIndex {
+            rules: Vec::new(),
+            by_id: HashMap::new(),
+            by_class: HashMap::new(),
+            by_tag: HashMap::new(),
+            universal: Vec::new(),
+        };
+        for (position, rule) in rules.iter().copied().enumerate() {
+            for selector in &rule.selectors {
+                let subject = match selector {
+                    css::Selector::Simple(subject) | css::Selector::Compound(subject, _) => subject,
+                };
+                let bucket = if let Some(ref id) = subject.id {
+                    index.by_id.entry(id).or_default()
+                } else if let Some(class) = subject.class.first() {
+                    index.by_class.entry(class).or_default()
+                } else if let Some(ref tag_name) = subject.tag_name {
+                    index.by_tag.entry(tag_name).or_default()
+                } else {
+                    &mut index.universal
+                };
+                // a rule with several selectors in the same bucket is added once
+                if bucket.last() != Some(&position) {
+                    bucket.push(position);
+                }
+            }
+        }
+        index.rules = rules;
+        index
+    }
+
+    // The rules that may match `elem`, in source order
+    fn candidates(&self, elem: &dom::ElementData) -> Vec<&'a css::Rule> {
+        let mut positions: Vec<usize> = self.universal.clone();
+        let mut add = |bucket: Option<&Vec<usize>>| {
+            if let Some(bucket) = bucket {
+                positions.extend(bucket);
+            }
+        };
+        if let Some(id) = elem.id() {
+            add(self.by_id.get(id.as_str()));
+        }
+        for class in elem.classes() {
+            add(self.by_class.get(class));
+        }
+        add(self.by_tag.get(elem.tag_name.as_str()));
+
+        positions.sort_unstable();
+        positions.dedup();
+        positions
+            .into_iter()
+            .map(|position| self.rules[position])
+            .collect()
+    }
 }
 
 /// If `rule` matches `elem`, return a `MatchedRule`. Otherwise return `None`.
 ///
-/// Iterate through all selectors of a rule and returns the matched one
-fn match_rule<'a>(elem: &dom::ElementData, rule: &'a css::Rule) -> Option<MatchedRule<'a>> {
+/// Iterate through all selectors of a rule and returns the matched one,
+/// only selectors for `pseudo_element` are tried.
+fn match_rule<'a>(
+    elem: &dom::ElementRef,
+    rule: &'a css::Rule,
+    pseudo_element: Option<css::PseudoElement>,
+) -> Option<MatchedRule<'a>> {
     // Find the first (highest-specificity) matching selector.
-    rule.selectors.iter()
-        .find(|selector| matches(elem, *selector))
+    rule.selectors
+        .iter()
+        .filter(|selector| selector.pseudo_element() == pseudo_element)
+        .find(|selector| matches(elem, selector))
         .map(|selector| (selector.specificity(), rule))
 }
 
 // If a selector matches an element
-fn matches(elem: &dom::ElementData, selector: &css::Selector) -> bool {
+fn matches(elem: &dom::ElementRef, selector: &css::Selector) -> bool {
     // match all selectors, simple and compound
     match *selector {
-        css::Selector::Simple(ref simple_selector) => matches_simple_selector(
-            elem, simple_selector)
+        css::Selector::Simple(ref simple_selector) => {
+            matches_simple_selector(elem, simple_selector)
+        }
+        css::Selector::Compound(ref subject, ref chain) => {
+            matches_simple_selector(elem, subject) && matches_chain(elem, chain)
+        }
     }
 }
 
-fn matches_simple_selector(elem: &dom::ElementData, selector: &css::SimpleSelector) -> bool {
+/// Match the rest of a compound selector from right to left, starting at
+/// the element matched by the part right of `chain`.
+///
+/// Descendant and sibling combinators may skip elements, so each candidate
+/// is tried until the remaining chain matches as well.
+fn matches_chain(elem: &dom::ElementRef, chain: &[(css::Combinator, css::SimpleSelector)]) -> bool {
+    let ((combinator, selector), rest) = match chain.split_first() {
+        Some(first) => first,
+        None => return true,
+    };
+    let matches_here = |other: &dom::ElementRef| {
+        matches_simple_selector(other, selector) && matches_chain(other, rest)
+    };
+
+    match combinator {
+        css::Combinator::Child => elem.parent().is_some_and(&matches_here),
+        css::Combinator::Descendant => {
+            let mut ancestor = elem.parent();
+            while let Some(current) = ancestor {
+                if matches_here(current) {
+                    return true;
+                }
+                ancestor = current.parent();
+            }
+            false
+        }
+        css::Combinator::NextSibling => elem
+            .previous_siblings()
+            .next()
+            .is_some_and(|sibling| matches_here(&sibling)),
+        css::Combinator::SubsequentSibling => elem
+            .previous_siblings()
+            .any(|sibling| matches_here(&sibling)),
+    }
+}
+
+fn matches_simple_selector(elem_ref: &dom::ElementRef, selector: &css::SimpleSelector) -> bool {
+    let elem = elem_ref.data();
+
     // if selector has tag it should match
     if selector.tag_name.iter().any(|name| elem.tag_name != *name) {
         return false;
@@ -100,10 +840,729 @@ fn matches_simple_selector(elem: &dom::ElementData, selector: &css::SimpleSelect
 
     // if selector has class it should match
     let elem_classes = elem.classes();
-    if selector.class.iter().any(|class| !elem_classes.contains(&**class)) {
+    if selector
+        .class
+        .iter()
+        .any(|class| !elem_classes.contains(&**class))
+    {
+        return false;
+    }
+
+    // every attribute selector should match
+    if !selector
+        .attributes
+        .iter()
+        .all(|attribute| matches_attribute(elem, attribute))
+    {
+        return false;
+    }
+
+    // every pseudo-class should match
+    if !selector
+        .pseudo_classes
+        .iter()
+        .all(|pseudo_class| matches_pseudo_class(elem_ref, pseudo_class))
+    {
         return false;
     }
 
     // return true since everything matches
-    return true;
+    true
+}
+
+fn matches_pseudo_class(elem: &dom::ElementRef, pseudo_class: &css::PseudoClass) -> bool {
+    let matches_any = |list: &[css::Selector]| list.iter().any(|selector| matches(elem, selector));
+    let state = elem.data().state;
+
+    match *pseudo_class {
+        css::PseudoClass::Root => elem.parent().is_none(),
+        css::PseudoClass::Empty => elem.node.children.is_empty(),
+        css::PseudoClass::FirstChild => elem.previous_siblings().next().is_none(),
+        css::PseudoClass::LastChild => elem.next_siblings().next().is_none(),
+        css::PseudoClass::NthChild(nth, None) => {
+            nth.matches(elem.previous_siblings().count() as i32 + 1)
+        }
+        css::PseudoClass::NthChild(nth, Some(ref list)) => {
+            // only the siblings matching the list are counted
+            let position = elem
+                .previous_siblings()
+                .filter(|sibling| list.iter().any(|selector| matches(sibling, selector)))
+                .count();
+            matches_any(list) && nth.matches(position as i32 + 1)
+        }
+        css::PseudoClass::NthOfType(nth) => {
+            let tag_name = &elem.data().tag_name;
+            let position = elem
+                .previous_siblings()
+                .filter(|sibling| sibling.data().tag_name == *tag_name)
+                .count();
+            nth.matches(position as i32 + 1)
+        }
+        css::PseudoClass::Not(ref list) => !matches_any(list),
+        css::PseudoClass::Is(ref list) | css::PseudoClass::Where(ref list) => matches_any(list),
+        css::PseudoClass::Hover => state.contains(dom::ElementState::HOVER),
+        css::PseudoClass::Focus => state.contains(dom::ElementState::FOCUS),
+        css::PseudoClass::Checked => state.contains(dom::ElementState::CHECKED),
+    }
+}
+
+fn matches_attribute(elem: &dom::ElementData, selector: &css::AttributeSelector) -> bool {
+    let actual = match elem.attr(&selector.name) {
+        Some(actual) => actual,
+        None => return false,
+    };
+    let (operator, expected) = match selector.value {
+        Some((operator, ref expected)) => (operator, expected),
+        None => return true,
+    };
+    let fold = |s: &str| {
+        if selector.case_insensitive {
+            s.to_ascii_lowercase()
+        } else {
+            s.to_string()
+        }
+    };
+    let (actual, expected) = (fold(actual), fold(expected));
+
+    match operator {
+        css::AttrOperator::Equals => actual == expected,
+        css::AttrOperator::Includes => actual.split_whitespace().any(|word| word == expected),
+        css::AttrOperator::DashMatch => {
+            actual == expected || actual.starts_with(&format!("{}-", expected))
+        }
+        // an empty value matches nothing for the substring operators
+        css::AttrOperator::Prefix => !expected.is_empty() && actual.starts_with(&expected),
+        css::AttrOperator::Suffix => !expected.is_empty() && actual.ends_with(&expected),
+        css::AttrOperator::Substring => !expected.is_empty() && actual.contains(&expected),
+    }
+}
+
+#[test]
+fn test_inline_style() {
+    let dom = super::parser::html::parse(
+        "<div id=\"a\" style=\"COLOR: #00ff00; margin: 4px; bogus; padding: 1px\"></div>"
+            .to_string(),
+    );
+    let stylesheets = [css::parse(
+        "#a { color: #ff0000; display: block; }".to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let style = styled.computed_style();
+
+    assert_eq!(style.color, css::Color::from(0, 255, 0, 255));
+    assert_eq!(styled.display(), Display::Block);
+    assert_eq!(style.margin.top, computed::Length::Px(4.0));
+    assert_eq!(style.padding.left, computed::Length::Px(1.0));
+}
+
+#[test]
+fn test_cascade_order() {
+    let dom = super::parser::html::parse(
+        "<p id=\"a\" class=\"b\" style=\"color: #0000ff; margin: 1px\"></p>".to_string(),
+    );
+    let stylesheets = [
+        css::parse(
+            "p { color: #ff0000 !important; display: inline; } .b { display: block; padding: 1px; }"
+                .to_string(),
+            css::Origin::Author,
+        ),
+        css::parse(
+            ".b { display: none; padding: 2px ! IMPORTANT; } #a { padding: 3px; }".to_string(),
+            css::Origin::Author,
+        ),
+        css::parse(
+            "p { margin: 5px !important; }".to_string(),
+            css::Origin::User,
+        ),
+    ];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let style = styled.computed_style();
+
+    // important beats the style attribute
+    assert_eq!(style.color, css::Color::from(255, 0, 0, 255));
+    // the later of two equally specific rules wins
+    assert_eq!(styled.display(), Display::None);
+    // important beats specificity
+    assert_eq!(style.padding.top, computed::Length::Px(2.0));
+    // important user declarations beat author ones
+    assert_eq!(style.margin.top, computed::Length::Px(5.0));
+}
+
+#[test]
+fn test_cascade_drops_invalid_values() {
+    let dom = super::parser::html::parse("<p></p>".to_string());
+    let stylesheets = [css::parse(
+        "p { width: 5px; width: banana; color: red; color: 12px; padding: 1px; padding: 2px -1px; }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let style = styled.computed_style();
+
+    // a value the property doesn't accept leaves the earlier one
+    assert_eq!(style.width, computed::Length::Px(5.0));
+    assert_eq!(style.color, css::Color::from(255, 0, 0, 255));
+    assert_eq!(style.padding.left, computed::Length::Px(1.0));
+}
+
+#[test]
+fn test_user_agent_stylesheet() {
+    let dom = super::parser::html::parse(
+        "<html><head><title>t</title></head><body><h1>a</h1><span style=\"display: block\">b</span></body></html>"
+            .to_string(),
+    );
+    let stylesheets = [
+        css::parse("h1 { display: inline; }".to_string(), css::Origin::Author),
+        user_agent_stylesheet(),
+    ];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+
+    let head = &styled.children[0];
+    let body = &styled.children[1];
+    assert_eq!(styled.display(), Display::Block);
+    assert_eq!(head.display(), Display::None);
+    assert_eq!(body.display(), Display::Block);
+    // author styles beat the defaults
+    assert_eq!(body.children[0].display(), Display::Inline);
+    assert_eq!(body.children[0].computed_style().font_size, 32.0);
+    assert_eq!(body.children[1].display(), Display::Block);
+}
+
+#[test]
+fn test_computed_values() {
+    let dom = super::parser::html::parse(
+        "<div class=\"a\"><p style=\"font-size: 2em; width: 50%; border-color: inherit\">x<span class=\"b\">y</span></p></div>"
+            .to_string(),
+    );
+    let stylesheets = [css::parse(
+        ".a { color: #ff0000; margin: 1em; border-color: #0000ff; line-height: 2; } .b { color: initial; margin: inherit; font-weight: bolder; }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let p = &styled.children[0];
+    let text = &p.children[0];
+    let span = &p.children[1];
+
+    // inherited properties flow down, others don't
+    assert_eq!(p.computed_style().color, css::Color::from(255, 0, 0, 255));
+    assert_eq!(
+        text.computed_style().color,
+        css::Color::from(255, 0, 0, 255)
+    );
+    assert_eq!(p.computed_style().margin.top, computed::Length::Px(0.0));
+    assert_eq!(
+        styled.computed_style().margin.top,
+        computed::Length::Px(16.0)
+    );
+    assert_eq!(
+        p.computed_style().border_color.top,
+        css::Color::from(0, 0, 255, 255)
+    );
+
+    // em and line-height numbers follow the font size
+    assert_eq!(p.computed_style().font_size, 32.0);
+    assert_eq!(p.computed_style().line_height_px(), 64.0);
+    assert_eq!(p.computed_style().width, computed::Length::Percent(50.0));
+
+    assert_eq!(span.computed_style().color, css::Color::from(0, 0, 0, 255));
+    assert_eq!(span.computed_style().margin.top, computed::Length::Px(0.0));
+    assert_eq!(span.computed_style().font_weight, 700);
+}
+
+#[test]
+fn test_length_units() {
+    let dom = super::parser::html::parse("<div><p>x</p><span>y</span><em>z</em></div>".to_string());
+    let stylesheets = [css::parse(
+        "div { font-size: 20px; width: 50vw; height: 10vmax; margin: 1in 1.5em 12pt -2px; } p { font-size: 0.5rem; padding: 1ch 2ex 2.54cm 0; line-height: 1.25; } span { width: -10px; padding: -1px; font-weight: 550.5; } em { font-size: 150%; line-height: 10mm; margin: 0 auto; }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let options = StyleOptions {
+        media: MediaEnvironment {
+            width: 400.0,
+            height: 300.0,
+            ..MediaEnvironment::default()
+        },
+        ..StyleOptions::default()
+    };
+    let styled = style_tree(&dom, &stylesheets, options);
+    let div = styled.computed_style();
+    let p = styled.children[0].computed_style();
+    let span = styled.children[1].computed_style();
+    let em = styled.children[2].computed_style();
+
+    // viewport units
+    assert_eq!(div.width, computed::Length::Px(200.0));
+    assert_eq!(div.height, computed::Length::Px(40.0));
+    // absolute units at 96 px to the inch, em of the element itself
+    assert_eq!(div.margin.top, computed::Length::Px(96.0));
+    assert_eq!(div.margin.right, computed::Length::Px(30.0));
+    assert_eq!(div.margin.bottom, computed::Length::Px(16.0));
+    assert_eq!(div.margin.left, computed::Length::Px(-2.0));
+
+    // rem is the font size of the root, not the parent
+    assert_eq!(p.font_size, 10.0);
+    assert_eq!(p.padding.top, computed::Length::Px(6.0));
+    assert_eq!(p.padding.right, computed::Length::Px(10.0));
+    assert_eq!(p.padding.bottom, computed::Length::Px(96.0));
+    assert_eq!(p.padding.left, computed::Length::Px(0.0));
+    assert_eq!(p.line_height_px(), 12.5);
+
+    // negative sizes are invalid
+    assert_eq!(span.width, computed::Length::Auto);
+    assert_eq!(span.padding.top, computed::Length::Px(0.0));
+    assert_eq!(span.font_weight, 551);
+
+    assert_eq!(em.font_size, 30.0);
+    assert!((em.line_height_px() - 37.795).abs() < 0.001);
+    assert_eq!(em.margin.right, computed::Length::Auto);
+}
+
+#[test]
+fn test_media_rules() {
+    use super::parser::media::{ColorScheme, MediaType};
+
+    let dom = super::parser::html::parse("<div><p>x</p></div>".to_string());
+    let stylesheets = [css::parse(
+        "p { width: 1px; } @media (min-width: 600px) { p { width: 2px; } } @media print { div { height: 5px; } @media (prefers-color-scheme: dark) { p { width: 3px; } } } @media garbage { p { width: 4px; } }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let style = |media: MediaEnvironment| {
+        let styled = style_tree(
+            &dom,
+            &stylesheets,
+            StyleOptions {
+                media,
+                ..StyleOptions::default()
+            },
+        );
+        (
+            styled.computed_style().height.clone(),
+            styled.children[0].computed_style().width.clone(),
+        )
+    };
+    let px = computed::Length::Px;
+
+    assert_eq!(
+        style(MediaEnvironment::default()),
+        (computed::Length::Auto, px(2.0))
+    );
+    let narrow = MediaEnvironment {
+        width: 320.0,
+        ..MediaEnvironment::default()
+    };
+    assert_eq!(style(narrow), (computed::Length::Auto, px(1.0)));
+    let print = MediaEnvironment {
+        media_type: MediaType::Print,
+        color_scheme: ColorScheme::Dark,
+        ..MediaEnvironment::default()
+    };
+    assert_eq!(style(print), (px(5.0), px(3.0)));
+}
+
+#[test]
+fn test_custom_properties() {
+    let dom = super::parser::html::parse("<div><p>x</p><span>y</span><em>z</em></div>".to_string());
+    let stylesheets = [css::parse(
+        "div { --Accent: rgb(0, 0, 255); --gap: 4px; --a: var(--b); --b: var(--a, 1px); --size: 2em; margin: var(--gap) 0; } \
+         p { --gap: 10px; --label: \"hi\"; color: var(--Accent); padding: var(--gap) var(--missing, 3px); width: var(--a, 7px); border: var(--gap) solid var(--accent, red); } \
+         p::before { content: var(--label) } \
+         span { --Accent: initial; color: var(--Accent, green); height: var(--size); } \
+         em { font-weight: var(--gap); color: var(--gap); }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let div = styled.computed_style();
+    let p = &styled.children[0];
+    let span = styled.children[1].computed_style();
+    let em = styled.children[2].computed_style();
+    let px = computed::Length::Px;
+    let blue = css::Color::from(0, 0, 255, 255);
+
+    // shorthands with `var()` set all their longhands
+    assert_eq!((&div.margin.top, &div.margin.left), (&px(4.0), &px(0.0)));
+    // inherited, and set again on the child
+    assert_eq!(p.computed_style().color, blue);
+    assert_eq!(p.computed_style().padding.top, px(10.0));
+    assert_eq!(p.computed_style().padding.right, px(3.0));
+    assert_eq!(p.computed_style().border_width.top, 10.0);
+    // names are case-sensitive
+    assert_eq!(
+        p.computed_style().border_color.top,
+        css::Color::from(255, 0, 0, 255)
+    );
+    // `--a` and `--b` are in a cycle
+    assert_eq!(p.computed_style().width, px(7.0));
+    match p.children[0].children[0].node.node_type {
+        dom::NodeType::Text(ref text) => assert_eq!(text, "hi"),
+        _ => panic!("expected the text of ::before"),
+    }
+
+    assert_eq!(span.color, css::Color::from(0, 128, 0, 255));
+    // `em` is the font size of the element using the variable
+    assert_eq!(span.height, px(32.0));
+    // invalid once substituted, both inherit
+    assert_eq!(em.font_weight, 400);
+    assert_eq!(em.color, div.color);
+    assert_ne!(em.color, blue);
+}
+
+#[test]
+fn test_combinators() {
+    let dom = super::parser::html::parse(
+        "<div><nav><ul><li><a>1</a></li></ul><a>2</a></nav><h1></h1>text<p></p><p></p></div>"
+            .to_string(),
+    );
+    let stylesheets = [css::parse(
+        "nav a { font-size: 20px; } nav > a { font-weight: bold; } h1 + p { margin: 1px; } h1 ~ p { padding: 1px; } DIV P + p { width: 5px; }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let nav = &styled.children[0];
+    let nested = nav.children[0].children[0].children[0].computed_style();
+    let direct = nav.children[1].computed_style();
+    let first = styled.children[3].computed_style();
+    let second = styled.children[4].computed_style();
+
+    assert_eq!((nested.font_size, nested.font_weight), (20.0, 400));
+    assert_eq!((direct.font_size, direct.font_weight), (20.0, 700));
+    // the text between h1 and p doesn't count as a sibling
+    assert_eq!(first.margin.top, computed::Length::Px(1.0));
+    assert_eq!(second.margin.top, computed::Length::Px(0.0));
+    assert_eq!(second.padding.top, computed::Length::Px(1.0));
+    assert_eq!(first.width, computed::Length::Auto);
+    assert_eq!(second.width, computed::Length::Px(5.0));
+}
+
+#[test]
+fn test_attribute_selectors() {
+    let dom = super::parser::html::parse(
+        "<div><input disabled type=\"Text\" lang=\"en-US\" class=\"a b\" href=\"https://x.org/a.pdf\"></div>"
+            .to_string(),
+    );
+    let cases = [
+        ("[disabled]", true),
+        ("[checked]", false),
+        ("[type=text]", false),
+        ("[type=\"text\" i]", true),
+        ("[ class ~= 'b' ]", true),
+        ("[class~=\"a b\"]", false),
+        ("[lang|=en]", true),
+        ("[lang|=e]", false),
+        ("[href^=https]", true),
+        ("[href$=\".pdf\"]", true),
+        ("[href*=\"x.org\"]", true),
+        ("[href*=\"\"]", false),
+    ];
+    for &(selector, expected) in cases.iter() {
+        let stylesheets = [css::parse(
+            format!("input{} {{ width: 5px; }}", selector),
+            css::Origin::Author,
+        )];
+        let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+        let matched = styled.children[0].computed_style().width == computed::Length::Px(5.0);
+        assert_eq!(matched, expected, "{}", selector);
+    }
+
+    let stylesheet = css::parse("a[href][title] { }".to_string(), css::Origin::Author);
+    let rules = stylesheet.style_rules(&MediaEnvironment::default());
+    assert_eq!(rules[0].selectors[0].specificity(), (0, 2, 1));
+}
+
+#[test]
+fn test_pseudo_classes() {
+    let mut dom = super::parser::html::parse(
+        "<ul><li class=a>1</li><li>2</li><li class=a>3</li><li class=a>4</li><p></p><input type=checkbox checked></ul>"
+            .to_string(),
+    );
+    // the pointer is over the second item
+    if let dom::NodeType::Element(ref mut item) = dom.children[1].node_type {
+        item.state.set(dom::ElementState::HOVER, true);
+    }
+    let cases = [
+        (":root", vec![]),
+        ("li:first-child", vec![0]),
+        (":last-child", vec![5]),
+        ("li:nth-child(2n+1)", vec![0, 2]),
+        ("li:nth-child( -n + 2 )", vec![0, 1]),
+        ("li:nth-child(-2147483648)", vec![]),
+        ("li:nth-child(-2147483648n-2147483648)", vec![]),
+        (":nth-child(even of .a)", vec![2]),
+        ("li:nth-of-type(4)", vec![3]),
+        (":empty", vec![4, 5]),
+        ("li:not(.a, :first-child)", vec![1]),
+        (":is(p, input):where(:checked)", vec![5]),
+        ("li:hover", vec![1]),
+        // an unknown pseudo-class drops the whole rule
+        ("li:hover, li:visited", vec![]),
+        ("li:not(:bogus)", vec![]),
+    ];
+    for (selector, expected) in cases.iter() {
+        let stylesheets = [css::parse(
+            format!("{} {{ width: 5px; }}", selector),
+            css::Origin::Author,
+        )];
+        let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+        let matched: Vec<usize> = (0..styled.children.len())
+            .filter(|&i| styled.children[i].computed_style().width == computed::Length::Px(5.0))
+            .collect();
+        assert_eq!(&matched, expected, "{}", selector);
+    }
+
+    let specificity = |selector: &str| {
+        let stylesheet = css::parse(format!("{} {{}}", selector), css::Origin::Author);
+        stylesheet.style_rules(&MediaEnvironment::default())[0].selectors[0].specificity()
+    };
+    assert_eq!(specificity("li:hover"), (0, 1, 1));
+    assert_eq!(specificity(":is(#a, p) :where(#b)"), (1, 0, 0));
+    assert_eq!(specificity(":nth-child(2 of .a)"), (0, 2, 0));
+}
+
+#[test]
+fn test_generated_content() {
+    let dom = super::parser::html::parse(
+        "<div><ol><li title=A>a</li><li>b<ol><li>c</li></ol></li></ol><ol><li>d</li></ol></div>"
+            .to_string(),
+    );
+    let stylesheets = [css::parse(
+        "ol { counter-reset: item } li { counter-increment: item } \
+         li::before { content: counters(item, \".\") \") \" attr(title) } \
+         ol::after { content: '\\2192' \"End\" } \
+         ol ol:after { content: none }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+
+    fn generated(node: &StyledNode, texts: &mut Vec<String>) {
+        if let dom::NodeType::Element(ref data) = node.node.node_type {
+            if data.tag_name.starts_with("::") {
+                if let dom::NodeType::Text(ref text) = node.children[0].node.node_type {
+                    texts.push(format!("{}{}", data.tag_name, text));
+                }
+            }
+        }
+        for child in &node.children {
+            generated(child, texts);
+        }
+    }
+    let mut texts = Vec::new();
+    generated(&styled, &mut texts);
+    assert_eq!(
+        texts,
+        vec![
+            "::before1) A",
+            "::before2) ",
+            "::before2.1) ",
+            "::after\u{2192}End",
+            "::before1) ",
+            "::after\u{2192}End",
+        ]
+    );
+}
+
+// Run with `cargo test --release -- --ignored bench_rule_index --nocapture`
+#[test]
+#[ignore]
+fn bench_rule_index() {
+    use std::time::Instant;
+
+    // 5000 elements in lists of 10, with an id and two classes each
+    let mut html = String::from("<div>");
+    for list in 0..500 {
+        html.push_str("<ul>");
+        for item in 0..10 {
+            let n = list * 10 + item;
+            html.push_str(&format!(
+                "<li id=e{} class=\"c{} d{}\">{}</li>",
+                n,
+                n % 1000,
+                n % 7,
+                n
+            ));
+        }
+        html.push_str("</ul>");
+    }
+    html.push_str("</div>");
+    let dom = super::parser::html::parse(html);
+
+    // 2000 rules on ids, classes, tags and descendants
+    let mut css = String::new();
+    for n in 0..500 {
+        css.push_str(&format!("#e{} {{ width: 1px; }}\n", n * 10));
+        css.push_str(&format!(".c{} {{ height: 1px; }}\n", n));
+        css.push_str(&format!("ul li.c{} {{ color: red; }}\n", n + 500));
+        css.push_str(&format!("div > a[title=t{}] {{ margin: 1px; }}\n", n));
+    }
+    let stylesheet = css::parse(css, css::Origin::Author);
+    let media = MediaEnvironment::default();
+    let rules = stylesheet.style_rules(&media);
+    assert_eq!(rules.len(), 2000);
+
+    fn elements<'a, 'p>(elem: &dom::ElementRef<'a, 'p>, visit: &mut dyn FnMut(&dom::ElementRef)) {
+        visit(elem);
+        for (index, child) in elem.node.children.iter().enumerate() {
+            if let dom::NodeType::Element(_) = child.node_type {
+                elements(&dom::ElementRef::child(elem, index), visit);
+            }
+        }
+    }
+    let root = dom::ElementRef::root(&dom);
+
+    let index = RuleIndex::new(&stylesheet, &media);
+    let start = Instant::now();
+    let mut indexed = 0;
+    elements(&root, &mut |elem| {
+        indexed += matching_rules(elem, &index, None).len()
+    });
+    let indexed_time = start.elapsed();
+
+    let start = Instant::now();
+    let mut linear = 0;
+    elements(&root, &mut |elem| {
+        linear += rules
+            .iter()
+            .filter_map(|rule| match_rule(elem, rule, None))
+            .count()
+    });
+    let linear_time = start.elapsed();
+
+    println!(
+        "{} matches: indexed {:?}, every rule {:?}, {:.1}x faster",
+        indexed,
+        indexed_time,
+        linear_time,
+        linear_time.as_secs_f64() / indexed_time.as_secs_f64()
+    );
+    assert_eq!(indexed, linear);
+}
+
+#[test]
+fn test_parallel_style() {
+    let mut html = String::from("<div>");
+    for n in 0..50 {
+        html.push_str(&format!(
+            "<section id=s{0} class=\"c{1}\"><h2>Part {0}</h2><p>Text <em>here</em></p><ul><li>a</li><li title=t{0}>b</li></ul></section>",
+            n,
+            n % 3
+        ));
+    }
+    html.push_str("</div>");
+    let dom = super::parser::html::parse(html);
+    let stylesheets = [
+        user_agent_stylesheet(),
+        css::parse(
+            "div { counter-reset: part } section { counter-increment: part; font-size: 12px } \
+             .c1 { display: none } .c2 p { color: red } li + li { margin: 1em } \
+             h2::before { content: counter(part) \". \" } li[title]::after { content: attr(title) }"
+                .to_string(),
+            css::Origin::Author,
+        ),
+    ];
+
+    fn assert_same(a: &StyledNode, b: &StyledNode) {
+        assert_eq!(a.node.node_type.to_string(), b.node.node_type.to_string());
+        assert!(a.computed_style() == b.computed_style());
+        assert_eq!(a.children.len(), b.children.len());
+        for (a, b) in a.children.iter().zip(&b.children) {
+            assert_same(a, b);
+        }
+    }
+    let sequential = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let parallel = style_tree(
+        &dom,
+        &stylesheets,
+        StyleOptions {
+            parallel: true,
+            ..StyleOptions::default()
+        },
+    );
+    assert_same(&sequential, &parallel);
+}
+
+#[test]
+fn test_style_sharing() {
+    let dom = super::parser::html::parse(
+        "<div><ul><li class=a>1</li><li class=a>2</li><li class=b>3</li><li class=a title=x>4</li></ul>\
+         <ul><li class=a>5</li><li class=a id=six>6</li></ul></div>"
+            .to_string(),
+    );
+    let style = |css: &str| {
+        let stylesheets = [css::parse(css.to_string(), css::Origin::Author)];
+        let (styled, stats) = style_tree_with_stats(&dom, &stylesheets, StyleOptions::default());
+        let widths: Vec<computed::Length> = styled
+            .children
+            .iter()
+            .flat_map(|list| &list.children)
+            .map(|item| item.computed_style().width.clone())
+            .collect();
+        (widths, stats)
+    };
+    let px = computed::Length::Px;
+
+    // the second list shares the style of the first, as do the items of
+    // class `a` without an id, in both lists
+    let (widths, stats) = style("ul { height: 1px } .a { width: 1px }");
+    assert_eq!(
+        widths,
+        vec![
+            px(1.0),
+            px(1.0),
+            computed::Length::Auto,
+            px(1.0),
+            px(1.0),
+            px(1.0)
+        ]
+    );
+    assert_eq!(
+        stats,
+        StyleStats {
+            elements: 9,
+            shared: 4
+        }
+    );
+
+    // attributes of attribute selectors are compared
+    let (widths, stats) = style("[title] { width: 2px }");
+    assert_eq!(widths[3], px(2.0));
+    assert_eq!(stats.shared, 3);
+
+    // selectors on the position of an element turn sharing off
+    let (widths, stats) = style("li + li { width: 3px }");
+    assert_eq!(widths[0], computed::Length::Auto);
+    assert_eq!(widths[1], px(3.0));
+    assert_eq!(stats.shared, 0);
+}
+
+#[test]
+fn test_shorthands() {
+    let dom =
+        super::parser::html::parse("<p class=a style=\"padding: 1px 2px 3px\">x</p>".to_string());
+    let stylesheets = [css::parse(
+        ".a { margin-left: 5px; border-top-style: none } \
+         p { margin: 1px 2px; border: 3px solid red; font: italic bold 20px/2 serif }"
+            .to_string(),
+        css::Origin::Author,
+    )];
+    let styled = style_tree(&dom, &stylesheets, StyleOptions::default());
+    let style = styled.computed_style();
+    let px = computed::Length::Px;
+
+    // the longhand of the more specific rule wins over its part of the shorthand
+    assert_eq!(style.margin.top, px(1.0));
+    assert_eq!(style.margin.left, px(5.0));
+    assert_eq!(style.padding.bottom, px(3.0));
+    assert_eq!(style.padding.left, px(2.0));
+    assert_eq!(style.border_width.top, 0.0);
+    assert_eq!(style.border_width.left, 3.0);
+    assert_eq!(style.border_color.left, css::Color::from(255, 0, 0, 255));
+    assert_eq!(style.font_size, 20.0);
+    assert_eq!(style.font_weight, 700);
+    assert_eq!(style.font_style, computed::FontStyle::Italic);
+    assert_eq!(style.line_height_px(), 40.0);
 }
@@ -0,0 +1,145 @@
+/// Custom properties and `var()`
+///
+/// `--accent: #c00` sets a custom property, inherited like `color`, and
+/// `color: var(--accent, black)` uses it, or the fallback after the comma
+/// when it isn't set. The tokens of a custom property are kept as written
+/// and substituted when the style of an element is computed. A value that
+/// is invalid once substituted behaves like `unset`.
+///
+/// Custom properties that refer to each other in a cycle are all invalid,
+/// as if they were not set.
+use super::parser::css::{self, Value};
+use super::parser::css_tokenizer::Token;
+use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
+
+/// The custom properties of an element by name, `--` included
+pub type CustomProperties = HashMap<String, Vec<Token>>;
+
+/// Substitute the `var()` in the specified values of an element whose
+/// parent has the `inherited` custom properties.
+///
+/// Custom properties are given the tokens of their computed value, or
+/// `initial` when invalid. The other values are parsed, or `unset` when
+/// invalid.
+pub fn substitute(specified: &mut HashMap<String, Value>, inherited: &CustomProperties) {
+    let mut resolver = Resolver {
+        specified,
+        inherited,
+        resolved: HashMap::new(),
+        stack: Vec::new(),
+        cyclic: HashSet::new(),
+    };
+    let mut substituted = Vec::new();
+    for (name, value) in specified.iter() {
+        let value = match value {
+            Value::Custom(_) => match resolver.lookup(name) {
+                Some(tokens) => Value::Custom(tokens),
+                None => Value::Keyword("initial".to_string()),
+            },
+            Value::WithVariables { property, tokens } => resolver
+                .substitute(tokens)
+                .and_then(|tokens| css::parse_substituted(name, property, tokens))
+                .unwrap_or_else(|| Value::Keyword("unset".to_string())),
+            _ => continue,
+        };
+        substituted.push((name.clone(), value));
+    }
+    specified.extend(substituted);
+}
+
+struct Resolver<'a> {
+    specified: &'a HashMap<String, Value>,
+    inherited: &'a CustomProperties,
+    // computed values of the custom properties looked up so far, `None`
+    // when invalid
+    resolved: HashMap<String, Option<Vec<Token>>>,
+    // the custom properties being substituted, innermost last
+    stack: Vec<String>,
+    cyclic: HashSet<String>,
+}
+
+impl<'a> Resolver<'a> {
+    // Computed value of a custom property, `None` when it is not set or
+    // invalid
+    fn lookup(&mut self, name: &str) -> Option<Vec<Token>> {
+        if let Some(resolved) = self.resolved.get(name) {
+            return resolved.clone();
+        }
+        let specified = self.specified;
+        let tokens = match specified.get(name) {
+            Some(Value::Custom(tokens)) => tokens,
+            Some(Value::Keyword(keyword)) if keyword == "initial" => return None,
+            // `inherit`, `unset` or not set on the element
+            _ => return self.inherited.get(name).cloned(),
+        };
+        if let Some(position) = self.stack.iter().position(|other| other == name) {
+            // every property of the cycle is invalid
+            self.cyclic.extend(self.stack[position..].iter().cloned());
+            return None;
+        }
+
+        self.stack.push(name.to_string());
+        let value = self.substitute(tokens);
+        self.stack.pop();
+        let value = value.filter(|_| !self.cyclic.contains(name));
+        self.resolved.insert(name.to_string(), value.clone());
+        value
+    }
+
+    // The tokens with their `var()` replaced, `None` when one refers to an
+    // invalid custom property and has no fallback
+    fn substitute(&mut self, tokens: &[Token]) -> Option<Vec<Token>> {
+        let mut substituted = Vec::new();
+        let mut index = 0;
+        while index < tokens.len() {
+            match &tokens[index] {
+                Token::Function(name) if name.eq_ignore_ascii_case("var") => {
+                    let end = index + 1 + closing_paren(&tokens[index + 1..]);
+                    substituted.extend(self.var(&tokens[index + 1..end])?);
+                    index = end + 1;
+                }
+                token => {
+                    substituted.push(token.clone());
+                    index += 1;
+                }
+            }
+        }
+        Some(substituted)
+    }
+
+    // `var(--name)` or `var(--name, fallback)`, from its arguments
+    fn var(&mut self, arguments: &[Token]) -> Option<Vec<Token>> {
+        let mut arguments = arguments
+            .iter()
+            .skip_while(|token| **token == Token::Whitespace);
+        let name = match arguments.next() {
+            Some(Token::Ident(name)) if name.starts_with("--") => name,
+            _ => return None,
+        };
+        let rest: Vec<Token> = arguments.cloned().collect();
+        let fallback = match rest.iter().position(|token| *token != Token::Whitespace) {
+            None => None,
+            Some(comma) if rest[comma] == Token::Comma => Some(&rest[comma + 1..]),
+            Some(_) => return None,
+        };
+        match self.lookup(name) {
+            Some(value) => Some(value),
+            None => self.substitute(fallback?),
+        }
+    }
+}
+
+// Position of the `)` closing a function, over nested ones
+fn closing_paren(tokens: &[Token]) -> usize {
+    let mut depth = 0;
+    for (index, token) in tokens.iter().enumerate() {
+        match token {
+            Token::OpenParen | Token::Function(_) => depth += 1,
+            Token::CloseParen if depth == 0 => return index,
+            Token::CloseParen => depth -= 1,
+            _ => {}
+        }
+    }
+    tokens.len()
+}